@@ -0,0 +1,294 @@
+use rodio::Source;
+use rustfft::{num_complex::Complex, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::{f32::consts::PI, path::Path};
+
+use crate::audio::open_source;
+
+// Tempo and key only need the low end of the spectrum, so files are mixed to
+// mono and decimated to roughly this rate before analysis.
+const ANALYSIS_RATE: u32 = 11_025;
+const MAX_ANALYSIS_SECONDS: f32 = 120.0;
+// Anything shorter is treated as a one-shot with no meaningful tempo.
+const MIN_TEMPO_SECONDS: f32 = 4.0;
+
+const ONSET_FRAME: usize = 1024;
+const ONSET_HOP: usize = 256;
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 200.0;
+const PREFERRED_BPM: f32 = 120.0;
+
+const CHROMA_FRAME: usize = 4096;
+const CHROMA_HOP: usize = 2048;
+const MIN_PITCH_HZ: f32 = 55.0;
+const MAX_PITCH_HZ: f32 = 2000.0;
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+// Krumhansl-Kessler key profiles, starting from the tonic.
+const MAJOR_PROFILE: [f32; 12] = [6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88];
+const MINOR_PROFILE: [f32; 12] = [6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Analysis {
+    pub bpm: Option<f32>,
+    pub key: Option<String>,
+}
+
+pub fn analyze_file(path: &Path) -> Result<Analysis, String> {
+    let (samples, rate) = decode_mono(path)?;
+    println!("Analyzing tempo and key for {}", path.display());
+    Ok(Analysis {
+        bpm: detect_tempo(&samples, rate),
+        key: detect_key(&samples, rate),
+    })
+}
+
+fn decode_mono(path: &Path) -> Result<(Vec<f32>, f32), String> {
+    let (source, _) = open_source(path)?;
+    let channels = source.channels().max(1) as usize;
+    let sample_rate = source.sample_rate();
+    let decimation = (sample_rate / ANALYSIS_RATE).max(1) as usize;
+    let rate = sample_rate as f32 / decimation as f32;
+    let max_samples = (MAX_ANALYSIS_SECONDS * rate) as usize;
+
+    let mut samples = Vec::new();
+    let mut frame_sum = 0.0;
+    let mut block_sum = 0.0;
+    let mut channel = 0;
+    let mut frames_in_block = 0;
+    for sample in source {
+        frame_sum += sample as f32 / i16::MAX as f32;
+        channel += 1;
+        if channel < channels {
+            continue;
+        }
+
+        block_sum += frame_sum / channels as f32;
+        frame_sum = 0.0;
+        channel = 0;
+        frames_in_block += 1;
+        if frames_in_block == decimation {
+            samples.push(block_sum / decimation as f32);
+            block_sum = 0.0;
+            frames_in_block = 0;
+            if samples.len() >= max_samples {
+                break;
+            }
+        }
+    }
+    Ok((samples, rate))
+}
+
+// Calls `visit` with the magnitude spectrum of each Hann-windowed frame.
+fn for_each_spectrum(samples: &[f32], frame: usize, hop: usize, mut visit: impl FnMut(&[f32])) {
+    if samples.len() < frame {
+        return;
+    }
+
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(frame);
+    let window: Vec<f32> = (0..frame)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / frame as f32).cos())
+        .collect();
+    let mut buffer = vec![Complex::new(0.0, 0.0); frame];
+    let mut magnitudes = vec![0.0; frame / 2];
+
+    for start in (0..=samples.len() - frame).step_by(hop) {
+        for (i, value) in buffer.iter_mut().enumerate() {
+            *value = Complex::new(samples[start + i] * window[i], 0.0);
+        }
+        fft.process(&mut buffer);
+        for (magnitude, value) in magnitudes.iter_mut().zip(&buffer) {
+            *magnitude = value.norm();
+        }
+        visit(&magnitudes);
+    }
+}
+
+// Spectral flux onset envelope, autocorrelated over the lags of plausible tempos.
+fn detect_tempo(samples: &[f32], rate: f32) -> Option<f32> {
+    if (samples.len() as f32) < MIN_TEMPO_SECONDS * rate {
+        return None;
+    }
+
+    let mut envelope = Vec::new();
+    let mut previous: Option<Vec<f32>> = None;
+    for_each_spectrum(samples, ONSET_FRAME, ONSET_HOP, |magnitudes| {
+        let compressed: Vec<f32> = magnitudes.iter().map(|m| (1.0 + 100.0 * m).ln()).collect();
+        if let Some(previous) = &previous {
+            let flux = compressed
+                .iter()
+                .zip(previous)
+                .map(|(current, previous)| (current - previous).max(0.0))
+                .sum::<f32>();
+            envelope.push(flux);
+        }
+        previous = Some(compressed);
+    });
+
+    let frame_rate = rate / ONSET_HOP as f32;
+    let envelope = subtract_local_mean(&envelope, (frame_rate / 2.0) as usize);
+    let min_lag = (60.0 * frame_rate / MAX_BPM).floor() as usize;
+    let max_lag = (60.0 * frame_rate / MIN_BPM).ceil() as usize;
+    if envelope.len() <= max_lag + 1 || min_lag < 1 {
+        return None;
+    }
+
+    let correlation: Vec<f32> = (0..=max_lag + 1)
+        .map(|lag| {
+            envelope
+                .iter()
+                .zip(&envelope[lag..])
+                .map(|(a, b)| a * b)
+                .sum::<f32>()
+        })
+        .collect();
+    if correlation[0] <= f32::EPSILON {
+        return None;
+    }
+
+    // Favour tempos near 120 BPM so half and double time are less likely to win.
+    let weighted = |lag: usize| {
+        let bpm = 60.0 * frame_rate / lag as f32;
+        let octaves = (bpm / PREFERRED_BPM).log2();
+        correlation[lag] * (-0.5 * octaves * octaves).exp()
+    };
+    let best = (min_lag..=max_lag).max_by(|&a, &b| weighted(a).total_cmp(&weighted(b)))?;
+    if correlation[best] <= 0.0 {
+        return None;
+    }
+
+    // Parabolic interpolation around the peak for sub-frame lag precision.
+    let (left, centre, right) = (correlation[best - 1], correlation[best], correlation[best + 1]);
+    let denominator = left - 2.0 * centre + right;
+    let offset = if denominator.abs() > f32::EPSILON {
+        (0.5 * (left - right) / denominator).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    };
+
+    let bpm = 60.0 * frame_rate / (best as f32 + offset);
+    Some((bpm * 10.0).round() / 10.0)
+}
+
+fn subtract_local_mean(values: &[f32], radius: usize) -> Vec<f32> {
+    let mut prefix = vec![0.0; values.len() + 1];
+    for (i, value) in values.iter().enumerate() {
+        prefix[i + 1] = prefix[i] + value;
+    }
+
+    (0..values.len())
+        .map(|i| {
+            let start = i.saturating_sub(radius);
+            let end = (i + radius + 1).min(values.len());
+            let mean = (prefix[end] - prefix[start]) / (end - start) as f32;
+            (values[i] - mean).max(0.0)
+        })
+        .collect()
+}
+
+// Folds the spectrum into a 12-bin chromagram and picks the best matching key profile.
+fn detect_key(samples: &[f32], rate: f32) -> Option<String> {
+    let bin_hz = rate / CHROMA_FRAME as f32;
+    let mut chroma = [0.0f32; 12];
+    for_each_spectrum(samples, CHROMA_FRAME, CHROMA_HOP, |magnitudes| {
+        for (bin, magnitude) in magnitudes.iter().enumerate().skip(1) {
+            let frequency = bin as f32 * bin_hz;
+            if !(MIN_PITCH_HZ..=MAX_PITCH_HZ).contains(&frequency) {
+                continue;
+            }
+            let midi = 69.0 + 12.0 * (frequency / 440.0).log2();
+            let pitch_class = (midi.round() as i32).rem_euclid(12) as usize;
+            chroma[pitch_class] += magnitude * magnitude;
+        }
+    });
+
+    if chroma.iter().sum::<f32>() <= f32::EPSILON {
+        return None;
+    }
+
+    let mut best: Option<(f32, usize, bool)> = None;
+    for tonic in 0..12 {
+        for (profile, minor) in [(&MAJOR_PROFILE, false), (&MINOR_PROFILE, true)] {
+            let rotated: Vec<f32> = (0..12).map(|pc| profile[(pc + 12 - tonic) % 12]).collect();
+            let score = correlation(&chroma, &rotated);
+            if best.map_or(true, |(best_score, _, _)| score > best_score) {
+                best = Some((score, tonic, minor));
+            }
+        }
+    }
+
+    best.map(|(_, tonic, minor)| {
+        format!("{} {}", NOTE_NAMES[tonic], if minor { "minor" } else { "major" })
+    })
+}
+
+fn correlation(a: &[f32], b: &[f32]) -> f32 {
+    let mean_a = a.iter().sum::<f32>() / a.len() as f32;
+    let mean_b = b.iter().sum::<f32>() / b.len() as f32;
+    let mut covariance = 0.0;
+    let mut variance_a = 0.0;
+    let mut variance_b = 0.0;
+    for (x, y) in a.iter().zip(b) {
+        covariance += (x - mean_a) * (y - mean_b);
+        variance_a += (x - mean_a) * (x - mean_a);
+        variance_b += (y - mean_b) * (y - mean_b);
+    }
+    covariance / (variance_a * variance_b).sqrt().max(f32::EPSILON)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = ANALYSIS_RATE as f32;
+
+    // Short decaying 2 kHz bursts, one per beat.
+    fn click_train(bpm: f32, seconds: f32) -> Vec<f32> {
+        let period = 60.0 * RATE / bpm;
+        let click = (0.01 * RATE) as usize;
+        let mut samples = vec![0.0; (seconds * RATE) as usize];
+        let mut beat = 0.0;
+        while (beat as usize) < samples.len() {
+            let start = beat as usize;
+            for (i, sample) in samples[start..].iter_mut().take(click).enumerate() {
+                let t = i as f32 / RATE;
+                *sample = (2.0 * PI * 2000.0 * t).sin() * (-t * 400.0).exp();
+            }
+            beat += period;
+        }
+        samples
+    }
+
+    fn chord(frequencies: &[f32], seconds: f32) -> Vec<f32> {
+        (0..(seconds * RATE) as usize)
+            .map(|i| {
+                let t = i as f32 / RATE;
+                let sum: f32 = frequencies.iter().map(|frequency| (2.0 * PI * frequency * t).sin()).sum();
+                sum / frequencies.len() as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_the_tempo_of_a_click_train() {
+        for bpm in [90.0, 128.0, 174.0] {
+            let detected = detect_tempo(&click_train(bpm, 20.0), RATE).unwrap();
+            assert!((detected - bpm).abs() <= 1.0, "expected {} BPM, detected {}", bpm, detected);
+        }
+    }
+
+    #[test]
+    fn needs_a_few_seconds_for_tempo() {
+        assert_eq!(detect_tempo(&click_train(120.0, MIN_TEMPO_SECONDS - 1.0), RATE), None);
+        assert_eq!(detect_tempo(&vec![0.0; (10.0 * RATE) as usize], RATE), None);
+    }
+
+    #[test]
+    fn detects_the_key_of_a_sustained_triad() {
+        // A3, C4 and E4.
+        assert_eq!(detect_key(&chord(&[220.0, 261.63, 329.63], 10.0), RATE).as_deref(), Some("A minor"));
+        // C4, E4 and G4.
+        assert_eq!(detect_key(&chord(&[261.63, 329.63, 392.0], 10.0), RATE).as_deref(), Some("C major"));
+        assert_eq!(detect_key(&vec![0.0; (10.0 * RATE) as usize], RATE), None);
+    }
+}
@@ -0,0 +1,127 @@
+use serde::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+use crate::{
+    library::{analyze_song, measure_song_loudness, LibraryEntry, ScanOptions, Song},
+    library_index::LibraryChange,
+    library_service::LibraryService,
+};
+
+// Analyzed tracks merged into the index between "library-changed" events.
+const BATCH_SIZE: usize = 20;
+
+// Tempo, key and loudness analysis decode every file, so they run on its own thread after scans rather
+// than inside them, and only takes the index lock to read the work and merge results.
+#[derive(Default)]
+pub struct AnalysisJob {
+    pending: AtomicBool,
+    running: AtomicBool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AnalysisProgress {
+    pub done: usize,
+    pub total: usize,
+}
+
+// Requests made while a pass is running queue one more pass once it finishes.
+pub fn request_analysis(library: &impl LibraryService) {
+    let job = library.analysis_job();
+    job.pending.store(true, Ordering::SeqCst);
+    if job.running.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let library = library.clone();
+    thread::spawn(move || {
+        let job = library.analysis_job();
+        loop {
+            while job.pending.swap(false, Ordering::SeqCst) {
+                analyze_pending(&library);
+            }
+            job.running.store(false, Ordering::SeqCst);
+            // A request may have arrived after the last pass but while running was still set.
+            if !job.pending.load(Ordering::SeqCst) || job.running.swap(true, Ordering::SeqCst) {
+                break;
+            }
+        }
+    });
+}
+
+fn needs_analysis(entry: &LibraryEntry, options: &ScanOptions) -> bool {
+    (options.analyze && entry.song.analysis.is_none()) || (options.measure_loudness && entry.song.loudness.is_none())
+}
+
+fn analyze_pending(library: &impl LibraryService) {
+    let options = library.scan_options();
+    let pending: Vec<(PathBuf, LibraryEntry)> = {
+        let index = library.library_index().lock().unwrap();
+        index
+            .tracks
+            .iter()
+            .filter(|entry| needs_analysis(entry, &options))
+            .filter_map(|entry| {
+                let root = index.root(&entry.song.root)?;
+                Some((Path::new(&root.path).join(&entry.song.path), entry.clone()))
+            })
+            .collect()
+    };
+    if pending.is_empty() {
+        return;
+    }
+
+    let total = pending.len();
+    println!("Analyzing {} tracks in the background.", total);
+    let mut updated = Vec::new();
+    for (done, (path, entry)) in pending.into_iter().enumerate() {
+        let options = library.scan_options();
+        if !needs_analysis(&entry, &options) {
+            // Turned off while the job was running; later tracks may still need the kind that is on.
+            continue;
+        }
+        library.emit_event("library-analysis-progress", AnalysisProgress { done, total });
+
+        let mut song = entry.song.clone();
+        if options.analyze && song.analysis.is_none() {
+            analyze_song(&path, &mut song);
+        }
+        if options.measure_loudness && song.loudness.is_none() {
+            measure_song_loudness(&path, &mut song);
+        }
+        if song.analysis == entry.song.analysis && song.loudness == entry.song.loudness {
+            continue;
+        }
+
+        {
+            let mut index = library.library_index().lock().unwrap();
+            // Tracks removed or edited in the meantime are picked up by the next rescan.
+            let current = index.tracks.iter_mut().find(|current| {
+                current.key() == entry.key() && current.modified == entry.modified && current.size == entry.size
+            });
+            if let Some(current) = current {
+                current.song.analysis = current.song.analysis.take().or(song.analysis);
+                current.song.loudness = current.song.loudness.or(song.loudness);
+                current.song.resolve_tempo_and_key();
+                updated.push(current.song.clone());
+            }
+        }
+        if updated.len() >= BATCH_SIZE {
+            publish(library, std::mem::take(&mut updated));
+        }
+    }
+
+    publish(library, updated);
+    library.emit_event("library-analysis-progress", AnalysisProgress { done: total, total });
+}
+
+fn publish(library: &impl LibraryService, updated: Vec<Song>) {
+    if updated.is_empty() {
+        return;
+    }
+    library.save_track_stats(&library.library_index().lock().unwrap());
+    library.emit_event("library-changed", LibraryChange { updated, ..LibraryChange::default() });
+}
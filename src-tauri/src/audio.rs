@@ -0,0 +1,1150 @@
+use rodio::{
+    cpal::{
+        self,
+        traits::{DeviceTrait, HostTrait, StreamTrait},
+    },
+    dynamic_mixer::{self, DynamicMixer, DynamicMixerController},
+    Decoder, Sink, Source,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    f32::consts::FRAC_PI_2,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use symphonia::core::{
+    codecs::CodecParameters, formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions,
+    probe::{Hint, ProbeResult},
+};
+
+use crate::{
+    effects::{EffectsControl, EffectsSettings},
+    library::SongRef,
+    looping::{LoopControl, LoopMode},
+    loudness::{db_to_linear, Loudness},
+    meter::{Analyzer, MeterFrame, MeterTap},
+    metronome::{MetronomeClock, MetronomeSettings},
+    stretch::{StretchControl, TempoSettings},
+};
+
+const TICK: Duration = Duration::from_millis(20);
+const POSITION_INTERVAL: Duration = Duration::from_millis(250);
+const GAPLESS_LOOKAHEAD: Duration = Duration::from_secs(1);
+const DEVICE_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+const METER_INTERVAL: Duration = Duration::from_millis(50);
+// ReplayGain 2.0 reference level.
+const REFERENCE_LUFS: f64 = -18.0;
+
+pub struct Track {
+    // The library song this file belongs to, reported back in the playback events.
+    pub song: SongRef,
+    pub title: String,
+    pub path: PathBuf,
+    pub replay_gain: Option<ReplayGain>,
+    pub bpm: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayGain {
+    pub track: Loudness,
+    pub album: Option<Loudness>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalizationMode {
+    #[default]
+    Off,
+    Track,
+    Album,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct Normalization {
+    pub mode: NormalizationMode,
+    pub preamp_db: f32,
+    pub prevent_clipping: bool,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Normalization {
+            mode: NormalizationMode::Off,
+            preamp_db: 0.0,
+            prevent_clipping: true,
+        }
+    }
+}
+
+impl Normalization {
+    // Tracks that have not been measured yet play at unity gain.
+    fn gain(&self, replay_gain: Option<ReplayGain>) -> f32 {
+        let Some(replay_gain) = replay_gain else {
+            return 1.0;
+        };
+        let loudness = match self.mode {
+            NormalizationMode::Off => return 1.0,
+            NormalizationMode::Track => replay_gain.track,
+            NormalizationMode::Album => replay_gain.album.unwrap_or(replay_gain.track),
+        };
+
+        let mut gain = db_to_linear(REFERENCE_LUFS - loudness.integrated_lufs + self.preamp_db as f64);
+        if self.prevent_clipping {
+            gain = gain.min(1.0 / loudness.true_peak().max(f64::EPSILON));
+        }
+        gain as f32
+    }
+}
+
+pub enum EngineCommand {
+    // Replies once the track is playing, or with the reason it could not be opened.
+    Play(Track, Sender<Result<(), String>>),
+    Preload(Option<Track>),
+    Pause,
+    Resume,
+    Stop,
+    Seek(Duration),
+    SetVolume(f32),
+    SetCrossfade(Duration),
+    SetNormalization(Normalization),
+    SetMetering(bool),
+    SetEffects(EffectsSettings),
+    SetTempo(TempoSettings),
+    // Start and end in seconds; None clears the region.
+    SetLoopRegion(Option<(f64, f64)>),
+    SetWholeFileLoop(bool),
+    SetMetronome(MetronomeSettings),
+    SetOutputDevice(Option<String>),
+}
+
+pub enum EngineEvent {
+    TrackStarted(PlaybackStatus),
+    TrackPaused(PlaybackStatus),
+    TrackResumed(PlaybackStatus),
+    TrackEnded(PlaybackStatus),
+    Transitioned {
+        ended: PlaybackStatus,
+        started: PlaybackStatus,
+    },
+    Stopped(PlaybackStatus),
+    VolumeChanged(PlaybackStatus),
+    Position(PlaybackPosition),
+    LoopChanged(PlaybackStatus),
+    Meter(MeterFrame),
+    OutputDeviceChanged(OutputDeviceStatus),
+    Error(PlaybackError),
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayerState {
+    Stopped,
+    Playing,
+    Paused,
+}
+
+#[derive(Serialize, Clone)]
+pub struct PlaybackStatus {
+    pub song: Option<SongRef>,
+    pub title: Option<String>,
+    pub state: PlayerState,
+    pub position: f64,
+    pub duration: Option<f64>,
+    pub volume: f32,
+    pub loop_mode: LoopMode,
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct PlaybackPosition {
+    pub title: Option<String>,
+    pub position: f64,
+    pub duration: Option<f64>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct PlaybackError {
+    pub message: String,
+    pub status: PlaybackStatus,
+}
+
+#[derive(Serialize, Clone)]
+pub struct OutputDevice {
+    pub name: String,
+    pub is_default: bool,
+}
+
+#[derive(Serialize, Clone)]
+pub struct OutputDeviceStatus {
+    pub requested: Option<String>,
+    pub active: Option<String>,
+    pub fallback: bool,
+}
+
+pub struct AudioEngine {
+    commands: Sender<EngineCommand>,
+    status: Arc<Mutex<PlaybackStatus>>,
+    active_device: Arc<Mutex<Option<String>>>,
+}
+
+impl AudioEngine {
+    pub fn spawn() -> (AudioEngine, Receiver<EngineEvent>) {
+        let (command_tx, command_rx) = mpsc::channel();
+        let (event_tx, event_rx) = mpsc::channel();
+        let status = Arc::new(Mutex::new(PlaybackStatus {
+            song: None,
+            title: None,
+            state: PlayerState::Stopped,
+            position: 0.0,
+            duration: None,
+            volume: 1.0,
+            loop_mode: LoopMode::Off,
+        }));
+
+        let active_device = Arc::new(Mutex::new(None));
+
+        let engine_status = status.clone();
+        let engine_device = active_device.clone();
+        thread::Builder::new()
+            .name("audio-engine".into())
+            .spawn(move || run(command_rx, event_tx, engine_status, engine_device))
+            .expect("Failed to spawn audio engine thread");
+
+        (
+            AudioEngine {
+                commands: command_tx,
+                status,
+                active_device,
+            },
+            event_rx,
+        )
+    }
+
+    pub fn status(&self) -> PlaybackStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn position(&self) -> PlaybackPosition {
+        let status = self.status.lock().unwrap();
+        PlaybackPosition {
+            title: status.title.clone(),
+            position: status.position,
+            duration: status.duration,
+        }
+    }
+
+    pub fn send(&self, command: EngineCommand) {
+        if self.commands.send(command).is_err() {
+            eprintln!("Audio engine is not running.");
+        }
+    }
+
+    pub fn play(&self, track: Track) -> Result<(), String> {
+        let (reply, result) = mpsc::channel();
+        self.send(EngineCommand::Play(track, reply));
+        result.recv().map_err(|_| "Audio engine is not running.".to_string())?
+    }
+
+    // ALSA leaves out devices it cannot open, which includes the one we are
+    // playing on, so that one is added back from the engine's own record.
+    pub fn output_devices(&self) -> Result<Vec<OutputDevice>, String> {
+        let mut devices = output_devices()?;
+        if let Some(active) = self.active_device.lock().unwrap().clone() {
+            if !devices.iter().any(|device| device.name == active) {
+                devices.push(OutputDevice {
+                    is_default: default_output_device_name().as_ref() == Some(&active),
+                    name: active,
+                });
+            }
+        }
+        Ok(devices)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum FadeDirection {
+    In,
+    Out,
+}
+
+// Fades follow the deck's own playback position, so pausing mid-crossfade also pauses the fade.
+#[derive(Clone, Copy)]
+struct Fade {
+    direction: FadeDirection,
+    from: Duration,
+    length: Duration,
+}
+
+impl Fade {
+    fn gain(&self, position: Duration) -> (f32, bool) {
+        let elapsed = position.saturating_sub(self.from).as_secs_f32();
+        let progress = (elapsed / self.length.as_secs_f32().max(f32::EPSILON)).min(1.0);
+        let angle = progress * FRAC_PI_2;
+        let gain = match self.direction {
+            FadeDirection::In => angle.sin(),
+            FadeDirection::Out => angle.cos(),
+        };
+        (gain, progress >= 1.0)
+    }
+}
+
+struct QueuedTrack {
+    song: SongRef,
+    title: String,
+    path: PathBuf,
+    duration: Option<Duration>,
+    replay_gain: Option<ReplayGain>,
+    bpm: Option<f32>,
+    stretch: StretchControl,
+    looping: LoopControl,
+}
+
+// One sink per track; decks overlap on the stream's mixer while crossfading.
+struct Deck {
+    sink: Sink,
+    song: SongRef,
+    title: String,
+    path: PathBuf,
+    duration: Option<Duration>,
+    replay_gain: Option<ReplayGain>,
+    bpm: Option<f32>,
+    stretch: StretchControl,
+    looping: LoopControl,
+    fade: Option<Fade>,
+    queued: Option<QueuedTrack>,
+    tap: MeterTap,
+    // Last gain set on the sink, so meters read what is actually heard.
+    output_gain: f32,
+}
+
+// The output stream is not `Send`, so it lives on the engine thread for the whole session.
+struct Engine {
+    output: Option<Output>,
+    requested_device: Option<String>,
+    active_device: Arc<Mutex<Option<String>>>,
+    last_device_check: Instant,
+    current: Option<Deck>,
+    fading: Vec<Deck>,
+    next: Option<Track>,
+    crossfade: Duration,
+    volume: f32,
+    normalization: Normalization,
+    effects: EffectsControl,
+    tempo: TempoSettings,
+    // Applies to every track until turned off; regions belong to the current track only.
+    loop_whole_file: bool,
+    metronome_settings: MetronomeSettings,
+    // The click plays on its own sink on the same stream, so the mixer keeps it in step with the decks.
+    metronome: Option<(Sink, MetronomeClock)>,
+    metering: bool,
+    analyzer: Analyzer,
+    last_meter_event: Instant,
+    events: Sender<EngineEvent>,
+    status: Arc<Mutex<PlaybackStatus>>,
+    last_position_event: Instant,
+}
+
+fn run(
+    commands: Receiver<EngineCommand>,
+    events: Sender<EngineEvent>,
+    status: Arc<Mutex<PlaybackStatus>>,
+    active_device: Arc<Mutex<Option<String>>>,
+) {
+    let mut engine = Engine {
+        output: None,
+        requested_device: None,
+        active_device,
+        last_device_check: Instant::now(),
+        current: None,
+        fading: Vec::new(),
+        next: None,
+        crossfade: Duration::ZERO,
+        volume: 1.0,
+        normalization: Normalization::default(),
+        effects: EffectsControl::default(),
+        tempo: TempoSettings::default(),
+        loop_whole_file: false,
+        metronome_settings: MetronomeSettings::default(),
+        metronome: None,
+        metering: false,
+        analyzer: Analyzer::new(),
+        last_meter_event: Instant::now(),
+        events,
+        status,
+        last_position_event: Instant::now(),
+    };
+
+    loop {
+        match commands.recv_timeout(TICK) {
+            Ok(command) => engine.handle(command),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        engine.tick();
+    }
+
+    println!("Audio engine stopped.");
+}
+
+impl Engine {
+    fn handle(&mut self, command: EngineCommand) {
+        match command {
+            EngineCommand::Play(track, reply) => self.play(track, reply),
+            EngineCommand::Preload(track) => self.next = track,
+            EngineCommand::Pause => {
+                let Some(deck) = self.current.as_ref().filter(|deck| !deck.sink.is_paused()) else {
+                    return;
+                };
+                deck.sink.pause();
+                self.fading.iter().for_each(|deck| deck.sink.pause());
+                let status = self.publish_status();
+                self.send(EngineEvent::TrackPaused(status));
+            }
+            EngineCommand::Resume => {
+                let Some(deck) = self.current.as_ref().filter(|deck| deck.sink.is_paused()) else {
+                    return;
+                };
+                deck.sink.play();
+                self.fading.iter().for_each(|deck| deck.sink.play());
+                let status = self.publish_status();
+                self.send(EngineEvent::TrackResumed(status));
+            }
+            EngineCommand::Stop => {
+                // A deck can still be fading out after the current track has ended.
+                let playing = self.current.is_some();
+                self.stop();
+                if playing {
+                    self.send(EngineEvent::Stopped(self.snapshot()));
+                }
+            }
+            EngineCommand::Seek(position) => {
+                let Some(deck) = &self.current else {
+                    return;
+                };
+                if let Err(e) = deck.sink.try_seek(position) {
+                    self.fail(format!("Error seeking: {}", e));
+                }
+                self.publish_position();
+            }
+            EngineCommand::SetVolume(volume) => {
+                self.volume = volume;
+                self.apply_volume();
+                let status = self.publish_status();
+                self.send(EngineEvent::VolumeChanged(status));
+            }
+            EngineCommand::SetCrossfade(crossfade) => {
+                self.crossfade = crossfade;
+                println!("Crossfade set to: {:.1}s", crossfade.as_secs_f32());
+            }
+            EngineCommand::SetNormalization(normalization) => {
+                self.normalization = normalization;
+                self.apply_volume();
+            }
+            // Running sources pick the new settings up within a few milliseconds.
+            EngineCommand::SetEffects(settings) => self.effects.set(settings),
+            EngineCommand::SetTempo(tempo) => {
+                self.tempo = tempo;
+                self.apply_tempo();
+                self.publish_position();
+            }
+            EngineCommand::SetLoopRegion(region) => {
+                let Some(deck) = &self.current else {
+                    return;
+                };
+                deck.looping.set(match region {
+                    Some((start, end)) => LoopMode::Region { start, end },
+                    None => self.whole_file_mode(),
+                });
+                let status = self.publish_status();
+                self.send(EngineEvent::LoopChanged(status));
+            }
+            EngineCommand::SetWholeFileLoop(enabled) => {
+                self.loop_whole_file = enabled;
+                let mode = self.whole_file_mode();
+                if let Some(deck) = &self.current {
+                    if !matches!(deck.looping.mode(), LoopMode::Region { .. }) {
+                        deck.looping.set(mode);
+                    }
+                    if let Some(queued) = &deck.queued {
+                        queued.looping.set(mode);
+                    }
+                }
+                let status = self.publish_status();
+                self.send(EngineEvent::LoopChanged(status));
+            }
+            EngineCommand::SetMetronome(settings) => {
+                self.metronome_settings = settings;
+                match (&self.metronome, settings.enabled) {
+                    (Some((_, clock)), true) => clock.set(&settings),
+                    (None, true) => self.start_metronome(),
+                    (Some(_), false) => self.stop_metronome(),
+                    (None, false) => {}
+                }
+                self.apply_volume();
+            }
+            EngineCommand::SetMetering(enabled) => {
+                self.metering = enabled;
+                self.analyzer.reset();
+            }
+            EngineCommand::SetOutputDevice(name) => {
+                self.requested_device = name;
+                if self.output.is_some() {
+                    self.switch_output();
+                }
+            }
+        }
+    }
+
+    // The track is opened before the current one stops, so one that cannot be played
+    // leaves playback as it was.
+    fn play(&mut self, track: Track, reply: Sender<Result<(), String>>) {
+        let opened = open_source(&track.path).and_then(|opened| Ok((opened, self.new_sink()?)));
+        let ((source, duration), sink) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                eprintln!("{}", e);
+                let _ = reply.send(Err(e));
+                return;
+            }
+        };
+
+        self.stop();
+        self.analyzer.reset();
+
+        let delay = match &self.metronome {
+            Some((_, clock)) if self.metronome_settings.quantize_start => clock.until_next_bar(),
+            _ => Duration::ZERO,
+        };
+        let deck = self.build_deck(track, source, duration, sink, None, delay);
+        println!("Now playing: {}", deck.title);
+        self.current = Some(deck);
+        let status = self.publish_status();
+        self.send(EngineEvent::TrackStarted(status));
+        let _ = reply.send(Ok(()));
+    }
+
+    fn start_deck(&mut self, track: Track, fade: Option<Fade>) -> Result<Deck, String> {
+        let (source, duration) = open_source(&track.path)?;
+        let sink = self.new_sink()?;
+        Ok(self.build_deck(track, source, duration, sink, fade, Duration::ZERO))
+    }
+
+    // Leading silence is counted in mixer samples, which keeps a quantized start sample-accurate.
+    fn build_deck(
+        &self,
+        track: Track,
+        source: Decoder<BufReader<File>>,
+        duration: Option<Duration>,
+        sink: Sink,
+        fade: Option<Fade>,
+        delay: Duration,
+    ) -> Deck {
+        let gain = fade.map_or(1.0, |fade| fade.gain(Duration::ZERO).0);
+        let output_gain = self.volume * gain * self.normalization.gain(track.replay_gain);
+        let tap = MeterTap::default();
+        let stretch = StretchControl::new(self.tempo.speed_for(track.bpm), self.tempo.pitch_semitones);
+        let looping = LoopControl::new(self.whole_file_mode());
+        sink.set_volume(output_gain);
+        let source = tap.wrap(self.effects.wrap(stretch.wrap(looping.wrap(source))));
+        if delay.is_zero() {
+            sink.append(source);
+        } else {
+            sink.append(source.delay(delay));
+        }
+
+        Deck {
+            sink,
+            song: track.song,
+            title: track.title,
+            path: track.path,
+            duration,
+            replay_gain: track.replay_gain,
+            bpm: track.bpm,
+            stretch,
+            looping,
+            fade,
+            queued: None,
+            tap,
+            output_gain,
+        }
+    }
+
+    fn start_metronome(&mut self) {
+        let clock = MetronomeClock::new(&self.metronome_settings);
+        let sink = match self.new_sink() {
+            Ok(sink) => sink,
+            Err(e) => {
+                self.fail(e);
+                return;
+            }
+        };
+        sink.set_volume(self.volume * self.metronome_settings.volume);
+        sink.append(clock.click());
+        self.metronome = Some((sink, clock));
+        println!("Metronome started at {} BPM", self.metronome_settings.bpm);
+    }
+
+    fn stop_metronome(&mut self) {
+        if let Some((sink, _)) = self.metronome.take() {
+            sink.stop();
+            println!("Metronome stopped");
+        }
+    }
+
+    fn whole_file_mode(&self) -> LoopMode {
+        if self.loop_whole_file {
+            LoopMode::WholeFile
+        } else {
+            LoopMode::Off
+        }
+    }
+
+    fn apply_tempo(&self) {
+        let pitch = self.tempo.pitch_semitones;
+        for deck in self.fading.iter().chain(&self.current) {
+            deck.stretch.set(self.tempo.speed_for(deck.bpm), pitch);
+            if let Some(queued) = &deck.queued {
+                queued.stretch.set(self.tempo.speed_for(queued.bpm), pitch);
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        for deck in self.fading.drain(..).chain(self.current.take()) {
+            deck.sink.stop();
+        }
+        self.publish_status();
+    }
+
+    fn tick(&mut self) {
+        self.check_output_device();
+        self.apply_volume();
+
+        let Some(deck) = &self.current else {
+            return;
+        };
+
+        if deck.sink.empty() {
+            let status = self.deck_status(deck, PlayerState::Stopped);
+            self.current = None;
+            self.publish_status();
+            self.send(EngineEvent::TrackEnded(status));
+            return;
+        }
+
+        if deck.queued.is_some() && deck.sink.len() == 1 {
+            self.finish_gapless_transition();
+        }
+        self.start_transition_if_due();
+
+        let playing = self
+            .current
+            .as_ref()
+            .is_some_and(|deck| !deck.sink.is_paused());
+        if playing && self.last_position_event.elapsed() >= POSITION_INTERVAL {
+            self.publish_position();
+        }
+        if playing && self.metering && self.last_meter_event.elapsed() >= METER_INTERVAL {
+            self.publish_meter();
+        }
+    }
+
+    // Normalization gain is applied per deck on top of the user volume and any fade.
+    fn apply_volume(&mut self) {
+        let volume = self.volume;
+        let normalization = self.normalization;
+        if let Some((sink, _)) = &self.metronome {
+            sink.set_volume(volume * self.metronome_settings.volume);
+        }
+        self.fading.retain(|deck| {
+            let Some(fade) = deck.fade else {
+                return false;
+            };
+            let (gain, done) = fade.gain(deck.sink.get_pos());
+            if done || deck.sink.empty() {
+                deck.sink.stop();
+                return false;
+            }
+            deck.sink.set_volume(volume * gain * normalization.gain(deck.replay_gain));
+            true
+        });
+
+        if let Some(deck) = &mut self.current {
+            let gain = match deck.fade {
+                Some(fade) => {
+                    let (gain, done) = fade.gain(deck.sink.get_pos());
+                    if done {
+                        deck.fade = None;
+                    }
+                    gain
+                }
+                None => 1.0,
+            };
+            deck.output_gain = volume * gain * normalization.gain(deck.replay_gain);
+            deck.sink.set_volume(deck.output_gain);
+        }
+    }
+
+    fn start_transition_if_due(&mut self) {
+        let Some(deck) = &self.current else {
+            return;
+        };
+        if self.next.is_none()
+            || deck.queued.is_some()
+            || deck.fade.is_some()
+            || deck.sink.is_paused()
+            || deck.looping.mode() != LoopMode::Off
+        {
+            return;
+        }
+        let Some(duration) = deck.duration else {
+            return;
+        };
+
+        // Transitions are timed in real time, so the remaining source time is scaled by the speed.
+        let remaining = duration
+            .saturating_sub(deck.looping.position())
+            .div_f32(deck.stretch.speed().max(f32::EPSILON));
+        if self.crossfade.is_zero() {
+            if remaining <= GAPLESS_LOOKAHEAD {
+                self.queue_gapless();
+            }
+        } else if remaining <= self.crossfade {
+            self.start_crossfade(remaining);
+        }
+    }
+
+    // Appending to the same sink lets rodio play the next source without a gap.
+    fn queue_gapless(&mut self) {
+        let Some(track) = self.next.take() else {
+            return;
+        };
+
+        match open_source(&track.path) {
+            Ok((source, duration)) => {
+                if let Some(deck) = &mut self.current {
+                    let stretch = StretchControl::new(self.tempo.speed_for(track.bpm), self.tempo.pitch_semitones);
+                    let looping = LoopControl::new(LoopMode::Off);
+                    deck.sink.append(deck.tap.wrap(self.effects.wrap(stretch.wrap(looping.wrap(source)))));
+                    deck.queued = Some(QueuedTrack {
+                        song: track.song,
+                        title: track.title,
+                        path: track.path,
+                        duration,
+                        replay_gain: track.replay_gain,
+                        bpm: track.bpm,
+                        stretch,
+                        looping,
+                    });
+                }
+            }
+            Err(e) => self.fail(e),
+        }
+    }
+
+    fn finish_gapless_transition(&mut self) {
+        let Some(deck) = &mut self.current else {
+            return;
+        };
+        let Some(queued) = deck.queued.take() else {
+            return;
+        };
+
+        let duration = deck.duration.map(|duration| duration.as_secs_f64());
+        let ended = PlaybackStatus {
+            song: Some(std::mem::replace(&mut deck.song, queued.song)),
+            title: Some(std::mem::replace(&mut deck.title, queued.title)),
+            state: PlayerState::Stopped,
+            position: duration.unwrap_or_default(),
+            duration,
+            volume: self.volume,
+            loop_mode: LoopMode::Off,
+        };
+        deck.path = queued.path;
+        deck.duration = queued.duration;
+        deck.replay_gain = queued.replay_gain;
+        deck.bpm = queued.bpm;
+        deck.stretch = queued.stretch;
+        deck.looping = queued.looping;
+
+        println!("Now playing: {}", deck.title);
+        let started = self.publish_status();
+        self.send(EngineEvent::Transitioned { ended, started });
+    }
+
+    fn start_crossfade(&mut self, length: Duration) {
+        let Some(track) = self.next.take() else {
+            return;
+        };
+
+        let fade_in = Fade {
+            direction: FadeDirection::In,
+            from: Duration::ZERO,
+            length,
+        };
+        let deck = match self.start_deck(track, Some(fade_in)) {
+            Ok(deck) => deck,
+            Err(e) => {
+                self.fail(e);
+                return;
+            }
+        };
+
+        println!("Crossfading into: {}", deck.title);
+        if let Some(mut outgoing) = self.current.replace(deck) {
+            let ended = self.deck_status(&outgoing, PlayerState::Stopped);
+            outgoing.fade = Some(Fade {
+                direction: FadeDirection::Out,
+                from: outgoing.sink.get_pos(),
+                length,
+            });
+            self.fading.push(outgoing);
+
+            let started = self.publish_status();
+            self.send(EngineEvent::Transitioned { ended, started });
+        }
+    }
+
+    fn deck_status(&self, deck: &Deck, state: PlayerState) -> PlaybackStatus {
+        PlaybackStatus {
+            song: Some(deck.song.clone()),
+            title: Some(deck.title.clone()),
+            state,
+            position: deck.looping.position().as_secs_f64(),
+            duration: deck.duration.map(|duration| duration.as_secs_f64()),
+            volume: self.volume,
+            loop_mode: deck.looping.mode(),
+        }
+    }
+
+    fn snapshot(&self) -> PlaybackStatus {
+        match &self.current {
+            Some(deck) if deck.sink.is_paused() => self.deck_status(deck, PlayerState::Paused),
+            Some(deck) => self.deck_status(deck, PlayerState::Playing),
+            None => PlaybackStatus {
+                song: None,
+                title: None,
+                state: PlayerState::Stopped,
+                position: 0.0,
+                duration: None,
+                volume: self.volume,
+                loop_mode: LoopMode::Off,
+            },
+        }
+    }
+
+    fn publish_status(&mut self) -> PlaybackStatus {
+        let status = self.snapshot();
+        *self.status.lock().unwrap() = status.clone();
+        status
+    }
+
+    fn publish_position(&mut self) {
+        let status = self.publish_status();
+        self.send(EngineEvent::Position(PlaybackPosition {
+            title: status.title,
+            position: status.position,
+            duration: status.duration,
+        }));
+        self.last_position_event = Instant::now();
+    }
+
+    // Only the current deck is metered, so a crossfade hands the meter over at its start.
+    fn publish_meter(&mut self) {
+        self.last_meter_event = Instant::now();
+        let Some(deck) = &self.current else {
+            return;
+        };
+        if let Some(frame) = self
+            .analyzer
+            .measure(&deck.tap, deck.output_gain, Some(deck.title.clone()))
+        {
+            self.send(EngineEvent::Meter(frame));
+        }
+    }
+
+    fn fail(&mut self, message: String) {
+        eprintln!("{}", message);
+        let status = self.publish_status();
+        self.send(EngineEvent::Error(PlaybackError { message, status }));
+    }
+
+    fn send(&self, event: EngineEvent) {
+        let _ = self.events.send(event);
+    }
+
+    fn new_sink(&mut self) -> Result<Sink, String> {
+        if self.output.is_none() {
+            self.open_output()?;
+        }
+        Ok(self.output.as_ref().map(Output::sink).unwrap())
+    }
+
+    fn open_output(&mut self) -> Result<(), String> {
+        let requested = self.requested_device.clone();
+        let device = requested.as_deref().and_then(find_output_device);
+
+        let (output, active) = match device.map(|device| Output::open(&device)) {
+            Some(Ok(output)) => (output, requested.clone()),
+            opened => {
+                match (&requested, opened) {
+                    (Some(name), Some(Err(e))) => {
+                        eprintln!("Failed to open output device '{}': {}", name, e)
+                    }
+                    (Some(name), _) => println!("Output device '{}' not found, using default.", name),
+                    _ => {}
+                }
+                let device = cpal::default_host()
+                    .default_output_device()
+                    .ok_or("No output device available.")?;
+                (Output::open(&device)?, device.name().ok())
+            }
+        };
+
+        println!("Output device: {}", active.as_deref().unwrap_or("default"));
+        self.output = Some(output);
+        *self.active_device.lock().unwrap() = active.clone();
+        self.send(EngineEvent::OutputDeviceChanged(OutputDeviceStatus {
+            fallback: requested.is_some() && active != requested,
+            requested,
+            active,
+        }));
+        Ok(())
+    }
+
+    // Reopens the stream and picks the current track back up where it was.
+    fn switch_output(&mut self) {
+        let resume = self.current.as_mut().map(|deck| {
+            let queued = deck.queued.take();
+            let track = Track {
+                song: deck.song.clone(),
+                title: deck.title.clone(),
+                path: deck.path.clone(),
+                replay_gain: deck.replay_gain,
+                bpm: deck.bpm,
+            };
+            let state = (deck.looping.position(), deck.looping.mode(), deck.sink.is_paused());
+            (track, state, queued)
+        });
+
+        for deck in self.fading.drain(..).chain(self.current.take()) {
+            deck.sink.stop();
+        }
+        self.stop_metronome();
+        self.output = None;
+
+        if let Err(e) = self.open_output() {
+            self.fail(e);
+            return;
+        }
+        if self.metronome_settings.enabled {
+            self.start_metronome();
+        }
+
+        if let Some((track, (position, loop_mode, paused), queued)) = resume {
+            if let Some(queued) = queued {
+                self.next = Some(Track {
+                    song: queued.song,
+                    title: queued.title,
+                    path: queued.path,
+                    replay_gain: queued.replay_gain,
+                    bpm: queued.bpm,
+                });
+            }
+
+            match self.start_deck(track, None) {
+                Ok(deck) => {
+                    if let Err(e) = deck.sink.try_seek(position) {
+                        eprintln!("Error restoring position after device change: {}", e);
+                    }
+                    if paused {
+                        deck.sink.pause();
+                    }
+                    deck.looping.set(loop_mode);
+                    self.current = Some(deck);
+                }
+                Err(e) => self.fail(e),
+            }
+        }
+        self.publish_status();
+    }
+
+    // Loss of the device we play on is read from stream errors, never from
+    // the device list, which on ALSA leaves out any device already open. The
+    // list is only consulted while on a fallback, for the requested device.
+    fn check_output_device(&mut self) {
+        let Some(output) = &self.output else {
+            return;
+        };
+        if self.last_device_check.elapsed() < DEVICE_CHECK_INTERVAL {
+            return;
+        }
+        self.last_device_check = Instant::now();
+
+        let active = self.active_device.lock().unwrap().clone();
+        if output.failed() {
+            println!("Output device '{}' disappeared.", active.as_deref().unwrap_or("default"));
+            self.switch_output();
+            return;
+        }
+
+        let Some(requested) = &self.requested_device else {
+            return;
+        };
+        if active.as_ref() != Some(requested) && find_output_device(requested).is_some() {
+            println!("Output device '{}' is back.", requested);
+            self.switch_output();
+        }
+    }
+}
+
+// A cpal stream feeding rodio's mixer, like rodio's OutputStream, but with
+// an error callback the engine can see; rodio's only prints.
+struct Output {
+    _stream: cpal::Stream,
+    mixer: Arc<DynamicMixerController<f32>>,
+    failed: Arc<AtomicBool>,
+}
+
+impl Output {
+    fn open(device: &cpal::Device) -> Result<Output, String> {
+        let config = device
+            .default_output_config()
+            .map_err(|e| format!("Error reading output device config: {}", e))?;
+        let (mixer, source) = dynamic_mixer::mixer::<f32>(config.channels(), config.sample_rate().0);
+        let failed = Arc::new(AtomicBool::new(false));
+
+        let stream_config = config.config();
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => build_stream::<f32>(device, &stream_config, source, failed.clone()),
+            cpal::SampleFormat::F64 => build_stream::<f64>(device, &stream_config, source, failed.clone()),
+            cpal::SampleFormat::I16 => build_stream::<i16>(device, &stream_config, source, failed.clone()),
+            cpal::SampleFormat::I32 => build_stream::<i32>(device, &stream_config, source, failed.clone()),
+            cpal::SampleFormat::U16 => build_stream::<u16>(device, &stream_config, source, failed.clone()),
+            format => Err(format!("Unsupported output sample format: {}", format)),
+        }?;
+        stream.play().map_err(|e| format!("Error starting output stream: {}", e))?;
+
+        Ok(Output {
+            _stream: stream,
+            mixer,
+            failed,
+        })
+    }
+
+    fn sink(&self) -> Sink {
+        let (sink, queue) = Sink::new_idle();
+        self.mixer.add(queue);
+        sink
+    }
+
+    fn failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut source: DynamicMixer<f32>,
+    failed: Arc<AtomicBool>,
+) -> Result<cpal::Stream, String>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+{
+    device
+        .build_output_stream::<T, _, _>(
+            config,
+            move |data: &mut [T], _| {
+                for sample in data.iter_mut() {
+                    *sample = <T as cpal::Sample>::from_sample(source.next().unwrap_or(0.0));
+                }
+            },
+            move |e| {
+                // ALSA repeats the error on every write once the device is gone.
+                if !failed.swap(true, Ordering::Relaxed) {
+                    eprintln!("Output stream error: {}", e);
+                }
+            },
+            None,
+        )
+        .map_err(|e| format!("Error creating output stream: {}", e))
+}
+
+fn output_devices() -> Result<Vec<OutputDevice>, String> {
+    let default_name = default_output_device_name();
+    let devices = cpal::default_host()
+        .output_devices()
+        .map_err(|e| format!("Failed to list output devices: {}", e))?;
+
+    Ok(devices
+        .filter_map(|device| device.name().ok())
+        .map(|name| OutputDevice {
+            is_default: default_name.as_ref() == Some(&name),
+            name,
+        })
+        .collect())
+}
+
+fn find_output_device(name: &str) -> Option<cpal::Device> {
+    cpal::default_host()
+        .output_devices()
+        .ok()?
+        .find(|device| device.name().is_ok_and(|device_name| device_name == name))
+}
+
+fn default_output_device_name() -> Option<String> {
+    cpal::default_host()
+        .default_output_device()
+        .and_then(|device| device.name().ok())
+}
+
+pub fn open_source(path: &Path) -> Result<(Decoder<BufReader<File>>, Option<Duration>), String> {
+    let file = File::open(path)
+        .map_err(|e| format!("Error opening file: {}: {}", path.display(), e))?;
+    let source = Decoder::new(BufReader::new(file))
+        .map_err(|e| format!("Error decoding audio: {}", e))?;
+
+    let duration = source.total_duration().or_else(|| probe_duration(path));
+    Ok((source, duration))
+}
+
+pub fn probe_duration(path: &Path) -> Option<Duration> {
+    let probed = probe_file(path).ok()?;
+    codec_duration(&probed.format.default_track()?.codec_params)
+}
+
+// The same symphonia probe the decoder behind `open_source` runs, for readers
+// that need the codec parameters or samples wider than 16 bits.
+pub fn probe_file(path: &Path) -> Result<ProbeResult, String> {
+    let file = File::open(path).map_err(|e| format!("Error opening file: {}: {}", path.display(), e))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| format!("Error decoding audio: {}", e))
+}
+
+pub fn codec_duration(params: &CodecParameters) -> Option<Duration> {
+    let frames = params.n_frames?;
+
+    if let Some(time_base) = params.time_base {
+        let time = time_base.calc_time(frames);
+        return Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac));
+    }
+    let sample_rate = params.sample_rate?;
+    Some(Duration::from_secs_f64(frames as f64 / sample_rate as f64))
+}
@@ -0,0 +1,693 @@
+use rand::{rngs::ThreadRng, Rng};
+use serde::{Deserialize, Serialize};
+use std::{
+    f64::consts::PI,
+    fs::{self, File},
+    io::{BufWriter, ErrorKind},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{CodecParameters, DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as DecodeError,
+    sample::SampleFormat as CodecSampleFormat,
+};
+use tauri::AppHandle;
+
+use crate::{audio::probe_file, emit_event, flac::FlacWriter, vorbis::VorbisWriter};
+
+const PROGRESS_STEP: f32 = 0.05;
+const MIN_SAMPLE_RATE: u32 = 8_000;
+const MAX_SAMPLE_RATE: u32 = 192_000;
+// Windowed-sinc taps on each side of an output sample, and how finely the
+// filter is tabulated between two input samples.
+const RESAMPLE_HALF_TAPS: usize = 32;
+const RESAMPLE_PHASES: usize = 256;
+// Cutoff as a fraction of the lower Nyquist frequency, leaving room for the
+// transition band so nothing folds back.
+const RESAMPLE_BANDWIDTH: f64 = 0.95;
+
+static NEXT_JOB: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    Wav,
+    Flac,
+    Vorbis,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SampleFormat {
+    #[serde(rename = "16")]
+    Int16,
+    #[serde(rename = "24")]
+    Int24,
+    #[serde(rename = "32f")]
+    Float32,
+}
+
+impl SampleFormat {
+    fn int_bits(self) -> Option<u32> {
+        match self {
+            SampleFormat::Int16 => Some(16),
+            SampleFormat::Int24 => Some(24),
+            SampleFormat::Float32 => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct ConvertOptions {
+    // None keeps the source's rate and picks the sample format that holds
+    // the source without loss: float stays float, above 16 bits becomes 24.
+    pub sample_rate: Option<u32>,
+    pub sample_format: Option<SampleFormat>,
+    // TPDF dither whenever samples end up with fewer bits than they had.
+    pub dither: bool,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        ConvertOptions {
+            sample_rate: None,
+            sample_format: None,
+            dither: true,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ConvertProgress {
+    pub job: u64,
+    pub input: String,
+    pub progress: f32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ConvertFinished {
+    pub job: u64,
+    pub input: String,
+    pub output: String,
+    pub error: Option<String>,
+}
+
+pub fn next_job_id() -> u64 {
+    NEXT_JOB.fetch_add(1, Ordering::Relaxed)
+}
+
+// Everything that can be checked without decoding, so the command fails
+// straight away instead of through a finished event.
+pub fn check_conversion(input: &Path, output: &Path, format: OutputFormat, options: &ConvertOptions) -> Result<(), String> {
+    if format == OutputFormat::Flac && options.sample_format == Some(SampleFormat::Float32) {
+        return Err("FLAC only stores integer samples. Choose 16 or 24 bit.".to_string());
+    }
+    if format == OutputFormat::Vorbis && options.sample_format.is_some_and(|format| format.int_bits().is_some()) {
+        return Err("Ogg Vorbis has no bit depth to choose.".to_string());
+    }
+    if let Some(rate) = options.sample_rate {
+        if !(MIN_SAMPLE_RATE..=MAX_SAMPLE_RATE).contains(&rate) {
+            return Err(format!("Sample rate must be between {} and {} Hz.", MIN_SAMPLE_RATE, MAX_SAMPLE_RATE));
+        }
+    }
+
+    if !input.is_file() {
+        return Err(format!("File not found: {}", input.display()));
+    }
+    if output.parent().is_some_and(|parent| !parent.as_os_str().is_empty() && !parent.is_dir()) {
+        return Err(format!("Output folder does not exist: {}", output.display()));
+    }
+    if output.exists() && fs::canonicalize(input).ok() == fs::canonicalize(output).ok() {
+        return Err("Output would overwrite the input file.".to_string());
+    }
+    Ok(())
+}
+
+// Decodes with symphonia directly rather than through `open_source`, which
+// rodio narrows to 16 bits, so 24-bit and float sources keep their precision.
+// Output goes to a .part file that replaces `output` only once complete.
+pub fn convert_file(
+    app: &AppHandle,
+    job: u64,
+    input: &Path,
+    output: &Path,
+    format: OutputFormat,
+    options: &ConvertOptions,
+) -> Result<(), String> {
+    let partial = partial_path(output);
+    let result = encode(app, job, input, &partial, format, options).and_then(|()| {
+        fs::rename(&partial, output).map_err(|e| format!("Failed to move {} into place: {}", output.display(), e))
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+fn partial_path(output: &Path) -> PathBuf {
+    let mut name = output.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    output.with_file_name(name)
+}
+
+fn encode(
+    app: &AppHandle,
+    job: u64,
+    input: &Path,
+    output: &Path,
+    format: OutputFormat,
+    options: &ConvertOptions,
+) -> Result<(), String> {
+    let mut probed = probe_file(input)?;
+    let track = probed
+        .format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(format!("No audio track in {}", input.display()))?;
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+
+    let source_rate = params.sample_rate.ok_or("Unknown sample rate.")?;
+    let channels = params.channels.map(|channels| channels.count()).ok_or("Unknown channel layout.")?;
+    let sample_rate = options.sample_rate.unwrap_or(source_rate);
+    let sample_format = options.sample_format.unwrap_or(default_sample_format(format, &params));
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&params, &DecoderOptions::default())
+        .map_err(|e| format!("Error decoding audio: {}", e))?;
+
+    let mut resampler = (sample_rate != source_rate).then(|| Resampler::new(channels, source_rate, sample_rate));
+    let narrowed = match (sample_format.int_bits(), params.bits_per_sample) {
+        (None, _) => false,
+        (Some(bits), Some(source_bits)) if !is_float(&params) => source_bits > bits,
+        (Some(_), _) => true,
+    };
+    let dither = options.dither && (narrowed || resampler.is_some());
+    let mut writer = Writer::create(output, format, channels, sample_rate, sample_format, dither)?;
+
+    let input_label = input.to_string_lossy().into_owned();
+
+    let expected_frames = params.n_frames.filter(|&frames| frames > 0);
+    let mut frames: u64 = 0;
+    let mut last_progress = 0.0;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    let mut resampled = Vec::new();
+
+    loop {
+        let packet = match probed.format.next_packet() {
+            Ok(packet) => packet,
+            Err(DecodeError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(format!("Error reading {}: {}", input_label, e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(DecodeError::DecodeError(e)) => {
+                eprintln!("Skipping corrupt packet in {}: {}", input_label, e);
+                continue;
+            }
+            Err(e) => return Err(format!("Error decoding {}: {}", input_label, e)),
+        };
+        let buffer = buffer.get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, *decoded.spec()));
+        if buffer.capacity() < decoded.capacity() * channels {
+            *buffer = SampleBuffer::new(decoded.capacity() as u64, *decoded.spec());
+        }
+        buffer.copy_interleaved_ref(decoded);
+        let samples = buffer.samples();
+        frames += (samples.len() / channels) as u64;
+
+        match &mut resampler {
+            Some(resampler) => {
+                resampler.process(samples, &mut resampled);
+                writer.write(&resampled)?;
+                resampled.clear();
+            }
+            None => writer.write(samples)?,
+        }
+
+        if let Some(expected) = expected_frames {
+            let progress = (frames as f32 / expected as f32).min(1.0);
+            if progress - last_progress >= PROGRESS_STEP {
+                last_progress = progress;
+                emit_event(app, "convert-progress", ConvertProgress { job, input: input_label.clone(), progress });
+            }
+        }
+    }
+
+    if let Some(resampler) = &mut resampler {
+        resampler.flush(&mut resampled);
+        writer.write(&resampled)?;
+    }
+    writer.finish()?;
+    emit_event(app, "convert-progress", ConvertProgress { job, input: input_label, progress: 1.0 });
+    Ok(())
+}
+
+fn is_float(params: &CodecParameters) -> bool {
+    matches!(params.sample_format, Some(CodecSampleFormat::F32 | CodecSampleFormat::F64))
+}
+
+fn default_sample_format(format: OutputFormat, params: &CodecParameters) -> SampleFormat {
+    // Vorbis encodes the decoded floats directly.
+    if format == OutputFormat::Vorbis {
+        return SampleFormat::Float32;
+    }
+    if is_float(params) {
+        return match format {
+            OutputFormat::Flac => SampleFormat::Int24,
+            _ => SampleFormat::Float32,
+        };
+    }
+    match params.bits_per_sample {
+        Some(bits) if bits > 16 => SampleFormat::Int24,
+        _ => SampleFormat::Int16,
+    }
+}
+
+enum Encoder {
+    Wav(hound::WavWriter<BufWriter<File>>),
+    Flac(FlacWriter),
+    Vorbis(VorbisWriter),
+}
+
+struct Writer {
+    encoder: Encoder,
+    quantizer: Option<Quantizer>,
+    quantized: Vec<i32>,
+}
+
+impl Writer {
+    fn create(
+        path: &Path,
+        format: OutputFormat,
+        channels: usize,
+        sample_rate: u32,
+        sample_format: SampleFormat,
+        dither: bool,
+    ) -> Result<Writer, String> {
+        let quantizer = sample_format.int_bits().map(|bits| Quantizer::new(bits, dither));
+        let encoder = match format {
+            OutputFormat::Wav => {
+                let spec = hound::WavSpec {
+                    channels: channels as u16,
+                    sample_rate,
+                    bits_per_sample: sample_format.int_bits().unwrap_or(32) as u16,
+                    sample_format: match sample_format {
+                        SampleFormat::Float32 => hound::SampleFormat::Float,
+                        _ => hound::SampleFormat::Int,
+                    },
+                };
+                let writer = hound::WavWriter::create(path, spec)
+                    .map_err(|e| format!("Error creating file: {}: {}", path.display(), e))?;
+                Encoder::Wav(writer)
+            }
+            OutputFormat::Flac => {
+                let bits = sample_format.int_bits().ok_or("FLAC only stores integer samples.")?;
+                Encoder::Flac(FlacWriter::create(path, channels, sample_rate, bits)?)
+            }
+            OutputFormat::Vorbis => Encoder::Vorbis(VorbisWriter::create(path, channels, sample_rate)?),
+        };
+
+        Ok(Writer {
+            encoder,
+            quantizer,
+            quantized: Vec::new(),
+        })
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let Some(quantizer) = &mut self.quantizer else {
+            match &mut self.encoder {
+                Encoder::Wav(writer) => {
+                    for sample in samples {
+                        writer
+                            .write_sample(*sample)
+                            .map_err(|e| format!("Failed to write WAV file: {}", e))?;
+                    }
+                }
+                Encoder::Vorbis(writer) => writer.write(samples)?,
+                Encoder::Flac(_) => {}
+            }
+            return Ok(());
+        };
+
+        self.quantized.clear();
+        self.quantized.extend(samples.iter().map(|sample| quantizer.quantize(*sample)));
+        match &mut self.encoder {
+            Encoder::Wav(writer) => {
+                for sample in &self.quantized {
+                    writer
+                        .write_sample(*sample)
+                        .map_err(|e| format!("Failed to write WAV file: {}", e))?;
+                }
+                Ok(())
+            }
+            Encoder::Flac(writer) => writer.write(&self.quantized),
+            Encoder::Vorbis(writer) => writer.write(samples),
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self.encoder {
+            Encoder::Wav(writer) => writer.finalize().map_err(|e| format!("Failed to write WAV file: {}", e)),
+            Encoder::Flac(writer) => writer.finish(),
+            Encoder::Vorbis(writer) => writer.finish(),
+        }
+    }
+}
+
+struct Quantizer {
+    scale: f32,
+    min: i32,
+    max: i32,
+    dither: Option<ThreadRng>,
+}
+
+impl Quantizer {
+    fn new(bits: u32, dither: bool) -> Quantizer {
+        let max = (1i32 << (bits - 1)) - 1;
+        Quantizer {
+            scale: (max + 1) as f32,
+            min: -(max + 1),
+            max,
+            dither: dither.then(rand::rng),
+        }
+    }
+
+    fn quantize(&mut self, sample: f32) -> i32 {
+        let mut value = sample * self.scale;
+        // The difference of two uniform values is triangular over ±1 LSB,
+        // which makes the rounding error independent of the signal.
+        if let Some(rng) = &mut self.dither {
+            value += rng.random::<f32>() - rng.random::<f32>();
+        }
+        (value.round() as i32).clamp(self.min, self.max)
+    }
+}
+
+// Band-limited resampling by windowed-sinc interpolation. The filter is
+// tabulated at RESAMPLE_PHASES offsets between input samples and linearly
+// interpolated in between, which keeps it cheap at arbitrary ratios.
+struct Resampler {
+    channels: usize,
+    // Input frames per output frame.
+    step: f64,
+    ratio: f64,
+    table: Vec<f32>,
+    // Interleaved input, starting with zeros so the first output has history.
+    buffer: Vec<f32>,
+    position: f64,
+    input_frames: u64,
+    output_frames: u64,
+    taps: Vec<f32>,
+}
+
+impl Resampler {
+    fn new(channels: usize, from: u32, to: u32) -> Resampler {
+        let ratio = to as f64 / from as f64;
+        let cutoff = ratio.min(1.0) * RESAMPLE_BANDWIDTH;
+        let width = 2 * RESAMPLE_HALF_TAPS;
+
+        let mut table = Vec::with_capacity((RESAMPLE_PHASES + 1) * width);
+        for phase in 0..=RESAMPLE_PHASES {
+            let offset = phase as f64 / RESAMPLE_PHASES as f64;
+            for tap in 0..width {
+                let x = tap as f64 + 1.0 - RESAMPLE_HALF_TAPS as f64 - offset;
+                table.push((cutoff * sinc(cutoff * x) * blackman(x / RESAMPLE_HALF_TAPS as f64)) as f32);
+            }
+        }
+
+        Resampler {
+            channels,
+            step: 1.0 / ratio,
+            ratio,
+            table,
+            buffer: vec![0.0; RESAMPLE_HALF_TAPS * channels],
+            position: RESAMPLE_HALF_TAPS as f64,
+            input_frames: 0,
+            output_frames: 0,
+            taps: vec![0.0; width],
+        }
+    }
+
+    fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        self.buffer.extend_from_slice(input);
+        self.input_frames += (input.len() / self.channels) as u64;
+        self.drain(output, u64::MAX);
+    }
+
+    // Pads with silence until the last input sample has left the filter,
+    // stopping at the length the input maps to.
+    fn flush(&mut self, output: &mut Vec<f32>) {
+        let expected = (self.input_frames as f64 * self.ratio).round() as u64;
+        self.buffer.resize(self.buffer.len() + 2 * RESAMPLE_HALF_TAPS * self.channels, 0.0);
+        self.drain(output, expected);
+    }
+
+    fn drain(&mut self, output: &mut Vec<f32>, limit: u64) {
+        let frames = self.buffer.len() / self.channels;
+        let width = 2 * RESAMPLE_HALF_TAPS;
+
+        while self.output_frames < limit {
+            let index = self.position as usize;
+            if index + RESAMPLE_HALF_TAPS >= frames {
+                break;
+            }
+
+            let phase = (self.position - index as f64) * RESAMPLE_PHASES as f64;
+            let row = (phase as usize).min(RESAMPLE_PHASES - 1);
+            let blend = (phase - row as f64) as f32;
+            let (low, high) = (&self.table[row * width..], &self.table[(row + 1) * width..]);
+            for (tap, (low, high)) in self.taps.iter_mut().zip(low.iter().zip(high)) {
+                *tap = low + (high - low) * blend;
+            }
+
+            let first = index + 1 - RESAMPLE_HALF_TAPS;
+            for channel in 0..self.channels {
+                let sample: f32 = self
+                    .taps
+                    .iter()
+                    .enumerate()
+                    .map(|(tap, weight)| self.buffer[(first + tap) * self.channels + channel] * weight)
+                    .sum();
+                output.push(sample);
+            }
+            self.position += self.step;
+            self.output_frames += 1;
+        }
+
+        // Drop input that no later output can reach.
+        let consumed = (self.position as usize + 1).saturating_sub(RESAMPLE_HALF_TAPS).min(frames);
+        self.buffer.drain(..consumed * self.channels);
+        self.position -= consumed as f64;
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+fn blackman(t: f64) -> f64 {
+    if t.abs() >= 1.0 {
+        return 0.0;
+    }
+    0.42 + 0.5 * (PI * t).cos() + 0.08 * (2.0 * PI * t).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use symphonia::core::conv::ConvertibleSample;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("convert-test-{}-{}", std::process::id(), name))
+    }
+
+    // A deterministic mix of tones, noise-like values and both full-scale extremes.
+    fn test_signal(frames: usize, channels: usize, bits: u32) -> Vec<i32> {
+        let max = (1i64 << (bits - 1)) - 1;
+        let mut state = 0x2545_f491_u64;
+        (0..frames * channels)
+            .map(|i| match i % 97 {
+                0 => max as i32,
+                1 => -(max as i32) - 1,
+                _ => {
+                    state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                    let tone = ((i / channels) as f64 * 0.05 * (1 + i % channels) as f64).sin() * 0.6;
+                    let noise = ((state >> 33) as f64 / (1u64 << 31) as f64 - 0.5) * 0.3;
+                    ((tone + noise) * max as f64) as i32
+                }
+            })
+            .collect()
+    }
+
+    fn write_file(path: &Path, format: OutputFormat, channels: usize, samples: &[i32], bits: u32) {
+        let sample_format = if bits == 16 { SampleFormat::Int16 } else { SampleFormat::Int24 };
+        let mut writer = Writer::create(path, format, channels, 44_100, sample_format, false).unwrap();
+        let scale = (1u32 << (bits - 1)) as f32;
+        let floats: Vec<f32> = samples.iter().map(|&sample| sample as f32 / scale).collect();
+        // Uneven chunks so encoder blocks do not line up with writes.
+        for chunk in floats.chunks(1_000 * channels + channels) {
+            writer.write(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn decode_samples<S: ConvertibleSample>(path: &Path) -> (usize, Vec<S>) {
+        let mut probed = probe_file(path).unwrap();
+        let track = probed.format.default_track().unwrap();
+        let (track_id, params) = (track.id, track.codec_params.clone());
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&params, &DecoderOptions::default())
+            .unwrap();
+
+        let mut samples = Vec::new();
+        while let Ok(packet) = probed.format.next_packet() {
+            if packet.track_id() != track_id {
+                continue;
+            }
+            let decoded = decoder.decode(&packet).unwrap();
+            let mut buffer = SampleBuffer::<S>::new(decoded.capacity() as u64, *decoded.spec());
+            buffer.copy_interleaved_ref(decoded);
+            samples.extend_from_slice(buffer.samples());
+        }
+        (params.channels.unwrap().count(), samples)
+    }
+
+    // Returns the channel count and the samples at their stored bit depth.
+    fn decode_file(path: &Path, bits: u32) -> (usize, Vec<i32>) {
+        let (channels, samples) = decode_samples::<i32>(path);
+        (channels, samples.iter().map(|sample| sample >> (32 - bits)).collect())
+    }
+
+    fn assert_round_trip(format: OutputFormat, extension: &str, channels: usize, frames: usize, bits: u32) {
+        let path = temp_path(&format!("{}ch-{}bit-{}.{}", channels, bits, frames, extension));
+        let samples = test_signal(frames, channels, bits);
+        write_file(&path, format, channels, &samples, bits);
+        let decoded = decode_file(&path, bits);
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(decoded.0, channels);
+        assert_eq!(decoded.1.len(), samples.len(), "{} channels, {} bits", channels, bits);
+        assert!(decoded.1 == samples, "{} channels, {} bits: samples differ", channels, bits);
+    }
+
+    #[test]
+    fn wav_round_trips_bit_exact() {
+        assert_round_trip(OutputFormat::Wav, "wav", 2, 10_007, 16);
+        assert_round_trip(OutputFormat::Wav, "wav", 1, 10_007, 24);
+    }
+
+    #[test]
+    fn flac_round_trips_bit_exact() {
+        assert_round_trip(OutputFormat::Flac, "flac", 2, 20_011, 16);
+        assert_round_trip(OutputFormat::Flac, "flac", 2, 20_011, 24);
+        assert_round_trip(OutputFormat::Flac, "flac", 6, 9_000, 24);
+        assert_round_trip(OutputFormat::Flac, "flac", 1, 3, 16);
+    }
+
+    // Lossy, so compared by signal to noise: a tone per channel over a little
+    // noise, with the decoded length allowed to run to the end of the last block.
+    #[test]
+    fn vorbis_round_trips_within_tolerance() {
+        for (channels, frames) in [(2, 44_100), (1, 1_000), (6, 5_000)] {
+            let path = temp_path(&format!("{}ch-{}.ogg", channels, frames));
+            let mut state = 0x2545_f491_u64;
+            let samples: Vec<f32> = (0..frames * channels)
+                .map(|i| {
+                    state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                    let frequency = 220.0 * (1 + i % channels) as f64;
+                    let tone = (2.0 * PI * frequency * (i / channels) as f64 / 44_100.0).sin() * 0.5;
+                    let noise = ((state >> 33) as f64 / (1u64 << 31) as f64 - 0.5) * 0.02;
+                    (tone + noise) as f32
+                })
+                .collect();
+
+            let mut writer = Writer::create(&path, OutputFormat::Vorbis, channels, 44_100, SampleFormat::Float32, false)
+                .unwrap();
+            for chunk in samples.chunks(1_000 * channels + channels) {
+                writer.write(chunk).unwrap();
+            }
+            writer.finish().unwrap();
+            let (decoded_channels, decoded) = decode_samples::<f32>(&path);
+            let _ = fs::remove_file(&path);
+
+            assert_eq!(decoded_channels, channels);
+            assert!(
+                (samples.len()..=samples.len() + 1_024 * channels).contains(&decoded.len()),
+                "{} channels: {} samples decoded from {}",
+                channels,
+                decoded.len(),
+                samples.len()
+            );
+            for channel in 0..channels {
+                let (mut signal, mut noise) = (0.0f64, 0.0f64);
+                for (original, decoded) in samples.iter().zip(&decoded).skip(channel).step_by(channels) {
+                    signal += (*original as f64).powi(2);
+                    noise += (*original as f64 - *decoded as f64).powi(2);
+                }
+                let snr = 10.0 * (signal / noise).log10();
+                assert!(snr > 30.0, "{} channels: channel {} at {:.1} dB", channels, channel, snr);
+            }
+        }
+    }
+
+    #[test]
+    fn quantizer_rounds_and_clamps() {
+        let mut quantizer = Quantizer::new(16, false);
+        assert_eq!(quantizer.quantize(0.0), 0);
+        assert_eq!(quantizer.quantize(1.4 / 32768.0), 1);
+        assert_eq!(quantizer.quantize(-1.0), -32768);
+        assert_eq!(quantizer.quantize(1.0), 32767);
+        assert_eq!(quantizer.quantize(2.0), 32767);
+
+        let mut dithered = Quantizer::new(24, true);
+        for i in 0..1_000 {
+            let sample = (i as f32 * 0.37).sin() * 0.9;
+            let error = dithered.quantize(sample) as f32 - sample * 8_388_608.0;
+            assert!(error.abs() <= 1.5, "error {} at {}", error, sample);
+        }
+    }
+
+    fn resample(channels: usize, from: u32, to: u32, input: &[f32]) -> Vec<f32> {
+        let mut resampler = Resampler::new(channels, from, to);
+        let mut output = Vec::new();
+        for chunk in input.chunks(4_093 * channels) {
+            resampler.process(chunk, &mut output);
+        }
+        resampler.flush(&mut output);
+        output
+    }
+
+    #[test]
+    fn resampler_output_length_follows_the_ratio() {
+        for (from, to) in [(44_100, 48_000), (48_000, 44_100), (44_100, 22_050), (8_000, 192_000)] {
+            for frames in [1, 999, 44_107] {
+                let output = resample(2, from, to, &vec![0.0; frames * 2]);
+                let expected = (frames as f64 * to as f64 / from as f64).round() as usize;
+                assert_eq!(output.len(), expected * 2, "{} frames from {} to {} Hz", frames, from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn resampler_keeps_unity_gain_at_dc() {
+        for (from, to) in [(44_100, 48_000), (48_000, 44_100), (96_000, 44_100)] {
+            let output = resample(2, from, to, &vec![0.5; from as usize * 2]);
+            // Away from the edges, where the filter still sees the silence around the input.
+            let middle = &output[output.len() / 4..output.len() * 3 / 4];
+            let worst = middle.iter().map(|sample| (sample - 0.5).abs()).fold(0.0, f32::max);
+            assert!(worst < 1e-3, "{} to {} Hz is off by {}", from, to, worst);
+        }
+    }
+}
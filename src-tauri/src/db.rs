@@ -2,7 +2,7 @@ use firebase_rs::Firebase;
 use serde::{Deserialize, Serialize};
 use std::{error::Error, fs};
 use std::path::Path;
-use base64::{encode, decode};
+use base64::{encode, decode, engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use dotenv::dotenv;
 use std::env;
 use argon2::{
@@ -13,6 +13,8 @@ use argon2::{
     Argon2
 };
 
+use crate::smart_playlists::SmartPlaylist;
+
 const FIREBASE_URL_ENV_VAR: &str = "FIREBASE_URL";
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -20,6 +22,10 @@ pub struct Sample {
     pub title: String,
     pub url: String,
     pub uploaded_by: String,
+    #[serde(default)]
+    pub bpm: Option<f32>,
+    #[serde(default)]
+    pub key: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -29,6 +35,12 @@ pub struct Preset {
     pub uploaded_by: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SharedSmartPlaylist {
+    pub playlist: SmartPlaylist,
+    pub shared_by: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct User {
     pub username: String,
@@ -157,6 +169,51 @@ pub async fn fetch_presets() -> Result<Vec<Preset>, Box<dyn Error>> {
     Ok(presets)
 }
 
+pub async fn share_smart_playlist(playlist: &SmartPlaylist, username: &str) -> Result<(), Box<dyn Error>> {
+    let firebase = init_firebase();
+    let shared_ref = firebase.at(&format!("smart_playlists/{}/{}", username, shared_playlist_key(&playlist.name)));
+
+    let shared = SharedSmartPlaylist {
+        playlist: playlist.clone(),
+        shared_by: username.to_string(),
+    };
+    shared_ref.set(&shared).await?;
+
+    println!("Smart playlist shared: {} by user '{}'", playlist.name, username);
+    Ok(())
+}
+
+// Playlist names may contain characters Firebase rejects in keys ('.', '/', '#', ...),
+// so the key is derived from the name and the name itself travels in the payload.
+fn shared_playlist_key(name: &str) -> String {
+    URL_SAFE_NO_PAD.encode(name)
+}
+
+pub async fn fetch_shared_smart_playlists() -> Result<Vec<SharedSmartPlaylist>, Box<dyn Error>> {
+    let firebase = init_firebase();
+    let shared_ref = firebase.at("smart_playlists");
+
+    let result: serde_json::Value = shared_ref.get().await?;
+    let mut playlists = Vec::new();
+
+    if let Some(users_map) = result.as_object() {
+        for (username, user_playlists) in users_map {
+            if let Some(inner_map) = user_playlists.as_object() {
+                for (key, playlist_value) in inner_map {
+                    match serde_json::from_value::<SharedSmartPlaylist>(playlist_value.clone()) {
+                        // Credit the user the entry is stored under rather than whatever the payload claims.
+                        Ok(shared) => playlists.push(SharedSmartPlaylist { shared_by: username.clone(), ..shared }),
+                        Err(e) => eprintln!("Skipping shared smart playlist '{}' from '{}': {}", key, username, e),
+                    }
+                }
+            }
+        }
+    }
+
+    println!("Fetched {} shared smart playlists from the database.", playlists.len());
+    Ok(playlists)
+}
+
 pub fn file_to_base64(file_path: &str) -> Result<String, Box<dyn Error>> {
     println!("Converting file to base64: {}", file_path);
     let file_bytes = fs::read(&file_path)?;
@@ -0,0 +1,739 @@
+use rodio::{source::SeekError, Source};
+use serde::{Deserialize, Serialize};
+use std::{
+    f32::consts::PI,
+    fs,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tauri::AppHandle;
+
+use crate::settings::app_data_file;
+
+const PRESETS_FILE: &str = "effect_presets.json";
+pub const MAX_EQ_BANDS: usize = 10;
+const MAX_GAIN_DB: f32 = 24.0;
+const MIN_Q: f32 = 0.1;
+const MAX_Q: f32 = 10.0;
+const MIN_FREQUENCY: f32 = 20.0;
+const MAX_FREQUENCY: f32 = 20_000.0;
+const BUTTERWORTH_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+// Adapters look for new settings this often, measured in frames.
+const REFRESH_FRAMES: usize = 256;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BandKind {
+    Peak,
+    LowShelf,
+    HighShelf,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EqBand {
+    pub kind: BandKind,
+    pub frequency: f32,
+    pub gain_db: f32,
+    pub q: f32,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PassFilter {
+    pub enabled: bool,
+    pub frequency: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Limiter {
+    pub enabled: bool,
+    pub threshold_db: f32,
+    pub release_ms: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct EffectsSettings {
+    // Bypasses the whole chain without losing the band settings.
+    pub enabled: bool,
+    pub bands: Vec<EqBand>,
+    pub high_pass: PassFilter,
+    pub low_pass: PassFilter,
+    pub limiter: Limiter,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Default for EffectsSettings {
+    fn default() -> Self {
+        let band = |kind, frequency| EqBand {
+            kind,
+            frequency,
+            gain_db: 0.0,
+            q: 1.0,
+            enabled: true,
+        };
+        EffectsSettings {
+            enabled: false,
+            bands: vec![
+                band(BandKind::LowShelf, 80.0),
+                band(BandKind::Peak, 250.0),
+                band(BandKind::Peak, 1000.0),
+                band(BandKind::Peak, 4000.0),
+                band(BandKind::HighShelf, 12000.0),
+            ],
+            high_pass: PassFilter {
+                enabled: false,
+                frequency: 30.0,
+            },
+            low_pass: PassFilter {
+                enabled: false,
+                frequency: 18000.0,
+            },
+            limiter: Limiter {
+                enabled: false,
+                threshold_db: -1.0,
+                release_ms: 100.0,
+            },
+        }
+    }
+}
+
+impl EffectsSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.bands.len() > MAX_EQ_BANDS {
+            return Err(format!("The equalizer supports at most {} bands.", MAX_EQ_BANDS));
+        }
+        for (index, band) in self.bands.iter().enumerate() {
+            validate_frequency(band.frequency).map_err(|e| format!("Band {}: {}", index, e))?;
+            if !(-MAX_GAIN_DB..=MAX_GAIN_DB).contains(&band.gain_db) {
+                return Err(format!("Band {}: gain must be between -{1} and {1} dB.", index, MAX_GAIN_DB));
+            }
+            if !(MIN_Q..=MAX_Q).contains(&band.q) {
+                return Err(format!("Band {}: Q must be between {} and {}.", index, MIN_Q, MAX_Q));
+            }
+        }
+        validate_frequency(self.high_pass.frequency).map_err(|e| format!("High-pass: {}", e))?;
+        validate_frequency(self.low_pass.frequency).map_err(|e| format!("Low-pass: {}", e))?;
+        if !(-24.0..=0.0).contains(&self.limiter.threshold_db) {
+            return Err("Limiter threshold must be between -24 and 0 dB.".to_string());
+        }
+        if !(1.0..=2000.0).contains(&self.limiter.release_ms) {
+            return Err("Limiter release must be between 1 and 2000 ms.".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn validate_frequency(frequency: f32) -> Result<(), String> {
+    if !(MIN_FREQUENCY..=MAX_FREQUENCY).contains(&frequency) {
+        return Err(format!(
+            "frequency must be between {} and {} Hz, got {}.",
+            MIN_FREQUENCY, MAX_FREQUENCY, frequency
+        ));
+    }
+    Ok(())
+}
+
+// Shared by the engine and every adapter it creates; the version tells
+// adapters on the audio thread when to pick up new settings.
+#[derive(Clone, Default)]
+pub struct EffectsControl {
+    settings: Arc<Mutex<EffectsSettings>>,
+    version: Arc<AtomicU64>,
+}
+
+impl EffectsControl {
+    pub fn set(&self, settings: EffectsSettings) {
+        *self.settings.lock().unwrap() = settings;
+        self.version.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn wrap<S>(&self, source: S) -> Limited<Equalized<S>>
+    where
+        S: Source<Item = i16>,
+    {
+        let equalized = Equalized {
+            inner: source,
+            control: self.clone(),
+            version: u64::MAX,
+            sample_rate: 0,
+            filters: Vec::new(),
+            channels: Vec::new(),
+            channel: 0,
+            frames: 0,
+        };
+        Limited {
+            inner: equalized,
+            control: self.clone(),
+            version: u64::MAX,
+            sample_rate: 0,
+            limiter: None,
+            envelope: 1.0,
+            frame: Vec::new(),
+            position: 0,
+            frames: 0,
+        }
+    }
+
+    // Never blocks the audio thread: if the lock is busy the old settings stay for another block.
+    fn latest(&self, seen: u64) -> Option<(u64, EffectsSettings)> {
+        let version = self.version.load(Ordering::Acquire);
+        if version == seen {
+            return None;
+        }
+        let settings = self.settings.try_lock().ok()?;
+        Some((version, settings.clone()))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Coefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Coefficients {
+    const UNITY: Coefficients = Coefficients {
+        b0: 1.0,
+        b1: 0.0,
+        b2: 0.0,
+        a1: 0.0,
+        a2: 0.0,
+    };
+
+    // Biquad designs from the RBJ audio EQ cookbook.
+    fn new(kind: FilterKind, frequency: f32, q: f32, gain_db: f32, sample_rate: f32) -> Coefficients {
+        let frequency = frequency.min(sample_rate * 0.49);
+        let w0 = 2.0 * PI * frequency / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+        let a = 10f32.powf(gain_db / 40.0);
+
+        let (b0, b1, b2, a0, a1, a2) = match kind {
+            FilterKind::Peak => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            FilterKind::LowShelf => {
+                let beta = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + beta),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - beta),
+                    (a + 1.0) + (a - 1.0) * cos + beta,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - beta,
+                )
+            }
+            FilterKind::HighShelf => {
+                let beta = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + beta),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - beta),
+                    (a + 1.0) - (a - 1.0) * cos + beta,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - beta,
+                )
+            }
+            FilterKind::HighPass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterKind::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+        };
+
+        Coefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum FilterKind {
+    Peak,
+    LowShelf,
+    HighShelf,
+    HighPass,
+    LowPass,
+}
+
+impl From<BandKind> for FilterKind {
+    fn from(kind: BandKind) -> Self {
+        match kind {
+            BandKind::Peak => FilterKind::Peak,
+            BandKind::LowShelf => FilterKind::LowShelf,
+            BandKind::HighShelf => FilterKind::HighShelf,
+        }
+    }
+}
+
+// Transposed direct form II state for one filter on one channel.
+#[derive(Clone, Copy, Default)]
+struct FilterState {
+    z1: f32,
+    z2: f32,
+}
+
+impl FilterState {
+    fn process(&mut self, c: &Coefficients, input: f32) -> f32 {
+        let output = c.b0 * input + self.z1;
+        self.z1 = c.b1 * input - c.a1 * output + self.z2;
+        self.z2 = c.b2 * input - c.a2 * output;
+        output
+    }
+}
+
+// Always the high-pass, one filter per band and the low-pass, so each filter keeps
+// its own state as settings change. Switched-off filters and 0 dB bands are unity.
+fn design(settings: &EffectsSettings, sample_rate: f32) -> Vec<Coefficients> {
+    if !settings.enabled {
+        return Vec::new();
+    }
+
+    let pass = |filter: &PassFilter, kind: FilterKind| {
+        if filter.enabled {
+            Coefficients::new(kind, filter.frequency, BUTTERWORTH_Q, 0.0, sample_rate)
+        } else {
+            Coefficients::UNITY
+        }
+    };
+    let mut filters = vec![pass(&settings.high_pass, FilterKind::HighPass)];
+    filters.extend(settings.bands.iter().map(|band| {
+        let gain_db = if band.enabled { band.gain_db } else { 0.0 };
+        Coefficients::new(band.kind.into(), band.frequency, band.q, gain_db, sample_rate)
+    }));
+    filters.push(pass(&settings.low_pass, FilterKind::LowPass));
+    filters
+}
+
+// High-pass, parametric bands and low-pass as one cascade of biquads. Samples
+// stay floating point until the limiter, so boosts are not clipped before it.
+pub struct Equalized<S> {
+    inner: S,
+    control: EffectsControl,
+    version: u64,
+    sample_rate: u32,
+    filters: Vec<Coefficients>,
+    // Filter state per channel, one entry per filter.
+    channels: Vec<Vec<FilterState>>,
+    channel: usize,
+    frames: usize,
+}
+
+impl<S> Equalized<S>
+where
+    S: Source<Item = i16>,
+{
+    fn refresh(&mut self) {
+        let sample_rate = self.inner.sample_rate();
+        let update = self.control.latest(self.version);
+        if update.is_none() && sample_rate == self.sample_rate {
+            return;
+        }
+
+        if let Some((version, settings)) = update {
+            self.version = version;
+            self.filters = design(&settings, sample_rate as f32);
+        } else if let Ok(settings) = self.control.settings.try_lock() {
+            self.filters = design(&settings, sample_rate as f32);
+        } else {
+            return;
+        }
+        self.sample_rate = sample_rate;
+
+        let channels = self.inner.channels().max(1) as usize;
+        // Keep filter memory unless a band was added or removed, so adjusting one does not click.
+        if self.channels.len() != channels || self.channels.first().map(Vec::len) != Some(self.filters.len()) {
+            self.channels = vec![vec![FilterState::default(); self.filters.len()]; channels];
+        }
+    }
+}
+
+impl<S> Iterator for Equalized<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 {
+            if self.frames % REFRESH_FRAMES == 0 {
+                self.refresh();
+            }
+            self.frames += 1;
+        }
+
+        let sample = self.inner.next()?;
+        let channel = self.channel;
+        self.channel = (self.channel + 1) % self.inner.channels().max(1) as usize;
+        let mut value = sample as f32 / 32768.0;
+        if let Some(states) = self.channels.get_mut(channel) {
+            for (state, coefficients) in states.iter_mut().zip(&self.filters) {
+                value = state.process(coefficients, value);
+            }
+        }
+        Some(value)
+    }
+}
+
+impl<S> Source for Equalized<S>
+where
+    S: Source<Item = i16>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.channel = 0;
+        self.inner.try_seek(pos)
+    }
+}
+
+// Peak limiter with instant attack, linked across channels so the stereo image holds.
+// This is where the chain is quantized back to 16 bits.
+pub struct Limited<S> {
+    inner: S,
+    control: EffectsControl,
+    version: u64,
+    sample_rate: u32,
+    // Threshold as a linear level and the per-sample release coefficient.
+    limiter: Option<(f32, f32)>,
+    envelope: f32,
+    frame: Vec<f32>,
+    position: usize,
+    frames: usize,
+}
+
+impl<S> Limited<S>
+where
+    S: Source<Item = f32>,
+{
+    // The release coefficient is per sample, so it follows sample rate changes as well as settings.
+    fn refresh(&mut self) {
+        let sample_rate = self.inner.sample_rate();
+        let update = self.control.latest(self.version);
+        if update.is_none() && sample_rate == self.sample_rate {
+            return;
+        }
+
+        if let Some((version, settings)) = update {
+            self.version = version;
+            self.limiter = limiter_parameters(&settings, sample_rate);
+        } else if let Ok(settings) = self.control.settings.try_lock() {
+            self.limiter = limiter_parameters(&settings, sample_rate);
+        } else {
+            return;
+        }
+        self.sample_rate = sample_rate;
+    }
+
+    fn fill_frame(&mut self) -> bool {
+        let channels = self.inner.channels().max(1) as usize;
+        self.frame.clear();
+        self.position = 0;
+        for _ in 0..channels {
+            match self.inner.next() {
+                Some(sample) => self.frame.push(sample),
+                None => break,
+            }
+        }
+        if self.frame.is_empty() {
+            return false;
+        }
+
+        let Some((threshold, release)) = self.limiter else {
+            return true;
+        };
+        let peak = self.frame.iter().map(|sample| sample.abs()).fold(0.0, f32::max);
+        let target = if peak > threshold { threshold / peak } else { 1.0 };
+        self.envelope = if target < self.envelope {
+            target
+        } else {
+            target + (self.envelope - target) * release
+        };
+
+        for sample in &mut self.frame {
+            *sample *= self.envelope;
+        }
+        true
+    }
+}
+
+fn limiter_parameters(settings: &EffectsSettings, sample_rate: u32) -> Option<(f32, f32)> {
+    (settings.enabled && settings.limiter.enabled).then(|| {
+        let threshold = 10f32.powf(settings.limiter.threshold_db / 20.0);
+        let release_samples = settings.limiter.release_ms / 1000.0 * sample_rate as f32;
+        (threshold, (-1.0 / release_samples.max(1.0)).exp())
+    })
+}
+
+impl<S> Iterator for Limited<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.position >= self.frame.len() {
+            if self.frames % REFRESH_FRAMES == 0 {
+                self.refresh();
+            }
+            self.frames += 1;
+            if !self.fill_frame() {
+                return None;
+            }
+        }
+        let sample = self.frame[self.position];
+        self.position += 1;
+        Some((sample * 32768.0).clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+    }
+}
+
+impl<S> Source for Limited<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.frame.clear();
+        self.position = 0;
+        self.inner.try_seek(pos)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EffectsPreset {
+    pub name: String,
+    pub settings: EffectsSettings,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct EffectsPresetStore {
+    pub presets: Vec<EffectsPreset>,
+}
+
+impl EffectsPresetStore {
+    pub fn load(app: &AppHandle) -> EffectsPresetStore {
+        let path = match app_data_file(app, PRESETS_FILE) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("{}", e);
+                return EffectsPresetStore::default();
+            }
+        };
+
+        let Ok(contents) = fs::read_to_string(&path) else {
+            println!("No effect presets found at {}, starting empty.", path.display());
+            return EffectsPresetStore::default();
+        };
+
+        match serde_json::from_str::<EffectsPresetStore>(&contents) {
+            Ok(store) => {
+                println!("Loaded {} effect presets.", store.presets.len());
+                store
+            }
+            Err(e) => {
+                eprintln!("Failed to parse effect presets at {}: {}", path.display(), e);
+                EffectsPresetStore::default()
+            }
+        }
+    }
+
+    pub fn save(&self, app: &AppHandle) -> Result<(), String> {
+        let path = app_data_file(app, PRESETS_FILE)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create effect presets directory: {}", e))?;
+        }
+
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize effect presets: {}", e))?;
+        fs::write(&path, contents).map_err(|e| format!("Failed to save effect presets: {}", e))?;
+        println!("Effect presets saved to {}", path.display());
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<&EffectsPreset, String> {
+        self.presets
+            .iter()
+            .find(|preset| preset.name == name)
+            .ok_or(format!("Effect preset '{}' not found.", name))
+    }
+
+    // Saving under an existing name overwrites that preset.
+    pub fn upsert(&mut self, name: &str, settings: EffectsSettings) -> Result<(), String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Effect preset name cannot be empty.".to_string());
+        }
+        settings.validate()?;
+
+        match self.presets.iter_mut().find(|preset| preset.name == name) {
+            Some(existing) => existing.settings = settings,
+            None => self.presets.push(EffectsPreset {
+                name: name.to_string(),
+                settings,
+            }),
+        }
+        Ok(())
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<(), String> {
+        let count = self.presets.len();
+        self.presets.retain(|preset| preset.name != name);
+        if self.presets.len() == count {
+            return Err(format!("Effect preset '{}' not found.", name));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    const SAMPLE_RATE: u32 = 44_100;
+
+    fn sine(amplitude: f32, frequency: f32, len: usize) -> Vec<i16> {
+        (0..len)
+            .map(|i| {
+                let phase = 2.0 * PI * frequency * i as f32 / SAMPLE_RATE as f32;
+                (phase.sin() * amplitude * 32767.0) as i16
+            })
+            .collect()
+    }
+
+    fn process(settings: EffectsSettings, input: &[i16]) -> Vec<i16> {
+        let control = EffectsControl::default();
+        control.set(settings);
+        control.wrap(SamplesBuffer::new(1, SAMPLE_RATE, input.to_vec())).collect()
+    }
+
+    #[test]
+    fn keeps_one_filter_per_band_across_zero_gain() {
+        let mut settings = EffectsSettings {
+            enabled: true,
+            ..EffectsSettings::default()
+        };
+        let count = design(&settings, SAMPLE_RATE as f32).len();
+        assert_eq!(count, settings.bands.len() + 2);
+
+        settings.bands[1].gain_db = 3.0;
+        settings.bands[2].enabled = false;
+        settings.high_pass.enabled = true;
+        assert_eq!(design(&settings, SAMPLE_RATE as f32).len(), count);
+    }
+
+    #[test]
+    fn flat_chain_is_bit_exact() {
+        let input = sine(0.9, 440.0, 4096);
+        let settings = EffectsSettings {
+            enabled: true,
+            ..EffectsSettings::default()
+        };
+        assert_eq!(process(settings, &input), input);
+    }
+
+    #[test]
+    fn limits_boosts_instead_of_clipping_them() {
+        let input = sine(0.5, 1000.0, SAMPLE_RATE as usize);
+        let mut settings = EffectsSettings {
+            enabled: true,
+            ..EffectsSettings::default()
+        };
+        settings.bands[2].gain_db = 12.0;
+        settings.limiter.enabled = true;
+        settings.limiter.threshold_db = -6.0;
+
+        let output = process(settings, &input);
+        let steady = &output[SAMPLE_RATE as usize / 2..];
+        let peak = steady.iter().map(|sample| sample.unsigned_abs()).max().unwrap();
+        assert!(peak <= 16_500, "peak {}", peak);
+        // A clipped sine spends most of its time near the peak; a scaled one does not.
+        let near_peak = steady.iter().filter(|sample| sample.unsigned_abs() as f32 > peak as f32 * 0.95).count();
+        assert!(near_peak < steady.len() / 4, "{} of {} samples near the peak", near_peak, steady.len());
+    }
+
+    #[test]
+    fn release_follows_sample_rate_changes() {
+        let mut settings = EffectsSettings {
+            enabled: true,
+            ..EffectsSettings::default()
+        };
+        settings.limiter.enabled = true;
+        let control = EffectsControl::default();
+        control.set(settings.clone());
+
+        let (sources, output) = rodio::queue::queue(false);
+        sources.append(SamplesBuffer::new(1, SAMPLE_RATE, sine(0.5, 440.0, 4096)));
+        sources.append(SamplesBuffer::new(1, 11_025, sine(0.5, 440.0, 4096)));
+        let mut limited = control.wrap(output);
+
+        limited.by_ref().take(2048).for_each(drop);
+        assert_eq!(limited.limiter, limiter_parameters(&settings, SAMPLE_RATE));
+        limited.by_ref().take(4096).for_each(drop);
+        assert_eq!(limited.sample_rate, 11_025);
+        assert_eq!(limited.limiter, limiter_parameters(&settings, 11_025));
+        assert_ne!(limiter_parameters(&settings, SAMPLE_RATE), limiter_parameters(&settings, 11_025));
+    }
+}
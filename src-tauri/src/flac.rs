@@ -0,0 +1,428 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+pub const BLOCK_SIZE: usize = 4096;
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_PARTITION_ORDER: u32 = 8;
+// Rice parameters above 14 need the 5-bit parameter coding.
+const MAX_RICE4_PARAMETER: u32 = 14;
+const MAX_RICE_PARAMETER: u32 = 30;
+
+// Minimal FLAC writer: fixed-size blocks, fixed polynomial predictors and
+// partitioned Rice residuals, with stereo decorrelation picked per block.
+// It compresses a little worse than the reference encoder at its default
+// level but needs no LPC analysis. The STREAMINFO MD5 is left zero, which
+// the format reserves for "not computed".
+pub struct FlacWriter {
+    file: BufWriter<File>,
+    channels: usize,
+    sample_rate: u32,
+    bits: u32,
+    block: Vec<Vec<i32>>,
+    frame_number: u64,
+    total_frames: u64,
+    min_frame_size: u32,
+    max_frame_size: u32,
+}
+
+impl FlacWriter {
+    pub fn create(path: &Path, channels: usize, sample_rate: u32, bits: u32) -> Result<FlacWriter, String> {
+        if !(1..=8).contains(&channels) {
+            return Err(format!("FLAC supports 1 to 8 channels, the source has {}.", channels));
+        }
+        let file = File::create(path).map_err(|e| format!("Error creating file: {}: {}", path.display(), e))?;
+        let mut writer = FlacWriter {
+            file: BufWriter::new(file),
+            channels,
+            sample_rate,
+            bits,
+            block: vec![Vec::with_capacity(BLOCK_SIZE); channels],
+            frame_number: 0,
+            total_frames: 0,
+            min_frame_size: u32::MAX,
+            max_frame_size: 0,
+        };
+
+        // STREAMINFO is rewritten with the final counts once the stream ends.
+        let header = writer.stream_info();
+        writer.write_bytes(b"fLaC")?;
+        writer.write_bytes(&header)?;
+        Ok(writer)
+    }
+
+    // Interleaved samples, already quantized to the stream's bit depth.
+    pub fn write(&mut self, samples: &[i32]) -> Result<(), String> {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, sample) in self.block.iter_mut().zip(frame) {
+                channel.push(*sample);
+            }
+            if self.block[0].len() == BLOCK_SIZE {
+                self.write_frame()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        if !self.block[0].is_empty() {
+            self.write_frame()?;
+        }
+
+        let header = self.stream_info();
+        self.file
+            .seek(SeekFrom::Start(4))
+            .map_err(|e| format!("Failed to finish FLAC header: {}", e))?;
+        self.write_bytes(&header)?;
+        self.file.flush().map_err(|e| format!("Failed to write FLAC file: {}", e))
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.file
+            .write_all(bytes)
+            .map_err(|e| format!("Failed to write FLAC file: {}", e))
+    }
+
+    fn stream_info(&self) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        // Last metadata block, type 0, 34 bytes long.
+        w.write(0x80, 8);
+        w.write(34, 24);
+        w.write(BLOCK_SIZE as u64, 16);
+        w.write(BLOCK_SIZE as u64, 16);
+        w.write(if self.max_frame_size == 0 { 0 } else { self.min_frame_size as u64 }, 24);
+        w.write(self.max_frame_size as u64, 24);
+        w.write(self.sample_rate as u64, 20);
+        w.write(self.channels as u64 - 1, 3);
+        w.write(self.bits as u64 - 1, 5);
+        w.write(self.total_frames, 36);
+        for _ in 0..4 {
+            w.write(0, 32);
+        }
+        w.bytes
+    }
+
+    fn write_frame(&mut self) -> Result<(), String> {
+        let size = self.block[0].len();
+        let (assignment, subframes) = self.decorrelate();
+
+        let mut w = BitWriter::default();
+        w.write(0x3FFE, 14);
+        w.write(0, 1);
+        // Fixed block size, so frames are numbered rather than sample-addressed.
+        w.write(0, 1);
+        // Block size as 16 bits after the header, sample rate from STREAMINFO.
+        w.write(0b0111, 4);
+        w.write(0, 4);
+        w.write(assignment, 4);
+        w.write(if self.bits == 16 { 0b100 } else { 0b110 }, 3);
+        w.write(0, 1);
+        write_utf8(&mut w, self.frame_number);
+        w.write(size as u64 - 1, 16);
+        let crc = crc8(&w.bytes);
+        w.write(crc as u64, 8);
+
+        for (samples, bits) in &subframes {
+            write_subframe(&mut w, samples, *bits);
+        }
+        w.align();
+        let crc = crc16(&w.bytes);
+        w.write(crc as u64, 16);
+
+        let frame_size = w.bytes.len() as u32;
+        self.min_frame_size = self.min_frame_size.min(frame_size);
+        self.max_frame_size = self.max_frame_size.max(frame_size);
+        self.write_bytes(&w.bytes)?;
+
+        self.frame_number += 1;
+        self.total_frames += size as u64;
+        for channel in &mut self.block {
+            channel.clear();
+        }
+        Ok(())
+    }
+
+    // Stereo blocks are coded as whichever pair of left, right, mid and side
+    // predicts best; the side channel needs one extra bit.
+    fn decorrelate(&self) -> (u64, Vec<(Vec<i32>, u32)>) {
+        let bits = self.bits;
+        if self.channels != 2 {
+            let subframes = self.block.iter().map(|channel| (channel.clone(), bits)).collect();
+            return (self.channels as u64 - 1, subframes);
+        }
+
+        let (left, right) = (&self.block[0], &self.block[1]);
+        let side: Vec<i32> = left.iter().zip(right).map(|(l, r)| l - r).collect();
+        let mid: Vec<i32> = left.iter().zip(right).map(|(l, r)| (l + r) >> 1).collect();
+        let [left_cost, right_cost, side_cost, mid_cost] =
+            [left, right, &side, &mid].map(|samples| best_fixed_order(samples).1);
+
+        let choices = [
+            (1, left_cost + right_cost),
+            (8, left_cost + side_cost),
+            (9, side_cost + right_cost),
+            (10, mid_cost + side_cost),
+        ];
+        let assignment = choices.iter().min_by_key(|(_, cost)| *cost).map_or(1, |(assignment, _)| *assignment);
+
+        let subframes = match assignment {
+            8 => vec![(left.clone(), bits), (side, bits + 1)],
+            9 => vec![(side, bits + 1), (right.clone(), bits)],
+            10 => vec![(mid, bits), (side, bits + 1)],
+            _ => vec![(left.clone(), bits), (right.clone(), bits)],
+        };
+        (assignment, subframes)
+    }
+}
+
+fn write_subframe(w: &mut BitWriter, samples: &[i32], bits: u32) {
+    if samples.iter().all(|sample| *sample == samples[0]) {
+        w.write(0, 8);
+        w.write_signed(samples[0] as i64, bits);
+        return;
+    }
+
+    let verbatim_bits = samples.len() as u64 * bits as u64;
+    if samples.len() > MAX_FIXED_ORDER {
+        let (order, _) = best_fixed_order(samples);
+        let residuals = fixed_residuals(samples, order);
+        let plan = plan_rice(&residuals, order, samples.len());
+        if plan.bits + (order as u64 * bits as u64) < verbatim_bits {
+            w.write(0, 1);
+            w.write(0b001000 | order as u64, 6);
+            w.write(0, 1);
+            for sample in &samples[..order] {
+                w.write_signed(*sample as i64, bits);
+            }
+            write_residuals(w, &residuals, order, samples.len(), &plan);
+            return;
+        }
+    }
+
+    w.write(0, 1);
+    w.write(0b000001, 6);
+    w.write(0, 1);
+    for sample in samples {
+        w.write_signed(*sample as i64, bits);
+    }
+}
+
+// Picks the polynomial order with the smallest absolute residual sum, the
+// same estimate the reference encoder uses for fixed subframes.
+fn best_fixed_order(samples: &[i32]) -> (usize, u64) {
+    if samples.len() <= MAX_FIXED_ORDER {
+        return (0, samples.iter().map(|sample| sample.unsigned_abs() as u64).sum());
+    }
+
+    let mut sums = [0u64; MAX_FIXED_ORDER + 1];
+    for i in MAX_FIXED_ORDER..samples.len() {
+        let x = |k: usize| samples[i - k] as i64;
+        let residuals = [
+            x(0),
+            x(0) - x(1),
+            x(0) - 2 * x(1) + x(2),
+            x(0) - 3 * x(1) + 3 * x(2) - x(3),
+            x(0) - 4 * x(1) + 6 * x(2) - 4 * x(3) + x(4),
+        ];
+        for (sum, residual) in sums.iter_mut().zip(residuals) {
+            *sum += residual.unsigned_abs();
+        }
+    }
+
+    sums.iter()
+        .copied()
+        .enumerate()
+        .min_by_key(|(_, sum)| *sum)
+        .unwrap_or((0, 0))
+}
+
+fn fixed_residuals(samples: &[i32], order: usize) -> Vec<i64> {
+    (order..samples.len())
+        .map(|i| {
+            let x = |k: usize| samples[i - k] as i64;
+            match order {
+                0 => x(0),
+                1 => x(0) - x(1),
+                2 => x(0) - 2 * x(1) + x(2),
+                3 => x(0) - 3 * x(1) + 3 * x(2) - x(3),
+                _ => x(0) - 4 * x(1) + 6 * x(2) - 4 * x(3) + x(4),
+            }
+        })
+        .collect()
+}
+
+struct RicePlan {
+    partition_order: u32,
+    parameters: Vec<u32>,
+    parameter_bits: u32,
+    // Size of the whole residual section.
+    bits: u64,
+}
+
+fn zigzag(residual: i64) -> u64 {
+    ((residual << 1) ^ (residual >> 63)) as u64
+}
+
+// Tries every partition order the block size allows and, per partition,
+// the Rice parameters around log2 of the mean, keeping the cheapest.
+fn plan_rice(residuals: &[i64], order: usize, block_size: usize) -> RicePlan {
+    let values: Vec<u64> = residuals.iter().map(|residual| zigzag(*residual)).collect();
+    let mut best: Option<RicePlan> = None;
+
+    for partition_order in 0..=MAX_PARTITION_ORDER {
+        let partitions = 1usize << partition_order;
+        let partition_size = block_size >> partition_order;
+        if block_size % partitions != 0 || partition_size <= order {
+            break;
+        }
+
+        let mut parameters = Vec::with_capacity(partitions);
+        let mut bits = 0;
+        let mut start = 0;
+        for partition in 0..partitions {
+            let count = if partition == 0 { partition_size - order } else { partition_size };
+            let (parameter, cost) = best_parameter(&values[start..start + count]);
+            parameters.push(parameter);
+            bits += cost;
+            start += count;
+        }
+
+        let parameter_bits = if parameters.iter().any(|parameter| *parameter > MAX_RICE4_PARAMETER) { 5 } else { 4 };
+        let bits = 6 + bits + parameter_bits as u64 * partitions as u64;
+        if !best.as_ref().is_some_and(|best| best.bits <= bits) {
+            best = Some(RicePlan {
+                partition_order,
+                parameters,
+                parameter_bits,
+                bits,
+            });
+        }
+    }
+
+    // Partition order 0 always fits, since the caller only predicts blocks longer than the order.
+    best.unwrap_or_else(|| {
+        let (parameter, cost) = best_parameter(&values);
+        RicePlan {
+            partition_order: 0,
+            parameters: vec![parameter],
+            parameter_bits: 5,
+            bits: 11 + cost,
+        }
+    })
+}
+
+fn best_parameter(values: &[u64]) -> (u32, u64) {
+    if values.is_empty() {
+        return (0, 0);
+    }
+    let mean = values.iter().sum::<u64>() / values.len() as u64;
+    let estimate = 64 - mean.leading_zeros();
+
+    (estimate.saturating_sub(1)..=estimate + 1)
+        .map(|parameter| parameter.min(MAX_RICE_PARAMETER))
+        .map(|parameter| {
+            let cost = values.iter().map(|value| (value >> parameter) + 1 + parameter as u64).sum();
+            (parameter, cost)
+        })
+        .min_by_key(|(_, cost)| *cost)
+        .unwrap_or((0, 0))
+}
+
+fn write_residuals(w: &mut BitWriter, residuals: &[i64], order: usize, block_size: usize, plan: &RicePlan) {
+    let parameter_bits = plan.parameter_bits;
+    w.write(if parameter_bits == 5 { 0b01 } else { 0b00 }, 2);
+    w.write(plan.partition_order as u64, 4);
+
+    let partition_size = block_size >> plan.partition_order;
+    let mut start = 0;
+    for (partition, parameter) in plan.parameters.iter().enumerate() {
+        let count = if partition == 0 { partition_size - order } else { partition_size };
+        w.write(*parameter as u64, parameter_bits);
+        for residual in &residuals[start..start + count] {
+            let value = zigzag(*residual);
+            w.write_unary(value >> parameter);
+            w.write(value, *parameter);
+        }
+        start += count;
+    }
+}
+
+// Frame numbers use the UTF-8 style variable-length coding, up to 36 bits.
+fn write_utf8(w: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        w.write(value, 8);
+        return;
+    }
+
+    let mut length = 2;
+    while length < 7 && value >= 1 << (5 * length + 1) {
+        length += 1;
+    }
+    w.write(((0xFF00u64 >> length) & 0xFF) | (value >> (6 * (length - 1))), 8);
+    for i in (0..length - 1).rev() {
+        w.write(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |mut crc, byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |mut crc, byte| {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    pending: u64,
+    pending_bits: u32,
+}
+
+impl BitWriter {
+    // Writes the low `count` bits of `value`, most significant first; at most 56 at a time.
+    fn write(&mut self, value: u64, count: u32) {
+        if count == 0 {
+            return;
+        }
+        self.pending = (self.pending << count) | (value & ((1 << count) - 1));
+        self.pending_bits += count;
+        while self.pending_bits >= 8 {
+            self.pending_bits -= 8;
+            self.bytes.push((self.pending >> self.pending_bits) as u8);
+        }
+        self.pending &= (1 << self.pending_bits) - 1;
+    }
+
+    fn write_signed(&mut self, value: i64, count: u32) {
+        self.write(value as u64, count);
+    }
+
+    fn write_unary(&mut self, mut zeros: u64) {
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+
+    fn align(&mut self) {
+        if self.pending_bits > 0 {
+            self.write(0, 8 - self.pending_bits);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+// Oldest plays are dropped past this so the library index stays small.
+pub const MAX_HISTORY: usize = 5000;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryEntry {
+    pub root: String,
+    pub path: String,
+    pub title: String,
+    pub artist: Option<String>,
+    pub played_at: u64,
+    pub skipped: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryFormat {
+    Json,
+    Csv,
+}
+
+impl HistoryFormat {
+    pub fn from_path(path: &Path) -> Result<HistoryFormat, String> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        match extension.as_deref() {
+            Some("json") => Ok(HistoryFormat::Json),
+            Some("csv") => Ok(HistoryFormat::Csv),
+            _ => Err(format!("Unsupported history format: {}", path.display())),
+        }
+    }
+}
+
+pub fn export_history_file(file: &Path, format: HistoryFormat, history: &[HistoryEntry]) -> Result<(), String> {
+    let contents = match format {
+        HistoryFormat::Json => serde_json::to_string_pretty(history)
+            .map_err(|e| format!("Failed to serialize history: {}", e))?,
+        HistoryFormat::Csv => write_csv(history),
+    };
+
+    fs::write(file, contents).map_err(|e| format!("Failed to write history {}: {}", file.display(), e))?;
+    println!("Exported {} history entries to {}", history.len(), file.display());
+    Ok(())
+}
+
+fn write_csv(history: &[HistoryEntry]) -> String {
+    let mut contents = String::from("played_at,root,path,title,artist,skipped\n");
+    for entry in history {
+        let fields = [
+            entry.played_at.to_string(),
+            csv_field(&entry.root),
+            csv_field(&entry.path),
+            csv_field(&entry.title),
+            csv_field(entry.artist.as_deref().unwrap_or_default()),
+            entry.skipped.to_string(),
+        ];
+        contents.push_str(&fields.join(","));
+        contents.push('\n');
+    }
+    contents
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
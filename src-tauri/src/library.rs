@@ -0,0 +1,462 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use symphonia::core::{
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::{MetadataOptions, MetadataRevision, StandardTagKey},
+    probe::Hint,
+};
+use walkdir::WalkDir;
+
+use crate::{
+    analysis::{analyze_file, Analysis},
+    audio::codec_duration,
+    loudness::{measure_file, Loudness},
+};
+
+const DEFAULT_EXTENSIONS: [&str; 8] = ["mp3", "wav", "flac", "ogg", "opus", "aiff", "aif", "m4a"];
+const DEFAULT_MAX_DEPTH: usize = 16;
+// Files indexed between "library-scan-progress" events.
+const SCAN_PROGRESS_INTERVAL: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Song {
+    pub title: String,
+    pub path: String,
+    pub root: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    // Effective tempo and key: the user's override, else the file's tags, else detection.
+    pub bpm: Option<f32>,
+    pub key: Option<String>,
+    pub tag_bpm: Option<f32>,
+    pub tag_key: Option<String>,
+    // None until the file has been analyzed.
+    pub analysis: Option<Analysis>,
+    pub loudness: Option<Loudness>,
+    pub duration: Option<f64>,
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u32>,
+    pub channels: Option<u16>,
+    // Seconds since the Unix epoch when the file first appeared in the library.
+    pub date_added: u64,
+    #[serde(flatten)]
+    pub stats: TrackStats,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct TrackStats {
+    pub play_count: u32,
+    pub skip_count: u32,
+    pub last_played: Option<u64>,
+    pub rating: u8,
+    pub favorite: bool,
+    pub bpm_override: Option<f32>,
+    pub key_override: Option<String>,
+}
+
+impl Song {
+    pub fn extension(&self) -> Option<String> {
+        Path::new(&self.path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase())
+    }
+
+    pub fn resolve_tempo_and_key(&mut self) {
+        let analysis = self.analysis.as_ref();
+        self.bpm = self
+            .stats
+            .bpm_override
+            .or(self.tag_bpm)
+            .or(analysis.and_then(|analysis| analysis.bpm));
+        self.key = self
+            .stats
+            .key_override
+            .clone()
+            .or_else(|| self.tag_key.clone())
+            .or_else(|| analysis.and_then(|analysis| analysis.key.clone()));
+    }
+}
+
+// Identifies a song across roots, since relative paths are only unique within one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SongRef {
+    pub root: String,
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LibraryRoot {
+    pub name: String,
+    pub path: String,
+}
+
+impl LibraryRoot {
+    pub fn from_directory(path: &str) -> LibraryRoot {
+        let name = Path::new(path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(path);
+        LibraryRoot {
+            name: name.to_string(),
+            path: path.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LibraryEntry {
+    #[serde(flatten)]
+    pub song: Song,
+    pub modified: u64,
+    pub size: u64,
+}
+
+impl LibraryEntry {
+    pub fn key(&self) -> (&str, &str) {
+        (&self.song.root, &self.song.path)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ScanOptions {
+    pub max_depth: usize,
+    pub extensions: Vec<String>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    // Detect tempo and key in the background after scanning; otherwise only on demand.
+    pub analyze: bool,
+    // Measure EBU R128 loudness in the background after scanning, for normalized playback.
+    pub measure_loudness: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            max_depth: DEFAULT_MAX_DEPTH,
+            extensions: DEFAULT_EXTENSIONS.iter().map(|ext| ext.to_string()).collect(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            analyze: false,
+            measure_loudness: false,
+        }
+    }
+}
+
+pub struct LibraryFilter {
+    max_depth: usize,
+    extensions: Vec<String>,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl LibraryFilter {
+    pub fn new(options: &ScanOptions) -> Result<Self, String> {
+        let extensions = options
+            .extensions
+            .iter()
+            .map(|ext| ext.trim_start_matches('.').to_lowercase())
+            .collect();
+        let include = if options.include.is_empty() {
+            None
+        } else {
+            Some(build_globset(&options.include)?)
+        };
+
+        Ok(LibraryFilter {
+            max_depth: options.max_depth,
+            extensions,
+            include,
+            exclude: build_globset(&options.exclude)?,
+        })
+    }
+
+    pub fn matches(&self, relative_path: &str) -> bool {
+        let extension = Path::new(relative_path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        let Some(extension) = extension else {
+            return false;
+        };
+
+        self.extensions.contains(&extension)
+            && self.include.as_ref().map_or(true, |include| include.is_match(relative_path))
+            && !self.exclude.is_match(relative_path)
+    }
+
+    // Applies the depth limit and directory exclusions a full scan would, for
+    // files that are checked one at a time.
+    pub fn accepts(&self, relative_path: &str) -> bool {
+        let parts: Vec<&str> = relative_path.split('/').collect();
+        parts.len() <= self.max_depth
+            && (1..parts.len()).all(|end| !self.exclude.is_match(parts[..end].join("/")))
+            && self.matches(relative_path)
+    }
+}
+
+fn build_globset(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| format!("Invalid pattern '{}': {}", pattern, e))?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|e| format!("Failed to build patterns: {}", e))
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+pub fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let parts = relative
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect::<Option<Vec<&str>>>()?;
+    Some(parts.join("/"))
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ScanProgress {
+    pub root: String,
+    pub scanned: usize,
+}
+
+pub fn scan_directory(
+    library_root: &LibraryRoot,
+    options: &ScanOptions,
+    previous: &[LibraryEntry],
+    mut on_progress: impl FnMut(usize),
+) -> Result<Vec<LibraryEntry>, String> {
+    let directory = library_root.path.as_str();
+    let root = Path::new(directory);
+    let filter = LibraryFilter::new(options)?;
+    let previous: HashMap<&str, &LibraryEntry> = previous
+        .iter()
+        .map(|entry| (entry.song.path.as_str(), entry))
+        .collect();
+
+    let mut entries = Vec::new();
+    let walker = WalkDir::new(root)
+        .follow_links(true)
+        .max_depth(options.max_depth)
+        .into_iter()
+        .filter_entry(|entry| {
+            !entry.file_type().is_dir()
+                || relative_path(root, entry.path())
+                    .map_or(true, |relative| !filter.exclude.is_match(relative))
+        });
+
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                match e.loop_ancestor() {
+                    Some(ancestor) => eprintln!("Skipping symlink loop back to {}", ancestor.display()),
+                    None => eprintln!("Failed to read entry in {}: {}", directory, e),
+                }
+                continue;
+            }
+        };
+
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let Some(relative) = relative_path(root, entry.path()) else {
+            continue;
+        };
+        if !filter.matches(&relative) {
+            continue;
+        }
+
+        let cached = previous.get(relative.as_str()).copied();
+        if let Some(indexed) = index_file(entry.path(), &library_root.name, relative, cached) {
+            entries.push(indexed);
+            if entries.len() % SCAN_PROGRESS_INTERVAL == 0 {
+                on_progress(entries.len());
+            }
+        }
+    }
+
+    on_progress(entries.len());
+    entries.sort_by(|a, b| a.song.path.cmp(&b.song.path));
+    println!("Found {} songs in directory: {}", entries.len(), directory);
+    Ok(entries)
+}
+
+// Reuses the cached entry when the file's size and modification time are
+// unchanged, so rescans only probe new or edited files.
+pub fn index_file(
+    path: &Path,
+    root: &str,
+    relative: String,
+    cached: Option<&LibraryEntry>,
+) -> Option<LibraryEntry> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let size = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_millis() as u64);
+
+    if let Some(cached) = cached {
+        if cached.size == size && cached.modified == modified {
+            return Some(cached.clone());
+        }
+    }
+
+    // Edited files are re-probed, but keep when they were added and how they were played.
+    let (date_added, stats) = match cached {
+        Some(cached) => (cached.song.date_added, cached.song.stats.clone()),
+        None => (unix_now(), TrackStats::default()),
+    };
+
+    let file_name = path.file_name()?.to_str()?;
+    let mut song = Song {
+        title: file_name.to_string(),
+        path: relative,
+        root: root.to_string(),
+        date_added,
+        stats,
+        ..Default::default()
+    };
+    read_metadata(path, &mut song);
+    song.resolve_tempo_and_key();
+
+    Some(LibraryEntry { song, modified, size })
+}
+
+pub fn analyze_song(path: &Path, song: &mut Song) {
+    match analyze_file(path) {
+        Ok(analysis) => {
+            song.analysis = Some(analysis);
+            song.resolve_tempo_and_key();
+        }
+        Err(e) => eprintln!("Failed to analyze {}: {}", path.display(), e),
+    }
+}
+
+pub fn measure_song_loudness(path: &Path, song: &mut Song) {
+    match measure_file(path) {
+        Ok(loudness) => song.loudness = Some(loudness),
+        Err(e) => eprintln!("Failed to measure loudness of {}: {}", path.display(), e),
+    }
+}
+
+pub fn read_metadata(path: &Path, song: &mut Song) {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to open {}: {}", path.display(), e);
+            return;
+        }
+    };
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let mut probed = match symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    ) {
+        Ok(probed) => probed,
+        Err(e) => {
+            eprintln!("Failed to read metadata from {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    if let Some(track) = probed.format.default_track() {
+        let params = &track.codec_params;
+        song.duration = codec_duration(params).map(|duration| duration.as_secs_f64());
+        song.sample_rate = params.sample_rate;
+        song.bit_depth = params.bits_per_sample.or(params.bits_per_coded_sample);
+        song.channels = params.channels.map(|channels| channels.count() as u16);
+    }
+
+    // Tags read before the container (ID3v2) come first, container tags
+    // (Vorbis comments, FLAC, RIFF INFO, MP4 atoms) override them.
+    if let Some(metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.current() {
+            apply_tags(revision, song);
+        }
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        apply_tags(revision, song);
+    }
+}
+
+fn apply_tags(revision: &MetadataRevision, song: &mut Song) {
+    // Within one revision the release date is preferred over the original release date.
+    let mut date = None;
+    let mut original_date = None;
+    for tag in revision.tags() {
+        let value = tag.value.to_string().trim().to_string();
+        if value.is_empty() {
+            continue;
+        }
+
+        match tag.std_key {
+            Some(StandardTagKey::TrackTitle) => song.title = value,
+            Some(StandardTagKey::Artist) => song.artist = Some(value),
+            Some(StandardTagKey::Album) => song.album = Some(value),
+            Some(StandardTagKey::TrackNumber) => {
+                song.track_number = parse_leading_number(&value).or(song.track_number)
+            }
+            Some(StandardTagKey::Genre) => song.genre = Some(value),
+            Some(StandardTagKey::Date) => date = parse_leading_number(&value).or(date),
+            Some(StandardTagKey::OriginalDate) => original_date = parse_leading_number(&value).or(original_date),
+            Some(StandardTagKey::Bpm) => song.tag_bpm = value.parse::<f32>().ok().or(song.tag_bpm),
+            None if is_key_tag(&tag.key) => song.tag_key = Some(value),
+            _ => {}
+        }
+    }
+    song.year = date.or(original_date).or(song.year);
+}
+
+// Initial key has no standard tag in symphonia, so match the raw names used
+// by ID3v2 (TKEY), Vorbis comments (INITIALKEY/KEY) and RIFF INFO (IKEY).
+fn is_key_tag(key: &str) -> bool {
+    matches!(
+        key.to_uppercase().as_str(),
+        "TKEY" | "TKE" | "INITIALKEY" | "INITIAL KEY" | "KEY" | "IKEY"
+    )
+}
+
+// Handles values such as "3/12" for track numbers and "2019-05-01" for dates.
+fn parse_leading_number(value: &str) -> Option<u32> {
+    let digits: String = value.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
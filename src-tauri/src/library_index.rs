@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+};
+use tauri::AppHandle;
+
+use crate::{
+    history::{HistoryEntry, MAX_HISTORY},
+    library::{LibraryEntry, LibraryRoot, Song, SongRef},
+    loudness::{combine, Loudness},
+    settings::app_data_file,
+};
+
+const INDEX_FILE: &str = "library_cache.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct LibraryIndex {
+    pub roots: Vec<LibraryRoot>,
+    pub tracks: Vec<LibraryEntry>,
+    pub history: Vec<HistoryEntry>,
+}
+
+impl LibraryIndex {
+    pub fn load(app: &AppHandle) -> LibraryIndex {
+        let path = match index_path(app) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("{}", e);
+                return LibraryIndex::default();
+            }
+        };
+
+        let Ok(contents) = fs::read_to_string(&path) else {
+            println!("No library index found at {}, starting empty.", path.display());
+            return LibraryIndex::default();
+        };
+
+        match serde_json::from_str::<LibraryIndex>(&contents) {
+            Ok(index) => {
+                println!("Loaded {} songs from library index.", index.tracks.len());
+                index
+            }
+            Err(e) => {
+                eprintln!("Failed to parse library index at {}: {}", path.display(), e);
+                LibraryIndex::default()
+            }
+        }
+    }
+
+    pub fn save(&self, app: &AppHandle) -> Result<(), String> {
+        let path = index_path(app)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create library index directory: {}", e))?;
+        }
+
+        let contents = serde_json::to_string(self)
+            .map_err(|e| format!("Failed to serialize library index: {}", e))?;
+        fs::write(&path, contents).map_err(|e| format!("Failed to save library index: {}", e))
+    }
+
+    pub fn songs(&self) -> Vec<Song> {
+        self.tracks.iter().map(|entry| entry.song.clone()).collect()
+    }
+
+    pub fn root(&self, name: &str) -> Option<&LibraryRoot> {
+        self.roots.iter().find(|root| root.name == name)
+    }
+
+    pub fn song_mut(&mut self, root: &str, path: &str) -> Option<&mut Song> {
+        self.tracks
+            .iter_mut()
+            .find(|entry| entry.song.root == root && entry.song.path == path)
+            .map(|entry| &mut entry.song)
+    }
+
+    // Loudness of the measured tracks sharing this song's album within its root.
+    pub fn album_loudness(&self, song: &Song) -> Option<Loudness> {
+        let album = song.album.as_ref()?;
+        let tracks: Vec<(Loudness, f64)> = self
+            .tracks
+            .iter()
+            .filter(|entry| entry.song.root == song.root && entry.song.album.as_ref() == Some(album))
+            .filter_map(|entry| Some((entry.song.loudness?, entry.song.duration.unwrap_or(1.0))))
+            .collect();
+        combine(&tracks)
+    }
+
+    pub fn record_history(&mut self, entry: HistoryEntry) {
+        self.history.push(entry);
+        if self.history.len() > MAX_HISTORY {
+            let excess = self.history.len() - MAX_HISTORY;
+            self.history.drain(..excess);
+        }
+    }
+
+    pub fn root_tracks(&self, name: &str) -> Vec<LibraryEntry> {
+        self.tracks
+            .iter()
+            .filter(|entry| entry.song.root == name)
+            .cloned()
+            .collect()
+    }
+
+    pub fn replace_root_tracks(&mut self, name: &str, mut tracks: Vec<LibraryEntry>) {
+        // Plays and ratings recorded while a scan was running must not be lost,
+        // nor analysis and loudness the background job finished for files that did not change.
+        let mut current: HashMap<&str, &LibraryEntry> = self
+            .tracks
+            .iter()
+            .filter(|entry| entry.song.root == name)
+            .map(|entry| (entry.song.path.as_str(), entry))
+            .collect();
+        for entry in &mut tracks {
+            if let Some(current) = current.remove(entry.song.path.as_str()) {
+                entry.song.stats = current.song.stats.clone();
+                if current.modified == entry.modified && current.size == entry.size {
+                    entry.song.analysis = entry.song.analysis.take().or_else(|| current.song.analysis.clone());
+                    entry.song.loudness = entry.song.loudness.or(current.song.loudness);
+                }
+                entry.song.resolve_tempo_and_key();
+            }
+        }
+
+        self.tracks.retain(|entry| entry.song.root != name);
+        self.tracks.extend(tracks);
+        self.sort_tracks();
+    }
+
+    pub fn sort_tracks(&mut self) {
+        self.tracks.sort_by(|a, b| a.key().cmp(&b.key()));
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct LibraryChange {
+    pub added: Vec<Song>,
+    pub updated: Vec<Song>,
+    pub removed: Vec<SongRef>,
+}
+
+impl LibraryChange {
+    pub fn between(old: &[LibraryEntry], new: &[LibraryEntry]) -> LibraryChange {
+        let previous: HashMap<(&str, &str), &LibraryEntry> = old.iter().map(|entry| (entry.key(), entry)).collect();
+        let current: HashSet<(&str, &str)> = new.iter().map(|entry| entry.key()).collect();
+
+        let mut change = LibraryChange::default();
+        for entry in new {
+            match previous.get(&entry.key()) {
+                None => change.added.push(entry.song.clone()),
+                Some(before) if before.modified != entry.modified || before.size != entry.size => {
+                    change.updated.push(entry.song.clone())
+                }
+                Some(_) => {}
+            }
+        }
+        change.removed = old
+            .iter()
+            .filter(|entry| !current.contains(&entry.key()))
+            .map(|entry| SongRef {
+                root: entry.song.root.clone(),
+                path: entry.song.path.clone(),
+            })
+            .collect();
+        change
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+fn index_path(app: &AppHandle) -> Result<PathBuf, String> {
+    app_data_file(app, INDEX_FILE)
+}
@@ -0,0 +1,33 @@
+use serde::Serialize;
+use std::sync::Mutex;
+
+use crate::{
+    analysis_job::AnalysisJob,
+    library::{LibraryRoot, ScanOptions, Song},
+    library_index::{LibraryChange, LibraryIndex},
+};
+
+// What the background library jobs need from the app, so they don't reach into its state.
+pub trait LibraryService: Clone + Send + 'static {
+    fn library_index(&self) -> &Mutex<LibraryIndex>;
+
+    fn scan_options(&self) -> ScanOptions;
+
+    // Rescans a whole root, saving the index and queueing analysis if anything changed.
+    fn rescan(&self, root: &LibraryRoot) -> Result<LibraryChange, String>;
+
+    // Replaces the songs the app serves and re-evaluates smart playlists against them.
+    fn update_song_cache(&self, songs: Vec<Song>);
+
+    // Must be called without the library_index lock held.
+    fn save_index(&self) -> Result<(), String>;
+
+    fn request_analysis(&self);
+
+    fn analysis_job(&self) -> &AnalysisJob;
+
+    // Play counts and analysis results are written with the next batched index save.
+    fn save_track_stats(&self, index: &LibraryIndex);
+
+    fn emit_event<S: Serialize + Clone>(&self, event: &str, payload: S);
+}
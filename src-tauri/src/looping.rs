@@ -0,0 +1,397 @@
+use rodio::{source::SeekError, Source};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    f32::consts::FRAC_PI_2,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+// Length of the equal-power crossfade across the loop seam.
+const SEAM_SECONDS: f64 = 0.005;
+// Regions up to this long are kept in memory after one pass, so later
+// repeats neither seek nor decode.
+const MAX_CACHED_SECONDS: f64 = 60.0;
+const REFRESH_FRAMES: usize = 256;
+// Shorter regions would be mostly seam.
+pub const MIN_LOOP_SECONDS: f64 = 0.05;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum LoopMode {
+    Off,
+    Region { start: f64, end: f64 },
+    WholeFile,
+}
+
+// Loop settings for one deck, plus the position in the file it has reached.
+// The sink's own clock counts output samples, which stop matching the file
+// once playback wraps or is stretched.
+#[derive(Clone)]
+pub struct LoopControl {
+    mode: Arc<Mutex<LoopMode>>,
+    version: Arc<AtomicU64>,
+    position_micros: Arc<AtomicU64>,
+}
+
+impl LoopControl {
+    pub fn new(mode: LoopMode) -> LoopControl {
+        LoopControl {
+            mode: Arc::new(Mutex::new(mode)),
+            version: Arc::new(AtomicU64::new(0)),
+            position_micros: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn set(&self, mode: LoopMode) {
+        *self.mode.lock().unwrap() = mode;
+        self.version.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn mode(&self) -> LoopMode {
+        *self.mode.lock().unwrap()
+    }
+
+    pub fn position(&self) -> Duration {
+        Duration::from_micros(self.position_micros.load(Ordering::Relaxed))
+    }
+
+    fn set_position(&self, frame: u64, sample_rate: u32) {
+        let micros = (frame as f64 / sample_rate as f64 * 1_000_000.0) as u64;
+        self.position_micros.store(micros, Ordering::Relaxed);
+    }
+
+    pub fn wrap<S>(&self, source: S) -> Looped<S>
+    where
+        S: Source<Item = i16>,
+    {
+        let channels = source.channels().max(1) as usize;
+        let sample_rate = source.sample_rate().max(1);
+        self.set_position(0, sample_rate);
+        Looped {
+            inner: source,
+            control: self.clone(),
+            version: u64::MAX,
+            channels,
+            sample_rate,
+            seam: ((sample_rate as f64 * SEAM_SECONDS) as usize).max(1),
+            region: None,
+            position: 0,
+            inner_position: 0,
+            delay: VecDeque::new(),
+            output: VecDeque::new(),
+            recording: None,
+            cache: None,
+            frames: 0,
+        }
+    }
+}
+
+// The loop start in frames and its end, which for a whole-file loop is only
+// known once the decoder runs out.
+#[derive(Clone, Copy, PartialEq)]
+struct Region {
+    start: u64,
+    end: Option<u64>,
+}
+
+// Repeats a region of the source. Output trails the decoder by one seam
+// length so the tail is still at hand when the end is reached and can be
+// crossfaded with the start instead of cutting.
+pub struct Looped<S> {
+    inner: S,
+    control: LoopControl,
+    version: u64,
+    channels: usize,
+    sample_rate: u32,
+    seam: usize,
+    region: Option<Region>,
+    // File frame of the next frame to be read, from the cache or the decoder.
+    position: u64,
+    inner_position: u64,
+    delay: VecDeque<i16>,
+    output: VecDeque<i16>,
+    // Contiguous frames read from the loop start on the current pass.
+    recording: Option<Vec<i16>>,
+    cache: Option<Vec<i16>>,
+    frames: usize,
+}
+
+impl<S> Looped<S>
+where
+    S: Source<Item = i16>,
+{
+    fn frames_at(&self, seconds: f64) -> u64 {
+        (seconds.max(0.0) * self.sample_rate as f64).round() as u64
+    }
+
+    fn refresh(&mut self) {
+        let version = self.control.version.load(Ordering::Acquire);
+        if version == self.version {
+            return;
+        }
+        // Never wait on the engine thread; the change is picked up on a later frame.
+        let Ok(mode) = self.control.mode.try_lock().map(|mode| *mode) else {
+            return;
+        };
+        self.version = version;
+
+        let region = match mode {
+            LoopMode::Off => None,
+            LoopMode::Region { start, end } => Some(Region {
+                start: self.frames_at(start),
+                end: Some(self.frames_at(end)),
+            }),
+            LoopMode::WholeFile => Some(Region { start: 0, end: None }),
+        };
+        if region != self.region {
+            self.cache = None;
+            self.recording = None;
+            self.region = region;
+        }
+
+        // A cached pass leaves the decoder behind; catch it up before it is read again.
+        if self.region.is_none() && self.inner_position != self.position {
+            self.seek_inner(self.position);
+        }
+    }
+
+    fn seek_inner(&mut self, frame: u64) -> bool {
+        let position = Duration::from_secs_f64(frame as f64 / self.sample_rate as f64);
+        match self.inner.try_seek(position) {
+            Ok(()) => {
+                self.inner_position = frame;
+                true
+            }
+            Err(e) => {
+                eprintln!("Failed to seek for loop: {}", e);
+                false
+            }
+        }
+    }
+
+    fn read_frame(&mut self) -> Option<Vec<i16>> {
+        if let (Some(region), Some(cache)) = (self.region, &self.cache) {
+            let offset = self.position.checked_sub(region.start).map(|offset| offset as usize * self.channels);
+            if let Some(frame) = offset.and_then(|offset| cache.get(offset..offset + self.channels)) {
+                self.position += 1;
+                return Some(frame.to_vec());
+            }
+        }
+
+        if self.inner_position != self.position && !self.seek_inner(self.position) {
+            return None;
+        }
+        let mut frame = Vec::with_capacity(self.channels);
+        for _ in 0..self.channels {
+            frame.push(self.inner.next()?);
+        }
+
+        if let Some(region) = self.region {
+            if self.position == region.start {
+                self.recording = Some(Vec::new());
+            }
+            let max_samples = (MAX_CACHED_SECONDS * self.sample_rate as f64) as usize * self.channels;
+            if let Some(recording) = &mut self.recording {
+                if recording.len() < max_samples {
+                    recording.extend(&frame);
+                } else {
+                    self.recording = None;
+                }
+            }
+        }
+
+        self.position += 1;
+        self.inner_position += 1;
+        Some(frame)
+    }
+
+    fn wrap_around(&mut self, region: Region) -> bool {
+        let end = self.position;
+        // The end of a whole-file loop is known after the first pass.
+        self.region = Some(Region { end: Some(end), ..region });
+        if let Some(recording) = self.recording.take() {
+            if recording.len() as u64 == (end - region.start) * self.channels as u64 && self.cache.is_none() {
+                self.cache = Some(recording);
+            }
+        }
+
+        let tail: Vec<i16> = self.delay.drain(..).collect();
+        self.position = region.start;
+        let mut head = Vec::with_capacity(tail.len());
+        while head.len() < tail.len() {
+            match self.read_frame() {
+                Some(frame) => head.extend(frame),
+                None => break,
+            }
+        }
+        if head.is_empty() {
+            self.output.extend(tail);
+            return false;
+        }
+
+        // Equal-power so the seam neither dips nor bumps on uncorrelated material.
+        let frames = tail.len() / self.channels;
+        for (i, (tail, head)) in tail.chunks(self.channels).zip(head.chunks(self.channels)).enumerate() {
+            let angle = (i as f32 + 0.5) / frames as f32 * FRAC_PI_2;
+            let (fade_in, fade_out) = angle.sin_cos();
+            for (tail, head) in tail.iter().zip(head) {
+                let mixed = *tail as f32 * fade_out + *head as f32 * fade_in;
+                self.output.push_back(mixed.clamp(i16::MIN as f32, i16::MAX as f32) as i16);
+            }
+        }
+        true
+    }
+
+    fn fill(&mut self) -> bool {
+        if self.frames % REFRESH_FRAMES == 0 {
+            self.refresh();
+        }
+        self.frames += 1;
+
+        let at_end = self
+            .region
+            .and_then(|region| region.end)
+            .is_some_and(|end| self.position >= end);
+        let frame = if at_end { None } else { self.read_frame() };
+
+        match (frame, self.region) {
+            (Some(frame), _) => {
+                self.delay.extend(frame);
+                if self.delay.len() > self.seam * self.channels {
+                    self.output.extend(self.delay.drain(..self.channels));
+                }
+                true
+            }
+            (None, Some(region)) if self.position > region.start => self.wrap_around(region),
+            (None, _) => {
+                self.output.extend(self.delay.drain(..));
+                !self.output.is_empty()
+            }
+        }
+    }
+}
+
+impl<S> Iterator for Looped<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        while self.output.is_empty() {
+            if !self.fill() {
+                return None;
+            }
+        }
+        if self.output.len() % self.channels == 0 {
+            let delayed = (self.delay.len() / self.channels) as u64;
+            self.control
+                .set_position(self.position.saturating_sub(delayed), self.sample_rate);
+        }
+        self.output.pop_front()
+    }
+}
+
+impl<S> Source for Looped<S>
+where
+    S: Source<Item = i16>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        match self.region {
+            Some(_) => None,
+            None => self.inner.total_duration(),
+        }
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let frame = (pos.as_secs_f64() * self.sample_rate as f64) as u64;
+        self.inner.try_seek(pos)?;
+        self.inner_position = frame;
+        self.position = frame;
+        self.delay.clear();
+        self.output.clear();
+        self.recording = None;
+        self.control.set_position(frame, self.sample_rate);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    // A power of two keeps region boundaries exact through the Duration round trips of seeking.
+    const SAMPLE_RATE: u32 = 1024;
+    const SEAM: usize = 5;
+
+    fn ramp(frames: i16) -> SamplesBuffer<i16> {
+        SamplesBuffer::new(1, SAMPLE_RATE, (0..frames).collect::<Vec<i16>>())
+    }
+
+    fn range(start: i16, end: i16) -> Vec<i16> {
+        (start..end).collect()
+    }
+
+    #[test]
+    fn passes_through_when_off() {
+        let control = LoopControl::new(LoopMode::Off);
+        let output: Vec<i16> = control.wrap(ramp(2048)).collect();
+        assert_eq!(output, range(0, 2048));
+        assert_eq!(control.position(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn repeats_a_region_with_a_crossfaded_seam() {
+        let control = LoopControl::new(LoopMode::Region { start: 0.25, end: 0.5 });
+        let output: Vec<i16> = control.wrap(ramp(2048)).take(1100).collect();
+
+        assert_eq!(output[..512 - SEAM], range(0, 507)[..]);
+        // A hard cut would jump straight from 506 down to 261.
+        let seam = &output[512 - SEAM - 1..=512];
+        assert!(seam.windows(2).all(|pair| (pair[1] - pair[0]).abs() < 245), "seam {:?}", seam);
+        // Each repeat overlaps the previous one by the seam. The first decodes
+        // again; later ones come from the cache.
+        assert_eq!(output[512..512 + 246], range(261, 507)[..]);
+        assert_eq!(output[763..763 + 246], range(261, 507)[..]);
+
+        let position = control.position().as_secs_f64();
+        assert!((0.25..0.5).contains(&position), "position {}", position);
+    }
+
+    #[test]
+    fn whole_file_loop_wraps_at_the_end() {
+        let control = LoopControl::new(LoopMode::WholeFile);
+        let output: Vec<i16> = control.wrap(ramp(300)).take(900).collect();
+        assert_eq!(output[..300 - SEAM], range(0, 295)[..]);
+        assert_eq!(output[300..300 + 290], range(5, 295)[..]);
+        assert_eq!(output[595..595 + 290], range(5, 295)[..]);
+    }
+
+    #[test]
+    fn turning_the_loop_off_continues_past_the_region() {
+        let control = LoopControl::new(LoopMode::Region { start: 0.25, end: 0.5 });
+        let mut looped = control.wrap(ramp(2048));
+        let _: Vec<i16> = looped.by_ref().take(1100).collect();
+
+        control.set(LoopMode::Off);
+        let rest: Vec<i16> = looped.collect();
+        assert_eq!(rest[rest.len() - 1536..], range(512, 2048)[..]);
+    }
+}
@@ -0,0 +1,394 @@
+use serde::{Deserialize, Serialize};
+use std::{f64::consts::PI, io::ErrorKind, path::Path};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as DecodeError,
+};
+
+use crate::audio::probe_file;
+
+// EBU R128 measures 400 ms blocks overlapping by 75%, so energy is gathered
+// in 100 ms steps and every four consecutive steps form one block.
+const STEP_SECONDS: f64 = 0.1;
+const STEPS_PER_BLOCK: usize = 4;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+// True peak is estimated by 4x oversampling with a windowed-sinc interpolator.
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    pub integrated_lufs: f64,
+    pub true_peak_dbtp: f64,
+}
+
+impl Loudness {
+    pub fn true_peak(&self) -> f64 {
+        db_to_linear(self.true_peak_dbtp)
+    }
+}
+
+pub fn db_to_linear(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+// Duration-weighted energy mean of the tracks, used as the album's loudness.
+pub fn combine(tracks: &[(Loudness, f64)]) -> Option<Loudness> {
+    let total: f64 = tracks.iter().map(|(_, duration)| duration).sum();
+    if tracks.is_empty() || total <= 0.0 {
+        return None;
+    }
+
+    let energy = tracks
+        .iter()
+        .map(|(loudness, duration)| duration * 10f64.powf(loudness.integrated_lufs / 10.0))
+        .sum::<f64>()
+        / total;
+    let true_peak_dbtp = tracks
+        .iter()
+        .map(|(loudness, _)| loudness.true_peak_dbtp)
+        .fold(f64::NEG_INFINITY, f64::max);
+    Some(Loudness {
+        integrated_lufs: 10.0 * energy.log10(),
+        true_peak_dbtp,
+    })
+}
+
+// Decodes with symphonia rather than through `open_source`, which rodio narrows
+// to 16 bits, so quiet passages and float sources are measured at full precision.
+pub fn measure_file(path: &Path) -> Result<Loudness, String> {
+    let mut probed = probe_file(path)?;
+    let track = probed
+        .format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(format!("No audio track in {}", path.display()))?;
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+    let sample_rate = params.sample_rate.ok_or("Unknown sample rate.")?;
+    let channels = params.channels.map(|channels| channels.count()).ok_or("Unknown channel layout.")?;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&params, &DecoderOptions::default())
+        .map_err(|e| format!("Error decoding audio: {}", e))?;
+    println!("Measuring loudness for {}", path.display());
+
+    let mut meter = LoudnessMeter::new(channels, sample_rate as f64);
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match probed.format.next_packet() {
+            Ok(packet) => packet,
+            Err(DecodeError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(format!("Error reading {}: {}", path.display(), e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(DecodeError::DecodeError(e)) => {
+                eprintln!("Skipping corrupt packet in {}: {}", path.display(), e);
+                continue;
+            }
+            Err(e) => return Err(format!("Error decoding {}: {}", path.display(), e)),
+        };
+        let buffer = buffer.get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, *decoded.spec()));
+        if buffer.capacity() < decoded.capacity() * channels {
+            *buffer = SampleBuffer::new(decoded.capacity() as u64, *decoded.spec());
+        }
+        buffer.copy_interleaved_ref(decoded);
+        meter.push(buffer.samples());
+    }
+
+    meter
+        .finish()
+        .ok_or(format!("{} is too short or too quiet to measure loudness.", path.display()))
+}
+
+// Integrated loudness and true peak of interleaved samples, fed in any number of pieces.
+struct LoudnessMeter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<KWeighting>,
+    peaks: Vec<TruePeak>,
+    step_frames: usize,
+    steps: Vec<f64>,
+    step_energy: f64,
+    frames_in_step: usize,
+    channel: usize,
+}
+
+impl LoudnessMeter {
+    fn new(channels: usize, sample_rate: f64) -> LoudnessMeter {
+        let channels = channels.max(1);
+        LoudnessMeter {
+            channels,
+            weights: (0..channels).map(|channel| channel_weight(channel, channels)).collect(),
+            filters: (0..channels).map(|_| KWeighting::new(sample_rate)).collect(),
+            peaks: (0..channels).map(|_| TruePeak::new()).collect(),
+            step_frames: ((sample_rate * STEP_SECONDS).round() as usize).max(1),
+            steps: Vec::new(),
+            step_energy: 0.0,
+            frames_in_step: 0,
+            channel: 0,
+        }
+    }
+
+    fn push(&mut self, samples: &[f32]) {
+        for &sample in samples {
+            let value = sample as f64;
+            let channel = self.channel;
+            self.peaks[channel].push(value);
+            let filtered = self.filters[channel].process(value);
+            self.step_energy += self.weights[channel] * filtered * filtered;
+
+            self.channel += 1;
+            if self.channel < self.channels {
+                continue;
+            }
+            self.channel = 0;
+            self.frames_in_step += 1;
+            if self.frames_in_step == self.step_frames {
+                self.steps.push(self.step_energy / self.step_frames as f64);
+                self.step_energy = 0.0;
+                self.frames_in_step = 0;
+            }
+        }
+    }
+
+    fn finish(self) -> Option<Loudness> {
+        let blocks: Vec<f64> = self
+            .steps
+            .windows(STEPS_PER_BLOCK)
+            .map(|window| window.iter().sum::<f64>() / STEPS_PER_BLOCK as f64)
+            .collect();
+        let integrated_lufs = gated_loudness(&blocks)?;
+        let peak = self.peaks.iter().map(|peak| peak.max).fold(0.0, f64::max);
+
+        Some(Loudness {
+            integrated_lufs,
+            true_peak_dbtp: 20.0 * peak.max(1e-9).log10(),
+        })
+    }
+}
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn gated_loudness(blocks: &[f64]) -> Option<f64> {
+    let absolute: Vec<f64> = blocks
+        .iter()
+        .copied()
+        .filter(|&energy| energy > 0.0 && energy_to_lufs(energy) > ABSOLUTE_GATE_LUFS)
+        .collect();
+    if absolute.is_empty() {
+        return None;
+    }
+
+    let mean = absolute.iter().sum::<f64>() / absolute.len() as f64;
+    let threshold = energy_to_lufs(mean) + RELATIVE_GATE_LU;
+    let relative: Vec<f64> = absolute
+        .into_iter()
+        .filter(|&energy| energy_to_lufs(energy) > threshold)
+        .collect();
+    if relative.is_empty() {
+        return None;
+    }
+    Some(energy_to_lufs(relative.iter().sum::<f64>() / relative.len() as f64))
+}
+
+// Surround channels count 1.41x and the LFE channel of a 5.1 layout is ignored.
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    match (channels, channel) {
+        (6, 3) => 0.0,
+        (6, 4) | (6, 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[1] * self.y[0]
+            - self.a[2] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+}
+
+// The two-stage K-weighting filter from ITU-R BS.1770, derived for any sample rate.
+struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    fn new(sample_rate: f64) -> KWeighting {
+        let f0 = 1681.974450955533;
+        let gain = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / sample_rate).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad {
+            b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+            a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        };
+
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad {
+            b: [1.0, -2.0, 1.0],
+            a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        };
+
+        KWeighting { shelf, high_pass }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        self.high_pass.process(self.shelf.process(input))
+    }
+}
+
+struct TruePeak {
+    phases: Vec<[f64; TAPS_PER_PHASE]>,
+    history: [f64; TAPS_PER_PHASE],
+    max: f64,
+}
+
+impl TruePeak {
+    fn new() -> TruePeak {
+        let length = OVERSAMPLING * TAPS_PER_PHASE;
+        let centre = (length - 1) as f64 / 2.0;
+        let mut phases = vec![[0.0; TAPS_PER_PHASE]; OVERSAMPLING];
+        for n in 0..length {
+            let t = (n as f64 - centre) / OVERSAMPLING as f64;
+            let sinc = if t.abs() < 1e-12 { 1.0 } else { (PI * t).sin() / (PI * t) };
+            let window = 0.5 - 0.5 * (2.0 * PI * n as f64 / (length - 1) as f64).cos();
+            phases[n % OVERSAMPLING][n / OVERSAMPLING] = sinc * window;
+        }
+
+        TruePeak {
+            phases,
+            history: [0.0; TAPS_PER_PHASE],
+            max: 0.0,
+        }
+    }
+
+    fn push(&mut self, sample: f64) {
+        self.history.rotate_right(1);
+        self.history[0] = sample;
+        self.max = self.max.max(sample.abs());
+        for phase in &self.phases {
+            let value: f64 = phase.iter().zip(&self.history).map(|(tap, x)| tap * x).sum();
+            self.max = self.max.max(value.abs());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f64 = 48_000.0;
+
+    // A stereo 1 kHz sine in (seconds, dBFS) segments. The EBU Tech 3341 cases below
+    // keep their levels and proportions but are a fifth of the length, since gating
+    // only depends on the share of blocks at each level.
+    fn sine(segments: &[(f64, f64)]) -> Vec<f32> {
+        let mut samples = Vec::new();
+        let mut n = 0;
+        for &(seconds, level) in segments {
+            let amplitude = db_to_linear(level);
+            for _ in 0..(seconds * RATE).round() as usize {
+                let value = (amplitude * (2.0 * PI * 1000.0 * n as f64 / RATE).sin()) as f32;
+                samples.extend([value, value]);
+                n += 1;
+            }
+        }
+        samples
+    }
+
+    fn measure(samples: &[f32]) -> Option<Loudness> {
+        let mut meter = LoudnessMeter::new(2, RATE);
+        // Uneven pieces, the way decoded packets arrive.
+        for chunk in samples.chunks(1153 * 2 + 1) {
+            meter.push(chunk);
+        }
+        meter.finish()
+    }
+
+    fn assert_lufs(segments: &[(f64, f64)], expected: f64) {
+        let loudness = measure(&sine(segments)).unwrap();
+        assert!(
+            (loudness.integrated_lufs - expected).abs() <= 0.1,
+            "expected {} LUFS, measured {:.3}",
+            expected,
+            loudness.integrated_lufs
+        );
+    }
+
+    #[test]
+    fn sine_at_reference_level_reads_minus_23() {
+        assert_lufs(&[(4.0, -23.0)], -23.0);
+        assert_lufs(&[(4.0, -33.0)], -33.0);
+    }
+
+    #[test]
+    fn relative_gate_ignores_quiet_passages() {
+        assert_lufs(&[(2.0, -36.0), (12.0, -23.0), (2.0, -36.0)], -23.0);
+    }
+
+    #[test]
+    fn absolute_gate_ignores_near_silence() {
+        assert_lufs(&[(2.0, -72.0), (2.0, -36.0), (12.0, -23.0), (2.0, -36.0), (2.0, -72.0)], -23.0);
+    }
+
+    #[test]
+    fn louder_and_quieter_passages_average_out() {
+        assert_lufs(&[(4.0, -26.0), (4.02, -20.0), (4.0, -26.0)], -23.0);
+    }
+
+    #[test]
+    fn silence_and_short_input_cannot_be_measured() {
+        assert!(measure(&sine(&[(2.0, -80.0)])).is_none());
+        assert!(measure(&sine(&[(0.3, -23.0)])).is_none());
+    }
+
+    #[test]
+    fn true_peak_of_a_sine_is_its_amplitude() {
+        let loudness = measure(&sine(&[(2.0, -6.0)])).unwrap();
+        assert!((loudness.true_peak_dbtp + 6.0).abs() < 0.1, "measured {} dBTP", loudness.true_peak_dbtp);
+    }
+
+    #[test]
+    fn album_loudness_is_duration_weighted() {
+        let loud = Loudness { integrated_lufs: -10.0, true_peak_dbtp: -1.0 };
+        let quiet = Loudness { integrated_lufs: -20.0, true_peak_dbtp: -3.0 };
+        let album = combine(&[(loud, 1.0), (quiet, 9.0)]).unwrap();
+        // (1 s at 0.1 + 9 s at 0.01) / 10 s in linear energy.
+        assert!((album.integrated_lufs - 10.0 * 0.019f64.log10()).abs() < 1e-9);
+        assert_eq!(album.true_peak_dbtp, -1.0);
+        assert!(combine(&[]).is_none());
+    }
+}
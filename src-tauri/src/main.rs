@@ -1,29 +1,94 @@
+mod analysis;
+mod analysis_job;
+mod audio;
+mod convert;
 mod db;
+mod effects;
+mod flac;
+mod history;
+mod library;
+mod library_index;
+mod library_service;
+mod looping;
+mod loudness;
+mod meter;
+mod metronome;
+mod playlists;
+mod queue;
+mod search;
+mod settings;
+mod smart_playlists;
+mod stretch;
+#[cfg(test)]
+mod test_support;
+mod vorbis;
+mod watcher;
+mod waveform;
 use db::{ add_song, download_preset, fetch_presets, 
     fetch_friends, fetch_samples, login_user, 
     register_user, upload_preset, remove_sample, 
-    Preset, Sample, 
+    share_smart_playlist, fetch_shared_smart_playlists,
+    Preset, Sample, SharedSmartPlaylist,
+};
+use analysis::analyze_file;
+use analysis_job::{request_analysis, AnalysisJob};
+use audio::{
+    probe_duration, AudioEngine, EngineCommand, EngineEvent, Normalization, NormalizationMode,
+    OutputDevice, PlaybackError, PlaybackPosition, PlaybackStatus, ReplayGain, Track,
+};
+use convert::{check_conversion, convert_file, next_job_id, ConvertFinished, ConvertOptions, OutputFormat};
+use effects::{EffectsPreset, EffectsPresetStore, EffectsSettings, EqBand};
+use history::{export_history_file, HistoryEntry, HistoryFormat};
+use library::{scan_directory, unix_now, LibraryFilter, LibraryRoot, ScanOptions, ScanProgress, Song, SongRef};
+use library_index::{LibraryChange, LibraryIndex};
+use library_service::LibraryService;
+use looping::MIN_LOOP_SECONDS;
+use loudness::{measure_file, Loudness};
+use meter::band_frequencies;
+use metronome::MetronomeSettings;
+use playlists::{
+    export_playlist_file, import_playlist_file, Playlist, PlaylistFormat, PlaylistImport, PlaylistStore,
+};
+use queue::{PlayQueue, QueueSnapshot, RepeatMode};
+use stretch::TempoSettings;
+use search::{search_songs, LibraryQuery, SearchResults};
+use settings::{Settings, MAX_CROSSFADE_SECONDS, MAX_PREAMP_DB};
+use smart_playlists::{SmartPlaylist, SmartPlaylistStore};
+use watcher::{watch_library, LibraryWatcher};
+use waveform::{load_waveform, WaveformData};
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, RunEvent, State};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{atomic::{AtomicBool, Ordering}, mpsc::Receiver, Arc, Mutex},
+    thread,
+    time::Duration,
 };
-use rodio::{Decoder, OutputStream, Sink};
 
-use serde::{Serialize, Deserialize};
-use tauri::State;
-use std::{fs::{self, File}, io::BufReader, path::Path, sync::{Arc, Mutex}, thread::{self}};
-
-#[derive(Serialize, Deserialize, Clone)]
-struct Song {
-    title: String,
-}
+// Play counts, skips and ratings change often, so they are written in batches.
+const INDEX_SAVE_INTERVAL: Duration = Duration::from_secs(5);
 
 pub struct AppState {
-    current_song: Mutex<Option<Arc<Sink>>>,
-    current_song_title: Mutex<Option<String>>,
+    engine: AudioEngine,
+    current_song: Mutex<Option<Song>>,
+    queue: Mutex<PlayQueue>,
     sample_cache: Mutex<Vec<Sample>>,
     song_cache: Mutex<Vec<Song>>,
+    library_index: Mutex<LibraryIndex>,
+    index_dirty: AtomicBool,
+    // Held while writing so an older snapshot never overwrites a newer one.
+    index_writer: Mutex<()>,
+    analysis: AnalysisJob,
+    library_watchers: Mutex<HashMap<String, LibraryWatcher>>,
+    playlists: Mutex<PlaylistStore>,
+    smart_playlists: Mutex<SmartPlaylistStore>,
+    effect_presets: Mutex<EffectsPresetStore>,
     preset_cache: Mutex<Vec<Preset>>,
-    directory_path: Mutex<Option<String>>,
     logged_in_user: Mutex<Option<String>>,
     friends_cache: Mutex<Vec<String>>,
+    settings: Mutex<Settings>,
 }
 
 #[tauri::command]
@@ -191,6 +256,9 @@ fn get_cached_presets(state: State<'_, Arc<AppState>>) -> Vec<Preset> {
 async fn upload_sample_metadata(
     title: String,
     url: String,
+    bpm: Option<f32>,
+    key: Option<String>,
+    file_path: Option<String>,
     state: tauri::State<'_, std::sync::Arc<AppState>>,
 ) -> Result<(), String> {
     let logged_in_user = {
@@ -205,10 +273,29 @@ async fn upload_sample_metadata(
         }
     };
 
+    let bpm = validate_bpm(bpm)?;
+    let key = normalize_key(key);
+
+    // Values given by the user win; anything missing is detected from the local file.
+    let detected = match file_path {
+        Some(file_path) if bpm.is_none() || key.is_none() => {
+            tauri::async_runtime::spawn_blocking(move || analyze_file(Path::new(&file_path)))
+                .await
+                .map_err(|e| format!("Sample analysis failed: {}", e))?
+                .unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    Default::default()
+                })
+        }
+        _ => Default::default(),
+    };
+
     let sample = Sample {
         title: title.clone(),
         url,
         uploaded_by: username.clone(),
+        bpm: bpm.or(detected.bpm),
+        key: key.or(detected.key),
     };
 
     add_song(sample.clone())
@@ -247,138 +334,930 @@ async fn download_preset_file(preset_name: String, output_path: String) -> Resul
 }
 
 #[tauri::command]
-fn play_song(title: String, state: State<'_, Arc<AppState>>) {
-    let dir_path = state.directory_path.lock().unwrap();
-    let Some(directory) = &*dir_path else {
-        eprintln!("No directory set. Cannot play song.");
-        return;
-    };
+fn play_song(root: String, path: String, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    let song = find_song(&state, &root, &path)?;
+    play_from_queue(&state, |queue| {
+        queue.jump_to(song);
+        queue.current().cloned()
+    })?;
+    Ok(())
+}
+
+fn find_song(state: &AppState, root: &str, path: &str) -> Result<Song, String> {
+    let song_cache = state.song_cache.lock().unwrap();
+    song_cache
+        .iter()
+        .find(|song| song.root == root && song.path == path)
+        .cloned()
+        .ok_or(format!("Song '{}' is not in library '{}'.", path, root))
+}
 
-    let path = format!("{}/{}", directory, title);
-    let state = state.inner().clone();
+// The queue only takes the change once the engine has started the track, so a track
+// that cannot be played leaves it pointing at what is actually playing.
+fn play_from_queue(
+    state: &AppState,
+    change: impl FnOnce(&mut PlayQueue) -> Option<Song>,
+) -> Result<Option<Song>, String> {
+    let mut queue = state.queue.lock().unwrap();
+    let mut changed = queue.clone();
+    let song = change(&mut changed);
+    if let Some(song) = &song {
+        start_playback(song, state)?;
+    }
+    *queue = changed;
+    drop(queue);
+
+    refresh_preload(state);
+    Ok(song)
+}
+
+fn start_playback(song: &Song, state: &AppState) -> Result<(), String> {
+    let track = resolve_track(state, song)
+        .ok_or(format!("Library root '{}' not found. Cannot play '{}'.", song.root, song.title))?;
+
+    state.engine.play(track)
+}
+
+fn resolve_track(state: &AppState, song: &Song) -> Option<Track> {
+    let index = state.library_index.lock().unwrap();
+    let root = index.root(&song.root)?;
+    Some(Track {
+        song: SongRef {
+            root: song.root.clone(),
+            path: song.path.clone(),
+        },
+        title: song.title.clone(),
+        path: PathBuf::from(&root.path).join(&song.path),
+        replay_gain: song.loudness.map(|track| ReplayGain {
+            track,
+            album: index.album_loudness(song),
+        }),
+        bpm: song.bpm,
+    })
+}
+
+fn refresh_preload(state: &AppState) {
+    let upcoming = state.queue.lock().unwrap().upcoming().cloned();
+    let track = upcoming.and_then(|song| resolve_track(state, &song));
+    state.engine.send(EngineCommand::Preload(track));
+}
 
+// Plays and skips are credited to the song the engine reports, since the queue may
+// already have moved on by the time its event is handled.
+fn playing_song(state: &AppState, status: &PlaybackStatus) -> Option<Song> {
+    let song = status.song.as_ref()?;
+    find_song(state, &song.root, &song.path).ok()
+}
+
+fn spawn_engine_listener(app: AppHandle, events: Receiver<EngineEvent>) {
     thread::spawn(move || {
-        let file = match File::open(&path) {
-            Ok(file) => file,
-            Err(e) => {
-                eprintln!("Error opening file: {}: {}", path, e);
-                return;
+        let state = app.state::<Arc<AppState>>().inner().clone();
+        for event in events {
+            match event {
+                EngineEvent::TrackStarted(status) => {
+                    // A track that is still current when the next one starts was skipped.
+                    let song = playing_song(&state, &status);
+                    let previous = std::mem::replace(&mut *state.current_song.lock().unwrap(), song.clone());
+                    if let Some(previous) = previous {
+                        record_skip(&app, &state, &previous);
+                    }
+                    if let Some(song) = &song {
+                        record_play(&app, &state, song);
+                    }
+                    emit_event(&app, "track-started", status);
+                }
+                EngineEvent::TrackPaused(status) => emit_event(&app, "track-paused", status),
+                EngineEvent::TrackResumed(status) => emit_event(&app, "track-resumed", status),
+                EngineEvent::TrackEnded(status) => {
+                    println!("Finished playing: {}", status.title.as_deref().unwrap_or_default());
+                    *state.current_song.lock().unwrap() = None;
+                    emit_event(&app, "track-ended", status);
+
+                    match play_from_queue(&state, PlayQueue::advance) {
+                        Ok(Some(next)) => println!("Advanced queue to: {}", next.title),
+                        Ok(None) => {}
+                        Err(message) => {
+                            eprintln!("{}", message);
+                            let status = state.engine.status();
+                            emit_event(&app, "playback-error", PlaybackError { message, status });
+                        }
+                    }
+                }
+                EngineEvent::Transitioned { ended, started } => {
+                    state.queue.lock().unwrap().advance();
+                    let song = playing_song(&state, &started);
+                    if let Some(song) = &song {
+                        record_play(&app, &state, song);
+                    }
+                    *state.current_song.lock().unwrap() = song;
+                    refresh_preload(&state);
+                    emit_event(&app, "track-ended", ended);
+                    emit_event(&app, "track-started", started);
+                }
+                EngineEvent::Stopped(status) => {
+                    *state.current_song.lock().unwrap() = None;
+                    emit_event(&app, "playback-stopped", status);
+                }
+                EngineEvent::VolumeChanged(status) => emit_event(&app, "volume-changed", status),
+                EngineEvent::Position(position) => emit_event(&app, "playback-position", position),
+                EngineEvent::LoopChanged(status) => emit_event(&app, "loop-changed", status),
+                EngineEvent::Meter(frame) => emit_event(&app, "playback-meter", frame),
+                EngineEvent::OutputDeviceChanged(device) => emit_event(&app, "output-device-changed", device),
+                EngineEvent::Error(error) => emit_event(&app, "playback-error", error),
             }
-        };
+        }
+    });
+}
+
+fn emit_event<S: Serialize + Clone>(app: &AppHandle, event: &str, payload: S) {
+    if let Err(e) = app.emit(event, payload) {
+        eprintln!("Failed to emit '{}': {}", event, e);
+    }
+}
 
-        let (_stream, stream_handle) = match OutputStream::try_default() {
-            Ok(output) => output,
-            Err(e) => {
-                eprintln!("Error creating output stream: {}", e);
-                return;
+#[tauri::command]
+fn enqueue(songs: Vec<SongRef>, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    let songs = songs
+        .iter()
+        .map(|song| find_song(&state, &song.root, &song.path))
+        .collect::<Result<Vec<Song>, String>>()?;
+
+    println!("Enqueued {} songs", songs.len());
+    state.queue.lock().unwrap().enqueue(songs);
+    refresh_preload(&state);
+    Ok(())
+}
+
+#[tauri::command]
+fn play_folder_from(root: String, path: String, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    let songs: Vec<Song> = state
+        .song_cache
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|song| song.root == root)
+        .cloned()
+        .collect();
+    let start = songs
+        .iter()
+        .position(|song| song.path == path)
+        .ok_or(format!("Song '{}' is not in library '{}'.", path, root))?;
+
+    play_from_queue(&state, |queue| queue.replace(songs, start))?;
+    Ok(())
+}
+
+#[tauri::command]
+fn play_next(state: State<'_, Arc<AppState>>) -> Result<Option<String>, String> {
+    let next = play_from_queue(&state, PlayQueue::next)?;
+    Ok(next.map(|song| song.title))
+}
+
+#[tauri::command]
+fn play_previous(state: State<'_, Arc<AppState>>) -> Result<Option<String>, String> {
+    let previous = play_from_queue(&state, PlayQueue::previous)?;
+    Ok(previous.map(|song| song.title))
+}
+
+#[tauri::command]
+fn clear_queue(state: State<'_, Arc<AppState>>) {
+    state.queue.lock().unwrap().clear();
+    refresh_preload(&state);
+    println!("Queue cleared");
+}
+
+#[tauri::command]
+fn set_shuffle(enabled: bool, state: State<'_, Arc<AppState>>) {
+    state.queue.lock().unwrap().set_shuffle(enabled);
+    refresh_preload(&state);
+    println!("Shuffle set to: {}", enabled);
+}
+
+#[tauri::command]
+fn set_repeat_mode(mode: RepeatMode, state: State<'_, Arc<AppState>>) {
+    state.queue.lock().unwrap().set_repeat(mode);
+    refresh_preload(&state);
+    println!("Repeat mode set to: {:?}", mode);
+}
+
+#[tauri::command]
+fn get_queue(state: State<'_, Arc<AppState>>) -> QueueSnapshot {
+    state.queue.lock().unwrap().snapshot()
+}
+
+#[tauri::command]
+fn list_playlists(state: State<'_, Arc<AppState>>) -> Vec<Playlist> {
+    state.playlists.lock().unwrap().playlists.clone()
+}
+
+#[tauri::command]
+fn create_playlist(name: String, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<Playlist, String> {
+    edit_playlists(&app, &state, |playlists| playlists.create(&name, Vec::new()).cloned())
+}
+
+#[tauri::command]
+fn rename_playlist(name: String, new_name: String, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    edit_playlists(&app, &state, |playlists| playlists.rename(&name, &new_name))
+}
+
+#[tauri::command]
+fn delete_playlist(name: String, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    edit_playlists(&app, &state, |playlists| playlists.delete(&name))
+}
+
+#[tauri::command]
+fn add_to_playlist(
+    name: String,
+    tracks: Vec<SongRef>,
+    position: Option<usize>,
+    app: AppHandle,
+    state: State<'_, Arc<AppState>>,
+) -> Result<(), String> {
+    for track in &tracks {
+        find_song(&state, &track.root, &track.path)?;
+    }
+    edit_playlists(&app, &state, |playlists| playlists.get_mut(&name)?.insert(tracks, position))
+}
+
+#[tauri::command]
+fn remove_from_playlist(name: String, index: usize, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    edit_playlists(&app, &state, |playlists| playlists.get_mut(&name)?.remove(index).map(|_| ()))
+}
+
+#[tauri::command]
+fn move_playlist_track(
+    name: String,
+    from: usize,
+    to: usize,
+    app: AppHandle,
+    state: State<'_, Arc<AppState>>,
+) -> Result<(), String> {
+    edit_playlists(&app, &state, |playlists| playlists.get_mut(&name)?.move_track(from, to))
+}
+
+#[tauri::command]
+fn import_playlist(
+    file_path: String,
+    name: Option<String>,
+    app: AppHandle,
+    state: State<'_, Arc<AppState>>,
+) -> Result<PlaylistImport, String> {
+    let file = PathBuf::from(&file_path);
+    let roots = state.library_index.lock().unwrap().roots.clone();
+    let songs = state.song_cache.lock().unwrap().clone();
+    let (tracks, missing) = import_playlist_file(&file, &roots, &songs)?;
+    if !missing.is_empty() {
+        println!("{} entries in {} are not in the library", missing.len(), file_path);
+    }
+
+    let playlist = edit_playlists(&app, &state, |playlists| {
+        let name = match name {
+            Some(name) => name,
+            None => {
+                let stem = file.file_stem().and_then(|stem| stem.to_str()).unwrap_or("Imported");
+                playlists.unique_name(stem)
             }
         };
+        playlists.create(&name, tracks).cloned()
+    })?;
+    println!("Imported playlist '{}' with {} tracks", playlist.name, playlist.tracks.len());
+    Ok(PlaylistImport { playlist, missing })
+}
 
-        let sink = match Sink::try_new(&stream_handle) {
-            Ok(sink) => Arc::new(sink),
-            Err(e) => {
-                eprintln!("Error creating sink: {}", e);
-                return;
-            }
+#[tauri::command]
+fn export_playlist(
+    name: String,
+    file_path: String,
+    format: Option<PlaylistFormat>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<(), String> {
+    let file = PathBuf::from(&file_path);
+    let format = match format {
+        Some(format) => format,
+        None => PlaylistFormat::from_path(&file)?,
+    };
+
+    let tracks = state.playlists.lock().unwrap().get(&name)?.tracks.clone();
+    let mut entries = Vec::new();
+    for track in tracks {
+        let Ok(song) = find_song(&state, &track.root, &track.path) else {
+            eprintln!("Skipping '{}', it is no longer in the library.", track.path);
+            continue;
         };
+        if let Some(resolved) = resolve_track(&state, &song) {
+            entries.push((resolved.path, song));
+        }
+    }
+    export_playlist_file(&file, format, &entries)
+}
+
+#[tauri::command]
+fn play_playlist(name: String, index: usize, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    let tracks = state.playlists.lock().unwrap().get(&name)?.tracks.clone();
+    if index >= tracks.len() {
+        return Err(format!("Track {} is out of range for playlist '{}'.", index, name));
+    }
 
-        match Decoder::new(BufReader::new(file)) {
-            Ok(source) => sink.append(source),
-            Err(e) => {
-                eprintln!("Error decoding audio: {}", e);
-                return;
+    // Tracks that have left the library are skipped rather than failing the whole playlist.
+    let mut songs = Vec::new();
+    let mut start = None;
+    for (i, track) in tracks.iter().enumerate() {
+        match find_song(&state, &track.root, &track.path) {
+            Ok(song) => {
+                if i == index {
+                    start = Some(songs.len());
+                }
+                songs.push(song);
             }
+            Err(e) => eprintln!("{}", e),
         }
+    }
+    let start = start.ok_or(format!("Track {} of playlist '{}' is no longer in the library.", index, name))?;
 
-        {
-            let mut current_song = state.current_song.lock().unwrap();
-            if let Some(ref current) = *current_song {
-                current.pause();
-            }
+    play_from_queue(&state, |queue| queue.replace(songs, start))?;
+    Ok(())
+}
 
-            *current_song = Some(sink.clone());
+fn edit_playlists<T>(
+    app: &AppHandle,
+    state: &AppState,
+    edit: impl FnOnce(&mut PlaylistStore) -> Result<T, String>,
+) -> Result<T, String> {
+    let mut playlists = state.playlists.lock().unwrap();
+    let result = edit(&mut playlists)?;
+    playlists.save(app)?;
+    Ok(result)
+}
 
-            let mut title_guard = state.current_song_title.lock().unwrap();
-            *title_guard = Some(title.clone());
-        }
+#[tauri::command]
+fn pause_song(state: State<'_, Arc<AppState>>) {
+    state.engine.send(EngineCommand::Pause);
+    println!("Song paused");
+}
 
-        sink.set_volume(1.0);
-        sink.sleep_until_end();
-        println!("Now playing: {}", title);
-    });
+#[tauri::command]
+fn unpause_song(state: State<'_, Arc<AppState>>) {
+    state.engine.send(EngineCommand::Resume);
+    println!("Song unpaused");
 }
 
+#[tauri::command]
+fn stop_song(state: State<'_, Arc<AppState>>) {
+    state.engine.send(EngineCommand::Stop);
+    println!("Song stopped");
+}
 
 #[tauri::command]
-fn pause_song(state: State<'_, Arc<AppState>>) {
-    let mut current_song = state.current_song.lock().unwrap();
-    if let Some(ref sink) = *current_song {
-        sink.pause();
-        println!("Song paused");
-    } else {
-        println!("No active song to pause");
+fn set_volume(vol: f32, state: State<'_, Arc<AppState>>) {
+    state.engine.send(EngineCommand::SetVolume(vol));
+    println!("Volume set to: {:.2}", vol);
+}
+
+#[tauri::command]
+fn set_crossfade(seconds: f32, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    if !(0.0..=MAX_CROSSFADE_SECONDS).contains(&seconds) {
+        return Err(format!("Crossfade must be between 0 and {} seconds.", MAX_CROSSFADE_SECONDS));
     }
+
+    let settings = {
+        let mut settings = state.settings.lock().unwrap();
+        settings.crossfade_seconds = seconds;
+        settings.clone()
+    };
+    settings.save(&app)?;
+
+    state.engine.send(EngineCommand::SetCrossfade(Duration::from_secs_f32(seconds)));
+    Ok(())
 }
 
 #[tauri::command]
-fn unpause_song(state: State<'_, Arc<AppState>>) {
-    let mut current_song = state.current_song.lock().unwrap();
-    if let Some(ref sink) = *current_song {
-        sink.play();
-        println!("Song unpaused");
-    } else {
-        println!("No active song to pause");
+fn get_crossfade(state: State<'_, Arc<AppState>>) -> f32 {
+    state.settings.lock().unwrap().crossfade_seconds
+}
+
+#[tauri::command]
+fn set_normalization(
+    mode: NormalizationMode,
+    preamp_db: f32,
+    prevent_clipping: bool,
+    app: AppHandle,
+    state: State<'_, Arc<AppState>>,
+) -> Result<(), String> {
+    if !(-MAX_PREAMP_DB..=MAX_PREAMP_DB).contains(&preamp_db) {
+        return Err(format!("Preamp must be between -{0} and {0} dB.", MAX_PREAMP_DB));
     }
+
+    let normalization = Normalization {
+        mode,
+        preamp_db,
+        prevent_clipping,
+    };
+    let settings = {
+        let mut settings = state.settings.lock().unwrap();
+        settings.normalization = normalization;
+        settings.clone()
+    };
+    settings.save(&app)?;
+
+    state.engine.send(EngineCommand::SetNormalization(normalization));
+    Ok(())
 }
 
 #[tauri::command]
-fn set_volume(vol: f32, state: State<'_, Arc<AppState>>) {
-    let mut current_song = state.current_song.lock().unwrap();
-    if let Some(ref sink) = *current_song {
-        sink.set_volume(vol);
-        println!("Volume set to: {:.2}", vol);
-    } else {
-        println!("No active song to set volume");
-    }
-}
-
-fn load_songs_from_directory(directory: &str) -> Vec<Song> {
-    let path = Path::new(directory);
-    let mut music_files = Vec::new();
-
-    let supported_extensions = vec!["mp3", "wav", "flac"];
-
-    if let Ok(entries) = fs::read_dir(path) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if path.is_file() {
-                    if let Some(ext) = path.extension() {
-                        if supported_extensions.iter().any(|&e| e == ext.to_str().unwrap_or("")) {
-                            if let Some(file_name) = path.file_name() {
-                                if let Some(file_name_str) = file_name.to_str() {
-                                    music_files.push(Song {
-                                        title: file_name_str.to_string(),
-                                    });
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+fn get_normalization(state: State<'_, Arc<AppState>>) -> Normalization {
+    state.settings.lock().unwrap().normalization
+}
+
+#[tauri::command]
+async fn measure_track_loudness(
+    root: String,
+    path: String,
+    app: AppHandle,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Loudness, String> {
+    let song = find_song(&state, &root, &path)?;
+    let track = resolve_track(&state, &song).ok_or(format!("Library root '{}' not found.", root))?;
+
+    let loudness = tauri::async_runtime::spawn_blocking(move || measure_file(&track.path))
+        .await
+        .map_err(|e| format!("Loudness measurement failed: {}", e))??;
+
+    let mut index = state.library_index.lock().unwrap();
+    let song = index
+        .song_mut(&root, &path)
+        .ok_or(format!("Song '{}' is not in library '{}'.", path, root))?;
+    song.loudness = Some(loudness);
+    save_track_stats(&app, &state, &index);
+    Ok(loudness)
+}
+
+// Applies an edit to the live effects chain and persists it with the settings.
+fn edit_effects(
+    app: &AppHandle,
+    state: &AppState,
+    edit: impl FnOnce(&mut EffectsSettings) -> Result<(), String>,
+) -> Result<EffectsSettings, String> {
+    let settings = {
+        let mut settings = state.settings.lock().unwrap();
+        let mut effects = settings.effects.clone();
+        edit(&mut effects)?;
+        effects.validate()?;
+        settings.effects = effects;
+        settings.clone()
+    };
+    settings.save(app)?;
+
+    state.engine.send(EngineCommand::SetEffects(settings.effects.clone()));
+    Ok(settings.effects)
+}
+
+#[tauri::command]
+fn get_effects(state: State<'_, Arc<AppState>>) -> EffectsSettings {
+    state.settings.lock().unwrap().effects.clone()
+}
+
+#[tauri::command]
+fn set_effects(effects: EffectsSettings, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<EffectsSettings, String> {
+    edit_effects(&app, &state, |current| {
+        *current = effects;
+        Ok(())
+    })
+}
+
+#[tauri::command]
+fn set_effects_enabled(enabled: bool, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<EffectsSettings, String> {
+    edit_effects(&app, &state, |effects| {
+        effects.enabled = enabled;
+        Ok(())
+    })
+}
+
+#[tauri::command]
+fn set_eq_band(index: usize, band: EqBand, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<EffectsSettings, String> {
+    edit_effects(&app, &state, |effects| {
+        let slot = effects
+            .bands
+            .get_mut(index)
+            .ok_or(format!("Equalizer band {} does not exist.", index))?;
+        *slot = band;
+        Ok(())
+    })
+}
+
+#[tauri::command]
+fn list_effect_presets(state: State<'_, Arc<AppState>>) -> Vec<EffectsPreset> {
+    state.effect_presets.lock().unwrap().presets.clone()
+}
+
+// Stores the current effects settings under the given name.
+#[tauri::command]
+fn save_effect_preset(name: String, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    let effects = state.settings.lock().unwrap().effects.clone();
+    let mut presets = state.effect_presets.lock().unwrap();
+    presets.upsert(&name, effects)?;
+    presets.save(&app)
+}
+
+#[tauri::command]
+fn apply_effect_preset(name: String, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<EffectsSettings, String> {
+    let preset = state.effect_presets.lock().unwrap().get(&name)?.settings.clone();
+    edit_effects(&app, &state, |effects| {
+        *effects = preset;
+        Ok(())
+    })
+}
+
+#[tauri::command]
+fn delete_effect_preset(name: String, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    let mut presets = state.effect_presets.lock().unwrap();
+    presets.delete(&name)?;
+    presets.save(&app)
+}
+
+fn edit_tempo(
+    app: &AppHandle,
+    state: &AppState,
+    edit: impl FnOnce(&mut TempoSettings),
+) -> Result<TempoSettings, String> {
+    let settings = {
+        let mut settings = state.settings.lock().unwrap();
+        let mut tempo = settings.tempo;
+        edit(&mut tempo);
+        tempo.validate()?;
+        settings.tempo = tempo;
+        settings.clone()
+    };
+    settings.save(app)?;
+
+    state.engine.send(EngineCommand::SetTempo(settings.tempo));
+    Ok(settings.tempo)
+}
+
+#[tauri::command]
+fn get_tempo(state: State<'_, Arc<AppState>>) -> TempoSettings {
+    state.settings.lock().unwrap().tempo
+}
+
+// Speed and pitch are independent: speed keeps the pitch, pitch keeps the tempo.
+#[tauri::command]
+fn set_playback_rate(
+    speed: f32,
+    pitch_semitones: f32,
+    app: AppHandle,
+    state: State<'_, Arc<AppState>>,
+) -> Result<TempoSettings, String> {
+    edit_tempo(&app, &state, |tempo| {
+        tempo.speed = speed;
+        tempo.pitch_semitones = pitch_semitones;
+    })
+}
+
+// Stretches tracks with a known BPM to the target; None goes back to the manual speed.
+#[tauri::command]
+fn set_sync_bpm(bpm: Option<f32>, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<TempoSettings, String> {
+    edit_tempo(&app, &state, |tempo| tempo.sync_bpm = bpm)
+}
+
+#[tauri::command]
+fn get_metronome(state: State<'_, Arc<AppState>>) -> MetronomeSettings {
+    state.settings.lock().unwrap().metronome
+}
+
+// With quantize_start on and the click running, tracks start on the next downbeat.
+#[tauri::command]
+fn set_metronome(metronome: MetronomeSettings, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    metronome.validate()?;
+
+    let settings = {
+        let mut settings = state.settings.lock().unwrap();
+        settings.metronome = metronome;
+        settings.clone()
+    };
+    settings.save(&app)?;
+
+    state.engine.send(EngineCommand::SetMetronome(metronome));
+    Ok(())
+}
+
+// Metering is off until the player shows a meter, since it runs an FFT 20 times a second.
+#[tauri::command]
+fn set_metering(enabled: bool, state: State<'_, Arc<AppState>>) {
+    state.engine.send(EngineCommand::SetMetering(enabled));
+    println!("Metering {}", if enabled { "enabled" } else { "disabled" });
+}
+
+#[tauri::command]
+fn get_spectrum_bands() -> Vec<f32> {
+    band_frequencies()
+}
+
+#[tauri::command]
+fn list_output_devices(state: State<'_, Arc<AppState>>) -> Result<Vec<OutputDevice>, String> {
+    state.engine.output_devices()
+}
+
+#[tauri::command]
+fn set_output_device(name: Option<String>, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    if let Some(name) = &name {
+        let devices = state.engine.output_devices()?;
+        if !devices.iter().any(|device| &device.name == name) {
+            return Err(format!("Output device '{}' not found.", name));
         }
-    } else {
-        eprintln!("Failed to read directory: {}", directory);
     }
 
-    println!("Found {} songs in directory: {}", music_files.len(), directory);
-    music_files
+    let settings = {
+        let mut settings = state.settings.lock().unwrap();
+        settings.output_device = name.clone();
+        settings.clone()
+    };
+    settings.save(&app)?;
+
+    println!("Output device set to: {}", name.as_deref().unwrap_or("default"));
+    state.engine.send(EngineCommand::SetOutputDevice(name));
+    Ok(())
+}
+
+#[tauri::command]
+fn get_output_device(state: State<'_, Arc<AppState>>) -> Option<String> {
+    state.settings.lock().unwrap().output_device.clone()
+}
+
+#[tauri::command]
+fn seek_to(seconds: f64, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    if !seconds.is_finite() || seconds < 0.0 {
+        return Err(format!("Invalid seek position: {}", seconds));
+    }
+
+    state.engine.send(EngineCommand::Seek(Duration::from_secs_f64(seconds)));
+    println!("Seeking to: {:.2}s", seconds);
+    Ok(())
+}
+
+// Loops between two points of the current track, in seconds.
+#[tauri::command]
+fn set_loop_region(start: f64, end: f64, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    let status = state.engine.status();
+    if status.title.is_none() {
+        return Err("Nothing is playing.".to_string());
+    }
+    if !start.is_finite() || !end.is_finite() || start < 0.0 {
+        return Err(format!("Invalid loop region: {} to {}", start, end));
+    }
+    if end - start < MIN_LOOP_SECONDS {
+        return Err(format!("Loop region must be at least {} seconds long.", MIN_LOOP_SECONDS));
+    }
+    if let Some(duration) = status.duration.filter(|&duration| end > duration) {
+        return Err(format!("Loop end {:.2}s is past the end of the track ({:.2}s).", end, duration));
+    }
+
+    state.engine.send(EngineCommand::SetLoopRegion(Some((start, end))));
+    println!("Looping {:.3}s to {:.3}s", start, end);
+    Ok(())
+}
+
+#[tauri::command]
+fn clear_loop(state: State<'_, Arc<AppState>>) {
+    state.engine.send(EngineCommand::SetLoopRegion(None));
+    println!("Loop region cleared");
+}
+
+#[tauri::command]
+fn set_whole_file_loop(enabled: bool, state: State<'_, Arc<AppState>>) {
+    state.engine.send(EngineCommand::SetWholeFileLoop(enabled));
+    println!("Whole-file loop {}", if enabled { "enabled" } else { "disabled" });
+}
+
+#[tauri::command]
+fn get_playback_status(state: State<'_, Arc<AppState>>) -> PlaybackStatus {
+    state.engine.status()
+}
+
+#[tauri::command]
+fn get_playback_position(state: State<'_, Arc<AppState>>) -> PlaybackPosition {
+    state.engine.position()
+}
+
+#[tauri::command]
+fn get_track_duration(root: String, path: String, state: State<'_, Arc<AppState>>) -> Result<Option<f64>, String> {
+    let song = find_song(&state, &root, &path)?;
+    let track = resolve_track(&state, &song).ok_or(format!("Library root '{}' not found.", root))?;
+    Ok(probe_duration(&track.path).map(|duration| duration.as_secs_f64()))
+}
+
+#[tauri::command]
+async fn get_waveform(path: String, width: usize, app: AppHandle) -> Result<WaveformData, String> {
+    if width == 0 {
+        return Err("Waveform width must be greater than zero.".to_string());
+    }
+
+    tauri::async_runtime::spawn_blocking(move || {
+        load_waveform(&app, Path::new(&path)).map(|waveform| waveform.render(width))
+    })
+    .await
+    .map_err(|e| format!("Waveform generation failed: {}", e))?
+}
+
+// Returns a job id straight away; progress arrives as "convert-progress" and
+// the outcome as "convert-finished", both tagged with that id.
+#[tauri::command]
+fn convert_audio(
+    input: String,
+    output: String,
+    format: OutputFormat,
+    options: Option<ConvertOptions>,
+    app: AppHandle,
+) -> Result<u64, String> {
+    let options = options.unwrap_or_default();
+    let (input, output) = (PathBuf::from(input), PathBuf::from(output));
+    check_conversion(&input, &output, format, &options)?;
+
+    let job = next_job_id();
+    tauri::async_runtime::spawn_blocking(move || {
+        let result = convert_file(&app, job, &input, &output, format, &options);
+        match &result {
+            Ok(()) => println!("Converted {} to {}", input.display(), output.display()),
+            Err(e) => eprintln!("Conversion of {} failed: {}", input.display(), e),
+        }
+        emit_event(
+            &app,
+            "convert-finished",
+            ConvertFinished {
+                job,
+                input: input.to_string_lossy().into_owned(),
+                output: output.to_string_lossy().into_owned(),
+                error: result.err(),
+            },
+        );
+    });
+    Ok(job)
 }
 
 #[tauri::command]
 fn get_current_song_playing(state: State<'_, Arc<AppState>>) -> Option<String> {
-    let title_guard = state.current_song_title.lock().unwrap();
-    title_guard.clone()
+    let song_guard = state.current_song.lock().unwrap();
+    song_guard.as_ref().map(|song| song.title.clone())
+}
+
+fn record_play(app: &AppHandle, state: &AppState, song: &Song) {
+    let now = unix_now();
+    let mut index = state.library_index.lock().unwrap();
+    if let Some(indexed) = index.song_mut(&song.root, &song.path) {
+        indexed.stats.play_count += 1;
+        indexed.stats.last_played = Some(now);
+    }
+    index.record_history(HistoryEntry {
+        root: song.root.clone(),
+        path: song.path.clone(),
+        title: song.title.clone(),
+        artist: song.artist.clone(),
+        played_at: now,
+        skipped: false,
+    });
+    save_track_stats(app, state, &index);
+}
+
+fn record_skip(app: &AppHandle, state: &AppState, song: &Song) {
+    let mut index = state.library_index.lock().unwrap();
+    if let Some(indexed) = index.song_mut(&song.root, &song.path) {
+        indexed.stats.skip_count += 1;
+    }
+    if let Some(entry) = index
+        .history
+        .iter_mut()
+        .rev()
+        .find(|entry| entry.root == song.root && entry.path == song.path)
+    {
+        entry.skipped = true;
+    }
+    println!("Skipped: {}", song.title);
+    save_track_stats(app, state, &index);
+}
+
+fn save_track_stats(app: &AppHandle, state: &AppState, index: &LibraryIndex) {
+    update_song_cache(app, state, index.songs());
+    state.index_dirty.store(true, Ordering::Relaxed);
+}
+
+// Must be called without the library_index lock held; the file is written
+// from a snapshot so other threads are not blocked on disk I/O.
+fn save_library_index(app: &AppHandle, state: &AppState) -> Result<(), String> {
+    let _writer = state.index_writer.lock().unwrap();
+    state.index_dirty.store(false, Ordering::Relaxed);
+    let index = state.library_index.lock().unwrap().clone();
+    index.save(app)
+}
+
+fn flush_library_index(app: &AppHandle, state: &AppState) {
+    if state.index_dirty.load(Ordering::Relaxed) {
+        if let Err(e) = save_library_index(app, state) {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+#[tauri::command]
+fn rate_track(root: String, path: String, rating: u8, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    if rating > 5 {
+        return Err(format!("Rating must be between 0 and 5, got {}.", rating));
+    }
+
+    let mut index = state.library_index.lock().unwrap();
+    let song = index
+        .song_mut(&root, &path)
+        .ok_or(format!("Song '{}' is not in library '{}'.", path, root))?;
+    song.stats.rating = rating;
+    save_track_stats(&app, &state, &index);
+    Ok(())
+}
+
+#[tauri::command]
+fn toggle_favorite(root: String, path: String, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<bool, String> {
+    let mut index = state.library_index.lock().unwrap();
+    let song = index
+        .song_mut(&root, &path)
+        .ok_or(format!("Song '{}' is not in library '{}'.", path, root))?;
+    song.stats.favorite = !song.stats.favorite;
+    let favorite = song.stats.favorite;
+    save_track_stats(&app, &state, &index);
+    Ok(favorite)
+}
+
+#[tauri::command]
+async fn analyze_track(root: String, path: String, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<Song, String> {
+    let song = find_song(&state, &root, &path)?;
+    let track = resolve_track(&state, &song).ok_or(format!("Library root '{}' not found.", root))?;
+
+    let analysis = tauri::async_runtime::spawn_blocking(move || analyze_file(&track.path))
+        .await
+        .map_err(|e| format!("Track analysis failed: {}", e))??;
+
+    let mut index = state.library_index.lock().unwrap();
+    let song = index
+        .song_mut(&root, &path)
+        .ok_or(format!("Song '{}' is not in library '{}'.", path, root))?;
+    song.analysis = Some(analysis);
+    song.resolve_tempo_and_key();
+    let song = song.clone();
+    save_track_stats(&app, &state, &index);
+    Ok(song)
+}
+
+// Passing None for either value clears that override.
+#[tauri::command]
+fn set_track_tempo_and_key(
+    root: String,
+    path: String,
+    bpm: Option<f32>,
+    key: Option<String>,
+    app: AppHandle,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Song, String> {
+    let bpm = validate_bpm(bpm)?;
+    let mut index = state.library_index.lock().unwrap();
+    let song = index
+        .song_mut(&root, &path)
+        .ok_or(format!("Song '{}' is not in library '{}'.", path, root))?;
+    song.stats.bpm_override = bpm;
+    song.stats.key_override = normalize_key(key);
+    song.resolve_tempo_and_key();
+    let song = song.clone();
+    save_track_stats(&app, &state, &index);
+    Ok(song)
+}
+
+fn validate_bpm(bpm: Option<f32>) -> Result<Option<f32>, String> {
+    match bpm {
+        Some(bpm) if !bpm.is_finite() || bpm <= 0.0 => Err(format!("BPM must be a positive number, got {}.", bpm)),
+        _ => Ok(bpm),
+    }
+}
+
+fn normalize_key(key: Option<String>) -> Option<String> {
+    key.map(|key| key.trim().to_string()).filter(|key| !key.is_empty())
+}
+
+#[tauri::command]
+fn get_history(limit: Option<usize>, state: State<'_, Arc<AppState>>) -> Vec<HistoryEntry> {
+    let index = state.library_index.lock().unwrap();
+    index
+        .history
+        .iter()
+        .rev()
+        .take(limit.unwrap_or(usize::MAX))
+        .cloned()
+        .collect()
+}
+
+#[tauri::command]
+fn export_history(file_path: String, format: Option<HistoryFormat>, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    let file = PathBuf::from(&file_path);
+    let format = match format {
+        Some(format) => format,
+        None => HistoryFormat::from_path(&file)?,
+    };
+
+    let history = state.library_index.lock().unwrap().history.clone();
+    export_history_file(&file, format, &history)
 }
 
 #[tauri::command]
@@ -389,20 +1268,292 @@ fn get_cached_songs(state: State<'_, Arc<AppState>>) -> Vec<Song> {
 }
 
 #[tauri::command]
-fn get_directory_path(state: State<'_, Arc<AppState>>) -> Option<String> {
-    let dir_path = state.directory_path.lock().unwrap();
-    dir_path.clone()
+fn search_library(query: LibraryQuery, state: State<'_, Arc<AppState>>) -> SearchResults {
+    let song_cache = state.song_cache.lock().unwrap();
+    search_songs(&song_cache, &query)
+}
+
+// Scans walk whole folders, so the commands that trigger one run it off the
+// main thread; progress arrives as "library-scan-progress" events.
+async fn run_scan<T: Send + 'static>(
+    app: AppHandle,
+    scan: impl FnOnce(&AppHandle, &AppState) -> Result<T, String> + Send + 'static,
+) -> Result<T, String> {
+    tauri::async_runtime::spawn_blocking(move || scan(&app, &app.state::<Arc<AppState>>()))
+        .await
+        .map_err(|e| format!("Library scan failed: {}", e))?
 }
 
 #[tauri::command]
-fn set_directory(path: String, state: State<'_, Arc<AppState>>) {
-    let mut dir_path = state.directory_path.lock().unwrap();
-    *dir_path = Some(path.clone());
+async fn set_directory(path: String, app: AppHandle) -> Result<(), String> {
     println!("Directory set: {}", path);
+    run_scan(app, move |app, state| {
+        let existing = {
+            let index = state.library_index.lock().unwrap();
+            index.roots.iter().find(|root| root.path == path).cloned()
+        };
+        if let Some(root) = existing {
+            rescan_library(app, state, &root)?;
+            return Ok(());
+        }
+
+        let mut root = LibraryRoot::from_directory(&path);
+        let base_name = root.name.clone();
+        let mut suffix = 2;
+        while state.library_index.lock().unwrap().root(&root.name).is_some() {
+            root.name = format!("{} ({})", base_name, suffix);
+            suffix += 1;
+        }
+        add_root(app, state, root)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn add_library_root(name: String, path: String, app: AppHandle) -> Result<(), String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Library root name cannot be empty.".to_string());
+    }
+    run_scan(app, move |app, state| add_root(app, state, LibraryRoot { name, path })).await
+}
+
+#[tauri::command]
+fn remove_library_root(name: String, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    state.library_watchers.lock().unwrap().remove(&name);
+
+    let mut index = state.library_index.lock().unwrap();
+    let root_count = index.roots.len();
+    index.roots.retain(|root| root.name != name);
+    if index.roots.len() == root_count {
+        return Err(format!("Library root '{}' not found.", name));
+    }
+
+    index.tracks.retain(|entry| entry.song.root != name);
+    update_song_cache(&app, &state, index.songs());
+    println!("Removed library root: {}", name);
+    drop(index);
+    save_library_index(&app, &state)
+}
+
+#[tauri::command]
+fn list_library_roots(state: State<'_, Arc<AppState>>) -> Vec<LibraryRoot> {
+    state.library_index.lock().unwrap().roots.clone()
+}
+
+fn add_root(app: &AppHandle, state: &AppState, root: LibraryRoot) -> Result<(), String> {
+    if !Path::new(&root.path).is_dir() {
+        return Err(format!("'{}' is not a directory.", root.path));
+    }
+
+    {
+        let mut index = state.library_index.lock().unwrap();
+        if index.root(&root.name).is_some() {
+            return Err(format!("A library root named '{}' already exists.", root.name));
+        }
+        if let Some(existing) = index.roots.iter().find(|existing| existing.path == root.path) {
+            return Err(format!("'{}' is already in the library as '{}'.", root.path, existing.name));
+        }
+        index.roots.push(root.clone());
+    }
+
+    rescan_library(app, state, &root)?;
+    println!("Added library root '{}': {}", root.name, root.path);
+    start_library_watcher(app, state, root);
+    Ok(())
+}
+
+fn start_library_watcher(app: &AppHandle, state: &AppState, root: LibraryRoot) {
+    let mut library_watchers = state.library_watchers.lock().unwrap();
+    // Drop the previous watcher first so it stops delivering events for the old folder.
+    library_watchers.remove(&root.name);
+    let name = root.name.clone();
+    match watch_library(app.clone(), root) {
+        Ok(watcher) => {
+            library_watchers.insert(name, watcher);
+        }
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+impl LibraryService for AppHandle {
+    fn library_index(&self) -> &Mutex<LibraryIndex> {
+        &self.state::<Arc<AppState>>().inner().library_index
+    }
+
+    fn scan_options(&self) -> ScanOptions {
+        self.state::<Arc<AppState>>().settings.lock().unwrap().scan.clone()
+    }
+
+    fn rescan(&self, root: &LibraryRoot) -> Result<LibraryChange, String> {
+        rescan_library(self, &self.state::<Arc<AppState>>(), root)
+    }
+
+    fn update_song_cache(&self, songs: Vec<Song>) {
+        update_song_cache(self, &self.state::<Arc<AppState>>(), songs)
+    }
 
-    let mut song_cache = state.song_cache.lock().unwrap();
-    *song_cache = load_songs_from_directory(&path);
-    println!("Cached songs from directory: {}", path);
+    fn save_index(&self) -> Result<(), String> {
+        save_library_index(self, &self.state::<Arc<AppState>>())
+    }
+
+    fn request_analysis(&self) {
+        request_analysis(self)
+    }
+
+    fn analysis_job(&self) -> &AnalysisJob {
+        &self.state::<Arc<AppState>>().inner().analysis
+    }
+
+    fn save_track_stats(&self, index: &LibraryIndex) {
+        save_track_stats(self, &self.state::<Arc<AppState>>(), index)
+    }
+
+    fn emit_event<S: Serialize + Clone>(&self, event: &str, payload: S) {
+        emit_event(self, event, payload)
+    }
+}
+
+fn rescan_library(app: &AppHandle, state: &AppState, root: &LibraryRoot) -> Result<LibraryChange, String> {
+    let options = state.settings.lock().unwrap().scan.clone();
+    let previous = state.library_index.lock().unwrap().root_tracks(&root.name);
+
+    let tracks = scan_directory(root, &options, &previous, |scanned| {
+        emit_event(app, "library-scan-progress", ScanProgress { root: root.name.clone(), scanned })
+    })?;
+    let change = LibraryChange::between(&previous, &tracks);
+
+    let mut index = state.library_index.lock().unwrap();
+    if index.root(&root.name) != Some(root) {
+        // Removed or replaced while the scan was running.
+        return Ok(LibraryChange::default());
+    }
+    index.replace_root_tracks(&root.name, tracks);
+    update_song_cache(app, state, index.songs());
+    drop(index);
+    save_library_index(app, state)?;
+    request_analysis(app);
+    Ok(change)
+}
+
+// Smart playlists are re-evaluated whenever the library contents change.
+fn update_song_cache(app: &AppHandle, state: &AppState, songs: Vec<Song>) {
+    let changes = state.smart_playlists.lock().unwrap().refresh(&songs);
+    *state.song_cache.lock().unwrap() = songs;
+    for change in changes {
+        emit_event(app, "smart-playlist-changed", change);
+    }
+}
+
+#[tauri::command]
+fn list_smart_playlists(state: State<'_, Arc<AppState>>) -> Vec<SmartPlaylist> {
+    state.smart_playlists.lock().unwrap().playlists.clone()
+}
+
+#[tauri::command]
+fn save_smart_playlist(playlist: SmartPlaylist, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    let songs = state.song_cache.lock().unwrap().clone();
+    let mut smart_playlists = state.smart_playlists.lock().unwrap();
+    smart_playlists.upsert(playlist)?;
+    let changes = smart_playlists.refresh(&songs);
+    smart_playlists.save(&app)?;
+    drop(smart_playlists);
+
+    for change in changes {
+        emit_event(&app, "smart-playlist-changed", change);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn delete_smart_playlist(name: String, app: AppHandle, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    let mut smart_playlists = state.smart_playlists.lock().unwrap();
+    smart_playlists.delete(&name)?;
+    smart_playlists.save(&app)
+}
+
+#[tauri::command]
+fn get_smart_playlist_tracks(name: String, state: State<'_, Arc<AppState>>) -> Result<Vec<Song>, String> {
+    let playlist = state.smart_playlists.lock().unwrap().get(&name)?.clone();
+    let songs = state.song_cache.lock().unwrap();
+    Ok(playlist.evaluate(&songs))
+}
+
+#[tauri::command]
+fn play_smart_playlist(name: String, index: usize, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    let playlist = state.smart_playlists.lock().unwrap().get(&name)?.clone();
+    let songs = playlist.evaluate(&state.song_cache.lock().unwrap());
+    if index >= songs.len() {
+        return Err(format!("Track {} is out of range for smart playlist '{}'.", index, name));
+    }
+
+    play_from_queue(&state, |queue| queue.replace(songs, index))?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn share_smart_playlist_command(name: String, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    let me = {
+        let guard = state.logged_in_user.lock().unwrap();
+        guard.clone()
+    }.ok_or("Must be logged in to share a smart playlist.")?;
+
+    let playlist = state.smart_playlists.lock().unwrap().get(&name)?.clone();
+    share_smart_playlist(&playlist, &me)
+        .await
+        .map_err(|e| format!("Failed to share smart playlist: {}", e))
+}
+
+#[tauri::command]
+async fn fetch_shared_smart_playlists_command(state: State<'_, Arc<AppState>>) -> Result<Vec<SharedSmartPlaylist>, String> {
+    let me = {
+        let guard = state.logged_in_user.lock().unwrap();
+        guard.clone()
+    }.ok_or("Not logged in")?;
+
+    let friends = match fetch_friends(&me).await {
+        Ok(friends_list) => friends_list,
+        Err(_) => {
+            println!("No friends found for user '{}', defaulting to an empty list.", me);
+            Vec::new()
+        }
+    };
+
+    let shared = fetch_shared_smart_playlists()
+        .await
+        .map_err(|e| format!("Failed to fetch shared smart playlists: {}", e))?;
+
+    Ok(shared
+        .into_iter()
+        .filter(|shared| friends.contains(&shared.shared_by))
+        .collect())
+}
+
+#[tauri::command]
+fn get_scan_options(state: State<'_, Arc<AppState>>) -> ScanOptions {
+    state.settings.lock().unwrap().scan.clone()
+}
+
+#[tauri::command]
+async fn set_scan_options(options: ScanOptions, app: AppHandle) -> Result<(), String> {
+    LibraryFilter::new(&options)?;
+
+    run_scan(app, move |app, state| {
+        let settings = {
+            let mut settings = state.settings.lock().unwrap();
+            settings.scan = options;
+            settings.clone()
+        };
+        settings.save(app)?;
+
+        let roots = state.library_index.lock().unwrap().roots.clone();
+        for root in roots {
+            rescan_library(app, state, &root)?;
+            println!("Rescanned '{}' with new scan options.", root.name);
+        }
+        Ok(())
+    })
+    .await
 }
 
 #[tauri::command]
@@ -429,26 +1580,87 @@ async fn remove_sample_command(title: String) -> Result<(), String> {
 }
 
 fn main() {
+    let (engine, engine_events) = AudioEngine::spawn();
+    let state = Arc::new(AppState {
+        engine,
+        current_song: Mutex::new(None),
+        queue: Mutex::new(PlayQueue::new()),
+        song_cache: Mutex::new(Vec::new()),
+        library_index: Mutex::new(LibraryIndex::default()),
+        index_dirty: AtomicBool::new(false),
+        index_writer: Mutex::new(()),
+        analysis: AnalysisJob::default(),
+        library_watchers: Mutex::new(HashMap::new()),
+        playlists: Mutex::new(PlaylistStore::default()),
+        smart_playlists: Mutex::new(SmartPlaylistStore::default()),
+        effect_presets: Mutex::new(EffectsPresetStore::default()),
+        sample_cache: Mutex::new(Vec::new()),
+        preset_cache: Mutex::new(Vec::new()),
+        logged_in_user: Mutex::new(None),
+        friends_cache: Mutex::new(Vec::new()),
+        settings: Mutex::new(Settings::default()),
+    });
+
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_fs::init())
-        .manage(Arc::new(AppState {
-            current_song: Mutex::new(None),
-            current_song_title: Mutex::new(None),
-            song_cache: Mutex::new(Vec::new()),
-            sample_cache: Mutex::new(Vec::new()),
-            preset_cache: Mutex::new(Vec::new()),
-            directory_path: Mutex::new(None),
-            logged_in_user: Mutex::new(None),
-            friends_cache: Mutex::new(Vec::new()),
-        }))
+        .manage(state)
+        .setup(|app| {
+            let state = app.state::<Arc<AppState>>();
+            let settings = Settings::load(app.handle());
+            state.engine.send(EngineCommand::SetCrossfade(Duration::from_secs_f32(settings.crossfade_seconds)));
+            state.engine.send(EngineCommand::SetNormalization(settings.normalization));
+            state.engine.send(EngineCommand::SetEffects(settings.effects.clone()));
+            state.engine.send(EngineCommand::SetTempo(settings.tempo));
+            state.engine.send(EngineCommand::SetMetronome(settings.metronome));
+            state.engine.send(EngineCommand::SetOutputDevice(settings.output_device.clone()));
+            *state.settings.lock().unwrap() = settings;
+
+            // Commands are only dispatched once setup returns, so the window
+            // never sees an empty library while the index is loading.
+            *state.playlists.lock().unwrap() = PlaylistStore::load(app.handle());
+            *state.smart_playlists.lock().unwrap() = SmartPlaylistStore::load(app.handle());
+            *state.effect_presets.lock().unwrap() = EffectsPresetStore::load(app.handle());
+            let index = LibraryIndex::load(app.handle());
+            update_song_cache(app.handle(), &state, index.songs());
+            let roots = index.roots.clone();
+            *state.library_index.lock().unwrap() = index;
+
+            for root in &roots {
+                start_library_watcher(app.handle(), &state, root.clone());
+            }
+
+            let handle = app.handle().clone();
+            thread::spawn(move || {
+                let state = handle.state::<Arc<AppState>>();
+                for root in roots {
+                    match rescan_library(&handle, &state, &root) {
+                        Ok(change) if !change.is_empty() => {
+                            emit_event(&handle, "library-changed", change)
+                        }
+                        Ok(_) => {}
+                        Err(e) => eprintln!("Failed to refresh library root '{}': {}", root.name, e),
+                    }
+                }
+            });
+
+            let handle = app.handle().clone();
+            thread::spawn(move || loop {
+                thread::sleep(INDEX_SAVE_INTERVAL);
+                flush_library_index(&handle, &handle.state::<Arc<AppState>>());
+            });
+
+            spawn_engine_listener(app.handle().clone(), engine_events);
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             fetch_all_samples,
             get_cached_samples,
             play_song,
             pause_song,
             unpause_song,
+            stop_song,
             fetch_all_presets,
             get_cached_presets,
             upload_preset_metadata,
@@ -456,8 +1668,16 @@ fn main() {
             upload_sample_metadata,
             set_volume,
             get_current_song_playing,
+            get_waveform,
+            convert_audio,
+            rate_track,
+            toggle_favorite,
+            analyze_track,
+            set_track_tempo_and_key,
+            get_history,
+            export_history,
             get_cached_songs,
-            get_directory_path,
+            search_library,
             set_directory,
             register_user_command,
             login_user_command,
@@ -466,7 +1686,72 @@ fn main() {
             get_cached_friends,
             remove_friend_command,
             remove_sample_command,
+            enqueue,
+            play_folder_from,
+            play_next,
+            play_previous,
+            clear_queue,
+            set_shuffle,
+            set_repeat_mode,
+            get_queue,
+            list_playlists,
+            create_playlist,
+            rename_playlist,
+            delete_playlist,
+            add_to_playlist,
+            remove_from_playlist,
+            move_playlist_track,
+            import_playlist,
+            export_playlist,
+            play_playlist,
+            list_smart_playlists,
+            save_smart_playlist,
+            delete_smart_playlist,
+            get_smart_playlist_tracks,
+            play_smart_playlist,
+            share_smart_playlist_command,
+            fetch_shared_smart_playlists_command,
+            seek_to,
+            get_playback_position,
+            get_playback_status,
+            get_track_duration,
+            set_crossfade,
+            get_crossfade,
+            set_normalization,
+            get_normalization,
+            measure_track_loudness,
+            get_metronome,
+            set_metronome,
+            set_metering,
+            set_loop_region,
+            clear_loop,
+            set_whole_file_loop,
+            get_tempo,
+            set_playback_rate,
+            set_sync_bpm,
+            get_effects,
+            set_effects,
+            set_effects_enabled,
+            set_eq_band,
+            list_effect_presets,
+            save_effect_preset,
+            apply_effect_preset,
+            delete_effect_preset,
+            get_spectrum_bands,
+            list_output_devices,
+            set_output_device,
+            get_output_device,
+            get_scan_options,
+            set_scan_options,
+            add_library_root,
+            remove_library_root,
+            list_library_roots,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let RunEvent::Exit = event {
+                flush_library_index(app, &app.state::<Arc<AppState>>());
+            }
+        });
 }
\ No newline at end of file
@@ -0,0 +1,317 @@
+use rodio::{source::SeekError, Source};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    f32::consts::PI,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+pub const SPECTRUM_BANDS: usize = 32;
+const FFT_SIZE: usize = 2048;
+const MIN_FREQUENCY: f32 = 20.0;
+const MAX_FREQUENCY: f32 = 20_000.0;
+// Levels below this are reported as the floor instead of -inf.
+const FLOOR_DB: f32 = -100.0;
+// Samples are handed over in batches so the audio thread rarely touches the lock.
+const FLUSH_SAMPLES: usize = 512;
+const MAX_BUFFERED_FRAMES: usize = FFT_SIZE * 8;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MeterFrame {
+    pub title: Option<String>,
+    // Per channel, in dBFS after volume, fades and normalization.
+    pub peak: Vec<f32>,
+    pub rms: Vec<f32>,
+    // Log-spaced bands from 20 Hz to 20 kHz, in dB.
+    pub spectrum: Vec<f32>,
+}
+
+#[derive(Default)]
+struct TapBuffer {
+    channels: u16,
+    sample_rate: u32,
+    samples: VecDeque<f32>,
+}
+
+// Shared between a deck's sources on the audio thread and the engine thread.
+#[derive(Clone, Default)]
+pub struct MeterTap {
+    buffer: Arc<Mutex<TapBuffer>>,
+}
+
+impl MeterTap {
+    pub fn wrap<S>(&self, source: S) -> Tap<S>
+    where
+        S: Source<Item = i16>,
+    {
+        Tap {
+            inner: source,
+            tap: self.clone(),
+            pending: Vec::with_capacity(FLUSH_SAMPLES),
+        }
+    }
+
+    fn take(&self) -> Option<(u16, u32, Vec<f32>)> {
+        let mut buffer = self.buffer.lock().unwrap();
+        if buffer.samples.is_empty() {
+            return None;
+        }
+        let samples = buffer.samples.drain(..).collect();
+        Some((buffer.channels, buffer.sample_rate, samples))
+    }
+}
+
+pub struct Tap<S> {
+    inner: S,
+    tap: MeterTap,
+    pending: Vec<f32>,
+}
+
+impl<S> Tap<S>
+where
+    S: Source<Item = i16>,
+{
+    fn flush(&mut self) {
+        // Never wait on the engine thread; if it holds the lock, try again next batch.
+        let Ok(mut buffer) = self.tap.buffer.try_lock() else {
+            if self.pending.len() > FLUSH_SAMPLES * 8 {
+                self.pending.clear();
+            }
+            return;
+        };
+
+        let channels = self.inner.channels().max(1);
+        if buffer.channels != channels || buffer.sample_rate != self.inner.sample_rate() {
+            buffer.samples.clear();
+            buffer.channels = channels;
+            buffer.sample_rate = self.inner.sample_rate();
+        }
+        buffer.samples.extend(self.pending.drain(..));
+
+        let capacity = MAX_BUFFERED_FRAMES * channels as usize;
+        if buffer.samples.len() > capacity {
+            let excess = buffer.samples.len() - capacity;
+            buffer.samples.drain(..excess);
+        }
+    }
+}
+
+impl<S> Iterator for Tap<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let sample = self.inner.next()?;
+        self.pending.push(sample as f32 / 32768.0);
+        if self.pending.len() >= FLUSH_SAMPLES {
+            self.flush();
+        }
+        Some(sample)
+    }
+}
+
+impl<S> Source for Tap<S>
+where
+    S: Source<Item = i16>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.pending.clear();
+        self.inner.try_seek(pos)
+    }
+}
+
+pub struct Analyzer {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    history: Vec<f32>,
+}
+
+impl Analyzer {
+    pub fn new() -> Analyzer {
+        let window = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FFT_SIZE as f32).cos())
+            .collect();
+        Analyzer {
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            history: Vec::with_capacity(FFT_SIZE),
+        }
+    }
+
+    // Levels cover every sample played since the last frame; the spectrum uses
+    // the most recent FFT_SIZE samples of the mono mix.
+    pub fn measure(&mut self, tap: &MeterTap, gain: f32, title: Option<String>) -> Option<MeterFrame> {
+        let (channels, sample_rate, samples) = tap.take()?;
+        let channels = channels as usize;
+
+        let mut peak = vec![0.0f32; channels];
+        let mut energy = vec![0.0f32; channels];
+        for frame in samples.chunks_exact(channels) {
+            let mut mono = 0.0;
+            for (channel, sample) in frame.iter().enumerate() {
+                let value = sample * gain;
+                peak[channel] = peak[channel].max(value.abs());
+                energy[channel] += value * value;
+                mono += value;
+            }
+            self.history.push(mono / channels as f32);
+        }
+        if self.history.len() > FFT_SIZE {
+            let excess = self.history.len() - FFT_SIZE;
+            self.history.drain(..excess);
+        }
+
+        let frames = (samples.len() / channels).max(1) as f32;
+        Some(MeterFrame {
+            title,
+            peak: peak.iter().map(|&peak| to_db(peak)).collect(),
+            rms: energy.iter().map(|&energy| to_db((energy / frames).sqrt())).collect(),
+            spectrum: self.spectrum(sample_rate as f32),
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.history.clear();
+    }
+
+    fn spectrum(&self, sample_rate: f32) -> Vec<f32> {
+        let offset = FFT_SIZE - self.history.len().min(FFT_SIZE);
+        let mut buffer: Vec<Complex<f32>> = (0..FFT_SIZE)
+            .map(|i| {
+                let sample = if i < offset { 0.0 } else { self.history[i - offset] };
+                Complex::new(sample * self.window[i], 0.0)
+            })
+            .collect();
+        self.fft.process(&mut buffer);
+
+        // Scaled so a full-scale sine reads close to 0 dB.
+        let scale = 4.0 / FFT_SIZE as f32;
+        let bin_hz = sample_rate / FFT_SIZE as f32;
+        let nyquist = FFT_SIZE / 2;
+        let edges = band_edges();
+        edges
+            .windows(2)
+            .map(|edge| {
+                let low = ((edge[0] / bin_hz).floor() as usize).clamp(1, nyquist - 1);
+                let high = ((edge[1] / bin_hz).ceil() as usize).clamp(low + 1, nyquist);
+                let magnitude = buffer[low..high]
+                    .iter()
+                    .map(|bin| bin.norm())
+                    .fold(0.0f32, f32::max);
+                to_db(magnitude * scale)
+            })
+            .collect()
+    }
+}
+
+// Centre frequencies of the spectrum bands, for labelling the analyzer.
+pub fn band_frequencies() -> Vec<f32> {
+    band_edges()
+        .windows(2)
+        .map(|edge| (edge[0] * edge[1]).sqrt())
+        .collect()
+}
+
+fn band_edges() -> Vec<f32> {
+    let ratio = MAX_FREQUENCY / MIN_FREQUENCY;
+    (0..=SPECTRUM_BANDS)
+        .map(|i| MIN_FREQUENCY * ratio.powf(i as f32 / SPECTRUM_BANDS as f32))
+        .collect()
+}
+
+fn to_db(value: f32) -> f32 {
+    if value <= 0.0 {
+        return FLOOR_DB;
+    }
+    (20.0 * value.log10()).max(FLOOR_DB)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    fn tap_with(channels: u16, samples: Vec<f32>) -> MeterTap {
+        let tap = MeterTap::default();
+        *tap.buffer.lock().unwrap() = TapBuffer {
+            channels,
+            sample_rate: RATE,
+            samples: samples.into(),
+        };
+        tap
+    }
+
+    // Full-scale stereo sine, long enough to fill the FFT window.
+    fn sine(frequency: f32) -> Vec<f32> {
+        (0..FFT_SIZE * 2)
+            .flat_map(|i| {
+                let value = (2.0 * PI * frequency * i as f32 / RATE as f32).sin();
+                [value, value]
+            })
+            .collect()
+    }
+
+    fn band_of(frequency: f32) -> usize {
+        band_edges().windows(2).position(|edge| (edge[0]..edge[1]).contains(&frequency)).unwrap()
+    }
+
+    #[test]
+    fn full_scale_sine_reads_zero_db_in_its_band() {
+        // Centred on an FFT bin, so the Hann window loses nothing to scalloping.
+        let frequency = 43.0 * RATE as f32 / FFT_SIZE as f32;
+        let frame = Analyzer::new().measure(&tap_with(2, sine(frequency)), 1.0, None).unwrap();
+
+        let band = band_of(frequency);
+        assert!(frame.spectrum[band].abs() < 0.1, "band {} read {} dB", band, frame.spectrum[band]);
+        for (i, level) in frame.spectrum.iter().enumerate() {
+            if i.abs_diff(band) > 2 {
+                assert!(*level < -40.0, "band {} read {} dB", i, level);
+            }
+        }
+    }
+
+    #[test]
+    fn off_bin_sine_stays_within_the_window_scalloping() {
+        let frame = Analyzer::new().measure(&tap_with(2, sine(1000.0)), 1.0, None).unwrap();
+        let level = frame.spectrum[band_of(1000.0)];
+        assert!((-1.5..=0.1).contains(&level), "read {} dB", level);
+    }
+
+    #[test]
+    fn levels_follow_gain() {
+        let frame = Analyzer::new().measure(&tap_with(2, sine(1000.0)), 0.5, None).unwrap();
+        for channel in 0..2 {
+            assert!((frame.peak[channel] + 6.02).abs() < 0.05, "peak {}", frame.peak[channel]);
+            assert!((frame.rms[channel] + 9.03).abs() < 0.05, "rms {}", frame.rms[channel]);
+        }
+    }
+
+    #[test]
+    fn silence_reads_the_floor() {
+        let frame = Analyzer::new().measure(&tap_with(1, vec![0.0; FFT_SIZE]), 1.0, None).unwrap();
+        assert_eq!(frame.peak, [FLOOR_DB]);
+        assert!(frame.spectrum.iter().all(|&level| level == FLOOR_DB));
+        assert!(Analyzer::new().measure(&MeterTap::default(), 1.0, None).is_none());
+    }
+}
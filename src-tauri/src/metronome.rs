@@ -0,0 +1,259 @@
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::{
+    f32::consts::PI,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+const SAMPLE_RATE: u32 = 48_000;
+const CLICK_SECONDS: f32 = 0.03;
+const ACCENT_HZ: f32 = 1500.0;
+const BEAT_HZ: f32 = 1000.0;
+const MIN_BPM: f32 = 20.0;
+const MAX_BPM: f32 = 300.0;
+const MAX_BEATS_PER_BAR: u32 = 16;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct MetronomeSettings {
+    pub enabled: bool,
+    // Counted in quarter notes, like track tempos, whatever the beat unit.
+    pub bpm: f32,
+    pub beats_per_bar: u32,
+    // Note value of one beat, the bottom of the time signature: 6/8 at 120 BPM
+    // clicks eighth notes, twice as often as 6/4 would.
+    pub beat_unit: u32,
+    pub volume: f32,
+    // Start tracks on the next downbeat while the metronome is running.
+    pub quantize_start: bool,
+}
+
+impl Default for MetronomeSettings {
+    fn default() -> Self {
+        MetronomeSettings {
+            enabled: false,
+            bpm: 120.0,
+            beats_per_bar: 4,
+            beat_unit: 4,
+            volume: 0.5,
+            quantize_start: false,
+        }
+    }
+}
+
+impl MetronomeSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_BPM..=MAX_BPM).contains(&self.bpm) {
+            return Err(format!(
+                "Metronome BPM must be between {} and {}.",
+                MIN_BPM, MAX_BPM
+            ));
+        }
+        if !(1..=MAX_BEATS_PER_BAR).contains(&self.beats_per_bar) {
+            return Err(format!(
+                "Beats per bar must be between 1 and {}.",
+                MAX_BEATS_PER_BAR
+            ));
+        }
+        if ![1, 2, 4, 8, 16].contains(&self.beat_unit) {
+            return Err(format!(
+                "Beat unit must be 1, 2, 4, 8 or 16, got {}.",
+                self.beat_unit
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.volume) {
+            return Err("Metronome volume must be between 0 and 1.".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Tempo and bar length the click reads live, and the beat position it has
+// reached so playback can be lined up with the next bar.
+#[derive(Clone)]
+pub struct MetronomeClock {
+    bpm: Arc<AtomicU32>,
+    beats_per_bar: Arc<AtomicU32>,
+    beat_unit: Arc<AtomicU32>,
+    beats: Arc<AtomicU64>,
+}
+
+impl MetronomeClock {
+    pub fn new(settings: &MetronomeSettings) -> MetronomeClock {
+        let clock = MetronomeClock {
+            bpm: Arc::new(AtomicU32::new(0)),
+            beats_per_bar: Arc::new(AtomicU32::new(0)),
+            beat_unit: Arc::new(AtomicU32::new(0)),
+            beats: Arc::new(AtomicU64::new(0f64.to_bits())),
+        };
+        clock.set(settings);
+        clock
+    }
+
+    pub fn set(&self, settings: &MetronomeSettings) {
+        self.bpm.store(settings.bpm.to_bits(), Ordering::Relaxed);
+        self.beats_per_bar
+            .store(settings.beats_per_bar, Ordering::Relaxed);
+        self.beat_unit.store(settings.beat_unit, Ordering::Relaxed);
+    }
+
+    // Clamped so a bad tempo can neither stall the click nor divide by zero.
+    fn beats_per_minute(&self) -> f64 {
+        let bpm = f32::from_bits(self.bpm.load(Ordering::Relaxed)).clamp(MIN_BPM, MAX_BPM);
+        let beat_unit = self.beat_unit.load(Ordering::Relaxed).max(1);
+        bpm as f64 * beat_unit as f64 / 4.0
+    }
+
+    fn beats_per_bar(&self) -> u32 {
+        self.beats_per_bar.load(Ordering::Relaxed).max(1)
+    }
+
+    // Time until the click reaches the next downbeat, measured in samples
+    // the mixer has not pulled yet, so a source started now with this much
+    // silence in front lands exactly on the bar.
+    pub fn until_next_bar(&self) -> Duration {
+        let beats = f64::from_bits(self.beats.load(Ordering::Relaxed));
+        let beats_per_bar = self.beats_per_bar() as f64;
+        let into_bar = beats % beats_per_bar;
+        let remaining = if into_bar < 1e-6 {
+            0.0
+        } else {
+            beats_per_bar - into_bar
+        };
+        Duration::from_secs_f64(remaining * 60.0 / self.beats_per_minute())
+    }
+
+    pub fn click(&self) -> Click {
+        Click {
+            clock: self.clone(),
+            beats: 0.0,
+        }
+    }
+}
+
+// Endless click track: an accented tone on the downbeat and a lower one on
+// the other beats, each a short decaying sine. Compound meters such as 6/8
+// and 12/8 also lift the first beat of every group of three.
+pub struct Click {
+    clock: MetronomeClock,
+    beats: f64,
+}
+
+impl Iterator for Click {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let beats_per_minute = self.clock.beats_per_minute();
+        let beats_per_bar = self.clock.beats_per_bar();
+        let compound = self.clock.beat_unit.load(Ordering::Relaxed) >= 8
+            && beats_per_bar > 3
+            && beats_per_bar % 3 == 0;
+
+        let seconds_per_beat = (60.0 / beats_per_minute) as f32;
+        let into_beat = (self.beats.fract() as f32) * seconds_per_beat;
+        let sample = if into_beat < CLICK_SECONDS {
+            let beat = (self.beats % beats_per_bar as f64) as u32;
+            let (frequency, level) = match beat {
+                0 => (ACCENT_HZ, 1.0),
+                beat if compound && beat % 3 == 0 => (BEAT_HZ, 1.0),
+                _ => (BEAT_HZ, 0.7),
+            };
+            let envelope = (-into_beat / (CLICK_SECONDS / 5.0)).exp();
+            (2.0 * PI * frequency * into_beat).sin() * envelope * level
+        } else {
+            0.0
+        };
+
+        self.beats += beats_per_minute / 60.0 / SAMPLE_RATE as f64;
+        self.clock
+            .beats
+            .store(self.beats.to_bits(), Ordering::Relaxed);
+        Some(sample)
+    }
+}
+
+impl Source for Click {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sample indices where a click starts, found from the silence before each one.
+    fn onsets(settings: &MetronomeSettings, seconds: f32) -> Vec<usize> {
+        let samples: Vec<f32> = MetronomeClock::new(settings)
+            .click()
+            .take((SAMPLE_RATE as f32 * seconds) as usize)
+            .collect();
+        (0..samples.len())
+            .filter(|&i| {
+                samples[i] != 0.0 && samples[i.saturating_sub(10)..i].iter().all(|sample| *sample == 0.0)
+            })
+            .collect()
+    }
+
+    fn peak(settings: &MetronomeSettings, beat: usize) -> f32 {
+        let samples_per_beat = (SAMPLE_RATE as f64 * 60.0 / (settings.bpm as f64 * settings.beat_unit as f64 / 4.0)) as usize;
+        MetronomeClock::new(settings)
+            .click()
+            .skip(beat * samples_per_beat)
+            .take(samples_per_beat / 2)
+            .fold(0.0, |peak, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn beat_unit_sets_the_click_rate() {
+        let quarters = MetronomeSettings::default();
+        let eighths = MetronomeSettings {
+            beats_per_bar: 6,
+            beat_unit: 8,
+            ..quarters
+        };
+        assert_eq!(onsets(&quarters, 2.0).len(), 4);
+        assert_eq!(onsets(&eighths, 2.0).len(), 8);
+    }
+
+    #[test]
+    fn compound_meters_accent_each_group() {
+        let settings = MetronomeSettings {
+            beats_per_bar: 6,
+            beat_unit: 8,
+            ..MetronomeSettings::default()
+        };
+        assert!(peak(&settings, 3) > peak(&settings, 1) * 1.2);
+        assert!(peak(&settings, 4) < peak(&settings, 3));
+    }
+
+    #[test]
+    fn zero_bpm_still_advances() {
+        let settings = MetronomeSettings {
+            bpm: 0.0,
+            ..MetronomeSettings::default()
+        };
+        assert!(settings.validate().is_err());
+
+        let clock = MetronomeClock::new(&settings);
+        let _: Vec<f32> = clock.click().take(SAMPLE_RATE as usize).collect();
+        let until = clock.until_next_bar();
+        assert!(until > Duration::ZERO && until < Duration::from_secs(12), "{:?}", until);
+    }
+}
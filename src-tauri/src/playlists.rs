@@ -0,0 +1,622 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+use percent_encoding::percent_decode_str;
+use tauri::AppHandle;
+use url::Url;
+
+use crate::{
+    library::{relative_path, LibraryRoot, Song, SongRef},
+    settings::app_data_file,
+};
+
+const PLAYLISTS_FILE: &str = "playlists.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Playlist {
+    pub name: String,
+    pub tracks: Vec<SongRef>,
+}
+
+impl Playlist {
+    pub fn insert(&mut self, tracks: Vec<SongRef>, position: Option<usize>) -> Result<(), String> {
+        let position = position.unwrap_or(self.tracks.len());
+        if position > self.tracks.len() {
+            return Err(format!("Position {} is out of range for playlist '{}'.", position, self.name));
+        }
+        self.tracks.splice(position..position, tracks);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<SongRef, String> {
+        if index >= self.tracks.len() {
+            return Err(format!("Track {} is out of range for playlist '{}'.", index, self.name));
+        }
+        Ok(self.tracks.remove(index))
+    }
+
+    pub fn move_track(&mut self, from: usize, to: usize) -> Result<(), String> {
+        if to >= self.tracks.len() {
+            return Err(format!("Track {} is out of range for playlist '{}'.", to, self.name));
+        }
+        let track = self.remove(from)?;
+        self.tracks.insert(to, track);
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PlaylistStore {
+    pub playlists: Vec<Playlist>,
+}
+
+impl PlaylistStore {
+    pub fn load(app: &AppHandle) -> PlaylistStore {
+        let path = match app_data_file(app, PLAYLISTS_FILE) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("{}", e);
+                return PlaylistStore::default();
+            }
+        };
+
+        let Ok(contents) = fs::read_to_string(&path) else {
+            println!("No playlists found at {}, starting empty.", path.display());
+            return PlaylistStore::default();
+        };
+
+        match serde_json::from_str::<PlaylistStore>(&contents) {
+            Ok(store) => {
+                println!("Loaded {} playlists.", store.playlists.len());
+                store
+            }
+            Err(e) => {
+                eprintln!("Failed to parse playlists at {}: {}", path.display(), e);
+                PlaylistStore::default()
+            }
+        }
+    }
+
+    pub fn save(&self, app: &AppHandle) -> Result<(), String> {
+        let path = app_data_file(app, PLAYLISTS_FILE)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create playlists directory: {}", e))?;
+        }
+
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize playlists: {}", e))?;
+        fs::write(&path, contents).map_err(|e| format!("Failed to save playlists: {}", e))?;
+        println!("Playlists saved to {}", path.display());
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Playlist, String> {
+        self.playlists
+            .iter()
+            .find(|playlist| playlist.name == name)
+            .ok_or(format!("Playlist '{}' not found.", name))
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Result<&mut Playlist, String> {
+        self.playlists
+            .iter_mut()
+            .find(|playlist| playlist.name == name)
+            .ok_or(format!("Playlist '{}' not found.", name))
+    }
+
+    pub fn create(&mut self, name: &str, tracks: Vec<SongRef>) -> Result<&Playlist, String> {
+        let name = self.validate_name(name, None)?;
+        self.playlists.push(Playlist { name, tracks });
+        Ok(&self.playlists[self.playlists.len() - 1])
+    }
+
+    pub fn rename(&mut self, name: &str, new_name: &str) -> Result<(), String> {
+        self.get(name)?;
+        let new_name = self.validate_name(new_name, Some(name))?;
+        self.get_mut(name)?.name = new_name;
+        Ok(())
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<(), String> {
+        let count = self.playlists.len();
+        self.playlists.retain(|playlist| playlist.name != name);
+        if self.playlists.len() == count {
+            return Err(format!("Playlist '{}' not found.", name));
+        }
+        Ok(())
+    }
+
+    // Picks a free name such as "Imported (2)" for playlists created from files.
+    pub fn unique_name(&self, name: &str) -> String {
+        let mut candidate = name.to_string();
+        let mut suffix = 2;
+        while self.name_taken(&candidate, None) {
+            candidate = format!("{} ({})", name, suffix);
+            suffix += 1;
+        }
+        candidate
+    }
+
+    // `renaming` is the playlist being renamed, which may keep its name or only change its case.
+    fn validate_name(&self, name: &str, renaming: Option<&str>) -> Result<String, String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Playlist name cannot be empty.".to_string());
+        }
+        if self.name_taken(name, renaming) {
+            return Err(format!("A playlist named '{}' already exists.", name));
+        }
+        Ok(name.to_string())
+    }
+
+    // Names differing only in case would be indistinguishable in the sidebar.
+    fn name_taken(&self, name: &str, except: Option<&str>) -> bool {
+        let name = name.to_lowercase();
+        self.playlists
+            .iter()
+            .any(|playlist| Some(playlist.name.as_str()) != except && playlist.name.to_lowercase() == name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaylistFormat {
+    M3u8,
+    Pls,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    pub fn from_path(path: &Path) -> Result<PlaylistFormat, String> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        match extension.as_deref() {
+            Some("m3u") | Some("m3u8") => Ok(PlaylistFormat::M3u8),
+            Some("pls") => Ok(PlaylistFormat::Pls),
+            Some("xspf") => Ok(PlaylistFormat::Xspf),
+            _ => Err(format!("Unsupported playlist format: {}", path.display())),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PlaylistImport {
+    pub playlist: Playlist,
+    pub missing: Vec<String>,
+}
+
+// Returns the playlist's tracks as library references, along with the
+// locations that could not be matched to any library root.
+pub fn import_playlist_file(
+    file: &Path,
+    roots: &[LibraryRoot],
+    songs: &[Song],
+) -> Result<(Vec<SongRef>, Vec<String>), String> {
+    let format = PlaylistFormat::from_path(file)?;
+    let bytes = fs::read(file).map_err(|e| format!("Failed to read playlist {}: {}", file.display(), e))?;
+    let is_m3u8 = file.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("m3u8"));
+    let contents = decode_playlist(bytes, is_m3u8)
+        .map_err(|e| format!("Failed to read playlist {}: {}", file.display(), e))?;
+    let base_dir = file.parent().unwrap_or(Path::new(""));
+
+    let locations = match format {
+        PlaylistFormat::M3u8 => parse_m3u(&contents),
+        PlaylistFormat::Pls => parse_pls(&contents),
+        PlaylistFormat::Xspf => parse_xspf(&contents),
+    };
+
+    let mut tracks = Vec::new();
+    let mut missing = Vec::new();
+    for location in locations {
+        match resolve_location(&location, format, base_dir, roots, songs) {
+            Some(track) => tracks.push(track),
+            None => missing.push(location),
+        }
+    }
+    Ok((tracks, missing))
+}
+
+pub fn export_playlist_file(
+    file: &Path,
+    format: PlaylistFormat,
+    entries: &[(PathBuf, Song)],
+) -> Result<(), String> {
+    let base_dir = file.parent().unwrap_or(Path::new(""));
+    let contents = match format {
+        PlaylistFormat::M3u8 => write_m3u(base_dir, entries),
+        PlaylistFormat::Pls => write_pls(base_dir, entries),
+        PlaylistFormat::Xspf => write_xspf(entries),
+    };
+
+    fs::write(file, contents).map_err(|e| format!("Failed to write playlist {}: {}", file.display(), e))?;
+    println!("Exported {} tracks to {}", entries.len(), file.display());
+    Ok(())
+}
+
+// Plain .m3u and .pls files are often written in the system's legacy code page,
+// so anything that is not valid UTF-8 is read as Latin-1. M3U8 is UTF-8 by definition.
+fn decode_playlist(bytes: Vec<u8>, is_m3u8: bool) -> Result<String, String> {
+    match String::from_utf8(bytes) {
+        Ok(contents) => Ok(contents),
+        Err(_) if is_m3u8 => Err("the file is not valid UTF-8".to_string()),
+        Err(e) => Ok(e.into_bytes().into_iter().map(char::from).collect()),
+    }
+}
+
+fn parse_m3u(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(|line| line.trim_start_matches('\u{feff}').trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+fn parse_pls(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .filter_map(|line| line.trim().split_once('='))
+        .filter(|(key, _)| key.trim().to_lowercase().starts_with("file"))
+        .map(|(_, value)| value.trim().to_string())
+        .collect()
+}
+
+fn parse_xspf(contents: &str) -> Vec<String> {
+    let mut locations = Vec::new();
+    let mut rest = contents;
+    while let Some(start) = rest.find("<location>") {
+        rest = &rest[start + "<location>".len()..];
+        let Some(end) = rest.find("</location>") else {
+            break;
+        };
+        locations.push(xml_unescape(rest[..end].trim()));
+        rest = &rest[end..];
+    }
+    locations
+}
+
+fn resolve_location(
+    location: &str,
+    format: PlaylistFormat,
+    base_dir: &Path,
+    roots: &[LibraryRoot],
+    songs: &[Song],
+) -> Option<SongRef> {
+    let path = location_to_path(location, format, base_dir);
+    if let Some(path) = path {
+        let path = normalize(&path);
+        for root in roots {
+            if let Some(relative) = relative_path(&normalize(Path::new(&root.path)), &path) {
+                if let Some(track) = find_track(songs, &root.name, &relative) {
+                    return Some(track);
+                }
+            }
+        }
+    }
+
+    // Playlists written by other tools often hold paths relative to the music folder itself.
+    let relative = match format {
+        PlaylistFormat::Xspf => percent_decode_str(location).decode_utf8_lossy().into_owned(),
+        _ => location.to_string(),
+    };
+    let relative = relative.replace('\\', "/");
+    roots
+        .iter()
+        .find_map(|root| find_track(songs, &root.name, relative.trim_start_matches("./")))
+}
+
+fn location_to_path(location: &str, format: PlaylistFormat, base_dir: &Path) -> Option<PathBuf> {
+    if location.starts_with("file:") {
+        return Url::parse(location).ok()?.to_file_path().ok();
+    }
+    if location.contains("://") {
+        return None;
+    }
+
+    if format == PlaylistFormat::Xspf {
+        // XSPF locations are URIs, so relative ones are percent-encoded.
+        let base = Url::from_directory_path(base_dir).ok()?;
+        return base.join(location).ok()?.to_file_path().ok();
+    }
+
+    let path = PathBuf::from(location);
+    if path.is_absolute() {
+        Some(path)
+    } else {
+        Some(base_dir.join(path))
+    }
+}
+
+fn find_track(songs: &[Song], root: &str, path: &str) -> Option<SongRef> {
+    songs
+        .iter()
+        .find(|song| song.root == root && song.path == path)
+        .map(|song| SongRef {
+            root: song.root.clone(),
+            path: song.path.clone(),
+        })
+}
+
+// Resolves `.` and `..` without touching the filesystem, since playlist
+// entries may point at files that no longer exist.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+fn relative_location(base_dir: &Path, target: &Path) -> String {
+    let base: Vec<Component> = base_dir.components().collect();
+    let target_parts: Vec<Component> = target.components().collect();
+    let common = base
+        .iter()
+        .zip(&target_parts)
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return target.to_string_lossy().into_owned();
+    }
+
+    let mut parts = vec!["..".to_string(); base.len() - common];
+    parts.extend(
+        target_parts[common..]
+            .iter()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned()),
+    );
+    parts.join("/")
+}
+
+fn display_title(song: &Song) -> String {
+    match &song.artist {
+        Some(artist) => format!("{} - {}", artist, song.title),
+        None => song.title.clone(),
+    }
+}
+
+fn length_seconds(song: &Song) -> i64 {
+    song.duration.map_or(-1, |duration| duration.round() as i64)
+}
+
+fn write_m3u(base_dir: &Path, entries: &[(PathBuf, Song)]) -> String {
+    let mut contents = String::from("#EXTM3U\n");
+    for (path, song) in entries {
+        contents.push_str(&format!("#EXTINF:{},{}\n", length_seconds(song), display_title(song)));
+        contents.push_str(&relative_location(base_dir, path));
+        contents.push('\n');
+    }
+    contents
+}
+
+fn write_pls(base_dir: &Path, entries: &[(PathBuf, Song)]) -> String {
+    let mut contents = String::from("[playlist]\n");
+    for (i, (path, song)) in entries.iter().enumerate() {
+        let number = i + 1;
+        contents.push_str(&format!("File{}={}\n", number, relative_location(base_dir, path)));
+        contents.push_str(&format!("Title{}={}\n", number, display_title(song)));
+        contents.push_str(&format!("Length{}={}\n", number, length_seconds(song)));
+    }
+    contents.push_str(&format!("NumberOfEntries={}\nVersion=2\n", entries.len()));
+    contents
+}
+
+fn write_xspf(entries: &[(PathBuf, Song)]) -> String {
+    let mut contents = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <trackList>\n",
+    );
+    for (path, song) in entries {
+        let location = Url::from_file_path(path)
+            .map(|url| url.to_string())
+            .unwrap_or_else(|_| path.to_string_lossy().into_owned());
+        contents.push_str("    <track>\n");
+        contents.push_str(&format!("      <location>{}</location>\n", xml_escape(&location)));
+        contents.push_str(&format!("      <title>{}</title>\n", xml_escape(&song.title)));
+        if let Some(artist) = &song.artist {
+            contents.push_str(&format!("      <creator>{}</creator>\n", xml_escape(artist)));
+        }
+        if let Some(album) = &song.album {
+            contents.push_str(&format!("      <album>{}</album>\n", xml_escape(album)));
+        }
+        if let Some(duration) = song.duration {
+            contents.push_str(&format!("      <duration>{}</duration>\n", (duration * 1000.0).round() as u64));
+        }
+        contents.push_str("    </track>\n");
+    }
+    contents.push_str("  </trackList>\n</playlist>\n");
+    contents
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Decodes the predefined entities and numeric character references in one pass,
+// leaving anything unrecognised as it was.
+fn xml_unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').and_then(|end| {
+            let character = match &rest[1..end] {
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                "amp" => '&',
+                entity => {
+                    let code = match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => entity.strip_prefix('#')?.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((character, end))
+        });
+        match decoded {
+            Some((character, end)) => {
+                unescaped.push(character);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::song;
+
+    fn music_dir() -> PathBuf {
+        std::env::temp_dir().join("Music")
+    }
+
+    fn roots() -> Vec<LibraryRoot> {
+        vec![LibraryRoot { name: "main".to_string(), path: music_dir().to_string_lossy().into_owned() }]
+    }
+
+    fn library() -> Vec<Song> {
+        vec![
+            Song { path: "Björk/Army of Me.flac".to_string(), ..song("Army of Me") },
+            Song { path: "AC & DC/T.N.T.mp3".to_string(), ..song("T.N.T") },
+        ]
+    }
+
+    fn song_ref(path: &str) -> SongRef {
+        SongRef { root: "main".to_string(), path: path.to_string() }
+    }
+
+    fn resolve(location: &str, format: PlaylistFormat, base_dir: &Path) -> Option<SongRef> {
+        resolve_location(location, format, base_dir, &roots(), &library())
+    }
+
+    fn resolve_all(locations: &[String], format: PlaylistFormat, base_dir: &Path) -> Vec<SongRef> {
+        locations.iter().filter_map(|location| resolve(location, format, base_dir)).collect()
+    }
+
+    #[test]
+    fn parses_m3u_entries() {
+        let contents = "\u{feff}#EXTM3U\n#EXTINF:123,Björk - Army of Me\nBjörk/Army of Me.flac\n\
+                        \n  AC & DC/T.N.T.mp3 \n";
+        assert_eq!(parse_m3u(contents), ["Björk/Army of Me.flac", "AC & DC/T.N.T.mp3"]);
+    }
+
+    #[test]
+    fn parses_pls_entries() {
+        let contents = "[playlist]\nFile1=a.flac\nTitle1=A\nfile2 = b.mp3\nLength2=10\nNumberOfEntries=2\n";
+        assert_eq!(parse_pls(contents), ["a.flac", "b.mp3"]);
+    }
+
+    #[test]
+    fn parses_xspf_entities() {
+        let contents = "<trackList><track><location>AC%20&amp;%20DC/T.N.T.mp3</location></track>\
+                        <track><location> caf&#233;&#x2F;x.flac </location></track></trackList>";
+        assert_eq!(parse_xspf(contents), ["AC%20&%20DC/T.N.T.mp3", "café/x.flac"]);
+    }
+
+    #[test]
+    fn leaves_unknown_entities_alone() {
+        assert_eq!(xml_unescape("a & b &nbsp; &#xZZ; &amp;lt;"), "a & b &nbsp; &#xZZ; &lt;");
+    }
+
+    #[test]
+    fn decodes_latin1_unless_m3u8() {
+        let bytes = b"Bj\xf6rk/Army of Me.flac".to_vec();
+        assert_eq!(decode_playlist(bytes.clone(), false).unwrap(), "Björk/Army of Me.flac");
+        assert!(decode_playlist(bytes, true).is_err());
+        assert_eq!(decode_playlist("Björk".as_bytes().to_vec(), false).unwrap(), "Björk");
+    }
+
+    #[test]
+    fn resolves_locations_against_roots() {
+        let playlists = music_dir().join("Playlists");
+        let absolute = music_dir().join("Björk").join("Army of Me.flac");
+        assert_eq!(
+            resolve(&absolute.to_string_lossy(), PlaylistFormat::M3u8, Path::new("")),
+            Some(song_ref("Björk/Army of Me.flac"))
+        );
+        assert_eq!(
+            resolve("../AC & DC/T.N.T.mp3", PlaylistFormat::Pls, &playlists),
+            Some(song_ref("AC & DC/T.N.T.mp3"))
+        );
+        let url = Url::from_file_path(&absolute).unwrap().to_string();
+        assert!(url.contains("Bj%C3%B6rk") && url.contains("Army%20of%20Me"));
+        assert_eq!(resolve(&url, PlaylistFormat::M3u8, Path::new("")), Some(song_ref("Björk/Army of Me.flac")));
+        assert_eq!(
+            resolve("../AC%20&%20DC/T.N.T.mp3", PlaylistFormat::Xspf, &playlists),
+            Some(song_ref("AC & DC/T.N.T.mp3"))
+        );
+    }
+
+    #[test]
+    fn falls_back_to_paths_relative_to_a_root() {
+        let elsewhere = std::env::temp_dir().join("Downloads");
+        let expected = Some(song_ref("AC & DC/T.N.T.mp3"));
+        assert_eq!(resolve("./AC & DC\\T.N.T.mp3", PlaylistFormat::M3u8, &elsewhere), expected);
+        assert_eq!(resolve("AC%20&%20DC/T.N.T.mp3", PlaylistFormat::Xspf, &elsewhere), expected);
+        assert_eq!(resolve("Missing/song.mp3", PlaylistFormat::M3u8, &elsewhere), None);
+        assert_eq!(resolve("http://example.com/stream.mp3", PlaylistFormat::Pls, &elsewhere), None);
+    }
+
+    #[test]
+    fn relative_locations_round_trip() {
+        let entries: Vec<(PathBuf, Song)> =
+            library().into_iter().map(|song| (music_dir().join(&song.path), song)).collect();
+        let expected = [song_ref("Björk/Army of Me.flac"), song_ref("AC & DC/T.N.T.mp3")];
+
+        for base_dir in [music_dir(), music_dir().join("Playlists").join("Party")] {
+            let m3u = parse_m3u(&write_m3u(&base_dir, &entries));
+            assert_eq!(resolve_all(&m3u, PlaylistFormat::M3u8, &base_dir), expected);
+            let pls = parse_pls(&write_pls(&base_dir, &entries));
+            assert_eq!(resolve_all(&pls, PlaylistFormat::Pls, &base_dir), expected);
+        }
+
+        assert_eq!(
+            relative_location(&music_dir().join("Playlists"), &music_dir().join("Björk").join("Army of Me.flac")),
+            "../Björk/Army of Me.flac"
+        );
+        let xspf = parse_xspf(&write_xspf(&entries));
+        assert_eq!(resolve_all(&xspf, PlaylistFormat::Xspf, Path::new("")), expected);
+    }
+
+    #[test]
+    fn renames_to_the_same_name_in_another_case() {
+        let mut store = PlaylistStore::default();
+        store.create("Drums", Vec::new()).unwrap();
+        store.create("Bass", Vec::new()).unwrap();
+
+        store.rename("Drums", "Drums").unwrap();
+        store.rename("Drums", " drums ").unwrap();
+        assert_eq!(store.get("drums").unwrap().name, "drums");
+        assert!(store.rename("drums", "BASS").is_err());
+        assert!(store.rename("Keys", "Piano").is_err());
+        assert!(store.create("bass", Vec::new()).is_err());
+        assert_eq!(store.unique_name("bass"), "bass (2)");
+    }
+}
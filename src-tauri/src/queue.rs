@@ -0,0 +1,269 @@
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::library::Song;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RepeatMode {
+    Off,
+    One,
+    All,
+}
+
+#[derive(Serialize, Clone)]
+pub struct QueueSnapshot {
+    pub tracks: Vec<Song>,
+    pub position: Option<usize>,
+    pub shuffle: bool,
+    pub repeat: RepeatMode,
+}
+
+#[derive(Clone)]
+pub struct PlayQueue {
+    tracks: Vec<Song>,
+    // Indices into `tracks` in the order they will be played.
+    order: Vec<usize>,
+    position: Option<usize>,
+    shuffle: bool,
+    repeat: RepeatMode,
+}
+
+impl Default for PlayQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PlayQueue {
+    pub fn new() -> Self {
+        PlayQueue {
+            tracks: Vec::new(),
+            order: Vec::new(),
+            position: None,
+            shuffle: false,
+            repeat: RepeatMode::Off,
+        }
+    }
+
+    pub fn current(&self) -> Option<&Song> {
+        self.position
+            .and_then(|pos| self.order.get(pos))
+            .map(|&index| &self.tracks[index])
+    }
+
+    pub fn enqueue(&mut self, songs: Vec<Song>) {
+        let first_new = self.tracks.len();
+        self.tracks.extend(songs);
+        let mut added: Vec<usize> = (first_new..self.tracks.len()).collect();
+        if self.shuffle {
+            added.shuffle(&mut rand::rng());
+        }
+        self.order.extend(added);
+    }
+
+    pub fn replace(&mut self, songs: Vec<Song>, start: usize) -> Option<Song> {
+        self.tracks = songs;
+        self.order = (0..self.tracks.len()).collect();
+        self.position = None;
+        if start >= self.tracks.len() {
+            return None;
+        }
+
+        self.position = Some(start);
+        if self.shuffle {
+            self.reshuffle();
+        }
+        self.current().cloned()
+    }
+
+    pub fn jump_to(&mut self, song: Song) {
+        if let Some(pos) = self
+            .order
+            .iter()
+            .position(|&index| self.tracks[index].root == song.root && self.tracks[index].path == song.path)
+        {
+            self.position = Some(pos);
+            return;
+        }
+
+        self.tracks.push(song);
+        let insert_at = self.position.map_or(self.order.len(), |pos| pos + 1);
+        self.order.insert(insert_at, self.tracks.len() - 1);
+        self.position = Some(insert_at);
+    }
+
+    pub fn next(&mut self) -> Option<Song> {
+        if self.order.is_empty() {
+            return None;
+        }
+
+        let next = match self.position {
+            None => 0,
+            Some(pos) if pos + 1 < self.order.len() => pos + 1,
+            Some(_) if self.repeat == RepeatMode::All => 0,
+            Some(_) => return None,
+        };
+        self.position = Some(next);
+        self.current().cloned()
+    }
+
+    pub fn upcoming(&self) -> Option<&Song> {
+        if self.repeat == RepeatMode::One && self.current().is_some() {
+            return self.current();
+        }
+
+        let next = match self.position {
+            None => 0,
+            Some(pos) if pos + 1 < self.order.len() => pos + 1,
+            Some(_) if self.repeat == RepeatMode::All => 0,
+            Some(_) => return None,
+        };
+        self.order.get(next).map(|&index| &self.tracks[index])
+    }
+
+    pub fn advance(&mut self) -> Option<Song> {
+        if self.repeat == RepeatMode::One && self.current().is_some() {
+            return self.current().cloned();
+        }
+        self.next()
+    }
+
+    pub fn previous(&mut self) -> Option<Song> {
+        if self.order.is_empty() {
+            return None;
+        }
+
+        let previous = match self.position {
+            Some(pos) if pos > 0 => pos - 1,
+            _ if self.repeat == RepeatMode::All => self.order.len() - 1,
+            _ => 0,
+        };
+        self.position = Some(previous);
+        self.current().cloned()
+    }
+
+    pub fn clear(&mut self) {
+        self.tracks.clear();
+        self.order.clear();
+        self.position = None;
+    }
+
+    pub fn set_shuffle(&mut self, enabled: bool) {
+        if self.shuffle == enabled {
+            return;
+        }
+        self.shuffle = enabled;
+
+        if enabled {
+            self.reshuffle();
+        } else {
+            let current = self.position.map(|pos| self.order[pos]);
+            self.order = (0..self.tracks.len()).collect();
+            self.position = current;
+        }
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.repeat = repeat;
+    }
+
+    pub fn snapshot(&self) -> QueueSnapshot {
+        QueueSnapshot {
+            tracks: self.order.iter().map(|&index| self.tracks[index].clone()).collect(),
+            position: self.position,
+            shuffle: self.shuffle,
+            repeat: self.repeat,
+        }
+    }
+
+    // Keeps the current track at the front so shuffling never interrupts playback.
+    fn reshuffle(&mut self) {
+        let current = self.position.map(|pos| self.order[pos]);
+        let mut rest: Vec<usize> = (0..self.tracks.len())
+            .filter(|&index| Some(index) != current)
+            .collect();
+        rest.shuffle(&mut rand::rng());
+
+        self.order = current.into_iter().chain(rest).collect();
+        self.position = current.map(|_| 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{song, songs, titles};
+
+    #[test]
+    fn next_and_previous_walk_the_queue() {
+        let mut queue = PlayQueue::new();
+        assert_eq!(titles(&queue.replace(songs(&["a", "b", "c"]), 0)), ["a"]);
+        assert_eq!(titles(&queue.next()), ["b"]);
+        assert_eq!(titles(&queue.next()), ["c"]);
+        assert!(queue.next().is_none());
+        assert_eq!(titles(&queue.previous()), ["b"]);
+    }
+
+    #[test]
+    fn repeat_all_wraps_in_both_directions() {
+        let mut queue = PlayQueue::new();
+        queue.replace(songs(&["a", "b"]), 1);
+        queue.set_repeat(RepeatMode::All);
+        assert_eq!(titles(queue.upcoming()), ["a"]);
+        assert_eq!(titles(&queue.next()), ["a"]);
+        assert_eq!(titles(&queue.previous()), ["b"]);
+    }
+
+    #[test]
+    fn repeat_one_keeps_the_current_track() {
+        let mut queue = PlayQueue::new();
+        queue.replace(songs(&["a", "b"]), 0);
+        queue.set_repeat(RepeatMode::One);
+        assert_eq!(titles(queue.upcoming()), ["a"]);
+        assert_eq!(titles(&queue.advance()), ["a"]);
+        // Skipping still moves on.
+        assert_eq!(titles(&queue.next()), ["b"]);
+    }
+
+    #[test]
+    fn shuffle_keeps_the_current_track_and_every_song() {
+        let mut queue = PlayQueue::new();
+        queue.replace(songs(&["a", "b", "c", "d", "e"]), 2);
+        queue.set_shuffle(true);
+
+        let snapshot = queue.snapshot();
+        assert_eq!(snapshot.position, Some(0));
+        assert_eq!(snapshot.tracks[0].title, "c");
+        let mut shuffled = titles(&snapshot.tracks);
+        shuffled.sort();
+        assert_eq!(shuffled, ["a", "b", "c", "d", "e"]);
+
+        queue.set_shuffle(false);
+        let snapshot = queue.snapshot();
+        assert_eq!(snapshot.position, Some(2));
+        assert_eq!(snapshot.tracks[2].title, "c");
+    }
+
+    #[test]
+    fn jump_to_inserts_unknown_songs_after_the_current_one() {
+        let mut queue = PlayQueue::new();
+        queue.replace(songs(&["a", "b"]), 0);
+        queue.jump_to(song("x"));
+        assert_eq!(titles(queue.current()), ["x"]);
+        assert_eq!(titles(&queue.next()), ["b"]);
+
+        queue.jump_to(song("a"));
+        assert_eq!(queue.snapshot().tracks.len(), 3);
+        assert_eq!(titles(queue.current()), ["a"]);
+    }
+
+    #[test]
+    fn enqueue_appends_to_the_end() {
+        let mut queue = PlayQueue::default();
+        queue.enqueue(songs(&["a"]));
+        assert_eq!(titles(&queue.next()), ["a"]);
+        queue.enqueue(songs(&["b"]));
+        assert_eq!(titles(&queue.next()), ["b"]);
+    }
+}
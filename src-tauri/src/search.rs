@@ -0,0 +1,234 @@
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+use crate::library::Song;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    Relevance,
+    Name,
+    DateAdded,
+    Duration,
+    Bpm,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct LibraryQuery {
+    pub text: Option<String>,
+    pub extensions: Vec<String>,
+    pub min_duration: Option<f64>,
+    pub max_duration: Option<f64>,
+    pub min_bpm: Option<f32>,
+    pub max_bpm: Option<f32>,
+    pub key: Option<String>,
+    pub roots: Vec<String>,
+    // Defaults to relevance when there is search text, otherwise name.
+    pub sort: Option<SortField>,
+    pub descending: bool,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SearchResults {
+    pub songs: Vec<Song>,
+    pub total: usize,
+}
+
+pub fn search_songs(songs: &[Song], query: &LibraryQuery) -> SearchResults {
+    let matcher = SkimMatcherV2::default().ignore_case();
+    let text = query.text.as_deref().map(str::trim).filter(|text| !text.is_empty());
+    let extensions: Vec<String> = query
+        .extensions
+        .iter()
+        .map(|ext| ext.trim_start_matches('.').to_lowercase())
+        .collect();
+
+    let mut matches: Vec<(i64, &Song)> = songs
+        .iter()
+        .filter(|song| matches_filters(song, query, &extensions))
+        .filter_map(|song| match text {
+            Some(text) => fuzzy_score(&matcher, song, text).map(|score| (score, song)),
+            None => Some((0, song)),
+        })
+        .collect();
+
+    let sort = query
+        .sort
+        .unwrap_or(if text.is_some() { SortField::Relevance } else { SortField::Name });
+    matches.sort_by(|(score_a, a), (score_b, b)| match sort {
+        SortField::Relevance => {
+            let ordering = score_b.cmp(score_a);
+            let ordering = if query.descending { ordering.reverse() } else { ordering };
+            ordering.then_with(|| compare_names(a, b))
+        }
+        _ => compare_songs(a, b, sort, query.descending),
+    });
+
+    let total = matches.len();
+    let songs = matches
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit.unwrap_or(usize::MAX))
+        .map(|(_, song)| song.clone())
+        .collect();
+
+    SearchResults { songs, total }
+}
+
+fn matches_filters(song: &Song, query: &LibraryQuery, extensions: &[String]) -> bool {
+    if !query.roots.is_empty() && !query.roots.contains(&song.root) {
+        return false;
+    }
+
+    if !extensions.is_empty() && !song.extension().is_some_and(|extension| extensions.contains(&extension)) {
+        return false;
+    }
+
+    if let Some(key) = &query.key {
+        if !song.key.as_ref().is_some_and(|song_key| song_key.eq_ignore_ascii_case(key.trim())) {
+            return false;
+        }
+    }
+
+    in_range(song.duration, query.min_duration, query.max_duration)
+        && in_range(song.bpm, query.min_bpm, query.max_bpm)
+}
+
+// Songs without a value are left out as soon as either bound is set.
+pub fn in_range<T: PartialOrd>(value: Option<T>, min: Option<T>, max: Option<T>) -> bool {
+    if min.is_none() && max.is_none() {
+        return true;
+    }
+    let Some(value) = value else {
+        return false;
+    };
+    min.map_or(true, |min| value >= min) && max.map_or(true, |max| value <= max)
+}
+
+fn fuzzy_score(matcher: &SkimMatcherV2, song: &Song, text: &str) -> Option<i64> {
+    [
+        Some(&song.title),
+        Some(&song.path),
+        song.artist.as_ref(),
+        song.album.as_ref(),
+        song.genre.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .filter_map(|field| matcher.fuzzy_match(field, text))
+    .max()
+}
+
+// Relevance only means something for text searches, so it compares as equal here.
+pub fn compare_songs(a: &Song, b: &Song, sort: SortField, descending: bool) -> Ordering {
+    let ordering = match sort {
+        SortField::Relevance => Ordering::Equal,
+        SortField::Name => directed(compare_names(a, b), descending),
+        SortField::DateAdded => directed(a.date_added.cmp(&b.date_added), descending),
+        SortField::Duration => compare_missing_last(a.duration, b.duration, descending),
+        SortField::Bpm => compare_missing_last(a.bpm, b.bpm, descending),
+    };
+    ordering.then_with(|| compare_names(a, b))
+}
+
+fn directed(ordering: Ordering, descending: bool) -> Ordering {
+    if descending {
+        ordering.reverse()
+    } else {
+        ordering
+    }
+}
+
+fn compare_names(a: &Song, b: &Song) -> Ordering {
+    a.title
+        .to_lowercase()
+        .cmp(&b.title.to_lowercase())
+        .then_with(|| a.path.cmp(&b.path))
+}
+
+// Songs without a value stay last whichever way the list is sorted.
+fn compare_missing_last<T: PartialOrd>(a: Option<T>, b: Option<T>, descending: bool) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => directed(a.partial_cmp(&b).unwrap_or(Ordering::Equal), descending),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{song, titles};
+
+    fn library() -> Vec<Song> {
+        vec![
+            Song { bpm: Some(120.0), duration: Some(1.5), ..song("Kick") },
+            Song { duration: Some(0.5), ..song("snare") },
+            Song { bpm: Some(90.0), ..song("Bass Loop") },
+            Song { bpm: Some(140.0), duration: Some(8.0), ..song("Arp") },
+        ]
+    }
+
+    #[test]
+    fn sorts_by_name_without_text() {
+        let results = search_songs(&library(), &LibraryQuery::default());
+        assert_eq!(titles(&results.songs), ["Arp", "Bass Loop", "Kick", "snare"]);
+        assert_eq!(results.total, 4);
+    }
+
+    #[test]
+    fn missing_values_sort_last_in_both_directions() {
+        let mut query = LibraryQuery { sort: Some(SortField::Bpm), ..LibraryQuery::default() };
+        assert_eq!(titles(&search_songs(&library(), &query).songs), ["Bass Loop", "Kick", "Arp", "snare"]);
+
+        query.descending = true;
+        assert_eq!(titles(&search_songs(&library(), &query).songs), ["Arp", "Kick", "Bass Loop", "snare"]);
+
+        query.sort = Some(SortField::Duration);
+        assert_eq!(titles(&search_songs(&library(), &query).songs), ["Arp", "Kick", "snare", "Bass Loop"]);
+    }
+
+    #[test]
+    fn ranks_text_matches_by_relevance() {
+        let query = LibraryQuery { text: Some(" kick ".to_string()), ..LibraryQuery::default() };
+        let results = search_songs(&library(), &query);
+        assert_eq!(titles(&results.songs), ["Kick"]);
+    }
+
+    #[test]
+    fn filters_by_range_extension_and_root() {
+        let mut songs = library();
+        songs[0].path = "Kick.flac".to_string();
+        songs[3].root = "other".to_string();
+
+        let query = LibraryQuery { min_bpm: Some(100.0), ..LibraryQuery::default() };
+        assert_eq!(titles(&search_songs(&songs, &query).songs), ["Arp", "Kick"]);
+
+        let query = LibraryQuery { extensions: vec![".FLAC".to_string()], ..LibraryQuery::default() };
+        assert_eq!(titles(&search_songs(&songs, &query).songs), ["Kick"]);
+
+        let query = LibraryQuery { roots: vec!["other".to_string()], ..LibraryQuery::default() };
+        assert_eq!(titles(&search_songs(&songs, &query).songs), ["Arp"]);
+    }
+
+    #[test]
+    fn pages_results_but_reports_the_full_total() {
+        let query = LibraryQuery { offset: 1, limit: Some(2), ..LibraryQuery::default() };
+        let results = search_songs(&library(), &query);
+        assert_eq!(titles(&results.songs), ["Bass Loop", "Kick"]);
+        assert_eq!(results.total, 4);
+    }
+
+    #[test]
+    fn in_range_excludes_missing_values_only_when_bounded() {
+        assert!(in_range::<f32>(None, None, None));
+        assert!(!in_range(None, Some(1.0), None));
+        assert!(in_range(Some(5.0), Some(1.0), Some(5.0)));
+        assert!(!in_range(Some(5.5), None, Some(5.0)));
+    }
+}
@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf};
+use tauri::{AppHandle, Manager};
+
+use crate::{
+    audio::Normalization, effects::EffectsSettings, library::{LibraryFilter, ScanOptions},
+    metronome::MetronomeSettings, stretch::TempoSettings,
+};
+
+pub const MAX_CROSSFADE_SECONDS: f32 = 12.0;
+pub const MAX_PREAMP_DB: f32 = 15.0;
+
+const SETTINGS_FILE: &str = "settings.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Settings {
+    pub crossfade_seconds: f32,
+    pub output_device: Option<String>,
+    pub scan: ScanOptions,
+    pub normalization: Normalization,
+    pub effects: EffectsSettings,
+    pub tempo: TempoSettings,
+    pub metronome: MetronomeSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            crossfade_seconds: 0.0,
+            output_device: None,
+            scan: ScanOptions::default(),
+            normalization: Normalization::default(),
+            effects: EffectsSettings::default(),
+            tempo: TempoSettings::default(),
+            metronome: MetronomeSettings::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(app: &AppHandle) -> Settings {
+        let path = match settings_path(app) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("{}", e);
+                return Settings::default();
+            }
+        };
+
+        let Ok(contents) = fs::read_to_string(&path) else {
+            println!("No settings found at {}, using defaults.", path.display());
+            return Settings::default();
+        };
+
+        match serde_json::from_str::<Settings>(&contents) {
+            Ok(mut settings) => {
+                settings.crossfade_seconds = settings.crossfade_seconds.clamp(0.0, MAX_CROSSFADE_SECONDS);
+                settings.normalization.preamp_db = settings.normalization.preamp_db.clamp(-MAX_PREAMP_DB, MAX_PREAMP_DB);
+                if let Err(e) = settings.effects.validate() {
+                    eprintln!("Ignoring invalid effects settings: {}", e);
+                    settings.effects = EffectsSettings::default();
+                }
+                if let Err(e) = settings.tempo.validate() {
+                    eprintln!("Ignoring invalid tempo settings: {}", e);
+                    settings.tempo = TempoSettings::default();
+                }
+                if let Err(e) = settings.metronome.validate() {
+                    eprintln!("Ignoring invalid metronome settings: {}", e);
+                    settings.metronome = MetronomeSettings::default();
+                }
+                if let Err(e) = LibraryFilter::new(&settings.scan) {
+                    eprintln!("Ignoring invalid scan options: {}", e);
+                    settings.scan = ScanOptions::default();
+                }
+                settings
+            }
+            Err(e) => {
+                eprintln!("Failed to parse settings at {}: {}", path.display(), e);
+                Settings::default()
+            }
+        }
+    }
+
+    pub fn save(&self, app: &AppHandle) -> Result<(), String> {
+        let path = settings_path(app)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create settings directory: {}", e))?;
+        }
+
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize settings: {}", e))?;
+        fs::write(&path, contents).map_err(|e| format!("Failed to save settings: {}", e))?;
+        println!("Settings saved to {}", path.display());
+        Ok(())
+    }
+}
+
+fn settings_path(app: &AppHandle) -> Result<PathBuf, String> {
+    app_data_file(app, SETTINGS_FILE)
+}
+
+pub fn app_data_file(app: &AppHandle, file_name: &str) -> Result<PathBuf, String> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))?;
+    Ok(dir.join(file_name))
+}
@@ -0,0 +1,330 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs};
+use tauri::AppHandle;
+
+use crate::{
+    library::{unix_now, Song, SongRef},
+    search::{compare_songs, in_range, SortField},
+    settings::app_data_file,
+};
+
+const SMART_PLAYLISTS_FILE: &str = "smart_playlists.json";
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TagField {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Path,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Rule {
+    Extension { extensions: Vec<String> },
+    Duration { min: Option<f64>, max: Option<f64> },
+    Bpm { min: Option<f32>, max: Option<f32> },
+    Key { key: String },
+    Year { min: Option<u32>, max: Option<u32> },
+    AddedWithin { days: f64 },
+    Root { roots: Vec<String> },
+    Contains { field: TagField, value: String },
+}
+
+impl Rule {
+    fn matches(&self, song: &Song, now: u64) -> bool {
+        match self {
+            Rule::Extension { extensions } => song.extension().is_some_and(|extension| {
+                extensions
+                    .iter()
+                    .any(|ext| ext.trim_start_matches('.').eq_ignore_ascii_case(&extension))
+            }),
+            Rule::Duration { min, max } => in_range(song.duration, *min, *max),
+            Rule::Bpm { min, max } => in_range(song.bpm, *min, *max),
+            Rule::Key { key } => song
+                .key
+                .as_ref()
+                .is_some_and(|song_key| song_key.eq_ignore_ascii_case(key.trim())),
+            Rule::Year { min, max } => in_range(song.year, *min, *max),
+            Rule::AddedWithin { days } => {
+                song.date_added > 0 && (now.saturating_sub(song.date_added) as f64) <= days * SECONDS_PER_DAY
+            }
+            Rule::Root { roots } => roots.contains(&song.root),
+            Rule::Contains { field, value } => {
+                let text = match field {
+                    TagField::Title => Some(&song.title),
+                    TagField::Artist => song.artist.as_ref(),
+                    TagField::Album => song.album.as_ref(),
+                    TagField::Genre => song.genre.as_ref(),
+                    TagField::Path => Some(&song.path),
+                };
+                text.is_some_and(|text| text.to_lowercase().contains(&value.to_lowercase()))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SmartPlaylist {
+    pub name: String,
+    pub rules: Vec<Rule>,
+    // When false a song only needs to match one of the rules.
+    #[serde(default = "default_match_all")]
+    pub match_all: bool,
+    #[serde(default)]
+    pub sort: Option<SortField>,
+    #[serde(default)]
+    pub descending: bool,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+fn default_match_all() -> bool {
+    true
+}
+
+impl SmartPlaylist {
+    pub fn evaluate(&self, songs: &[Song]) -> Vec<Song> {
+        let now = unix_now();
+
+        let mut matches: Vec<&Song> = songs
+            .iter()
+            .filter(|song| {
+                if self.match_all {
+                    self.rules.iter().all(|rule| rule.matches(song, now))
+                } else {
+                    self.rules.iter().any(|rule| rule.matches(song, now))
+                }
+            })
+            .collect();
+
+        let sort = self.sort.unwrap_or(SortField::Name);
+        matches.sort_by(|a, b| compare_songs(a, b, sort, self.descending));
+        matches
+            .into_iter()
+            .take(self.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SmartPlaylistChange {
+    pub name: String,
+    pub tracks: Vec<SongRef>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SmartPlaylistStore {
+    pub playlists: Vec<SmartPlaylist>,
+    // Last evaluated tracks per playlist, used to tell which ones changed.
+    #[serde(skip)]
+    results: HashMap<String, Vec<SongRef>>,
+}
+
+impl SmartPlaylistStore {
+    pub fn load(app: &AppHandle) -> SmartPlaylistStore {
+        let path = match app_data_file(app, SMART_PLAYLISTS_FILE) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("{}", e);
+                return SmartPlaylistStore::default();
+            }
+        };
+
+        let Ok(contents) = fs::read_to_string(&path) else {
+            println!("No smart playlists found at {}, starting empty.", path.display());
+            return SmartPlaylistStore::default();
+        };
+
+        match serde_json::from_str::<SmartPlaylistStore>(&contents) {
+            Ok(store) => {
+                println!("Loaded {} smart playlists.", store.playlists.len());
+                store
+            }
+            Err(e) => {
+                eprintln!("Failed to parse smart playlists at {}: {}", path.display(), e);
+                SmartPlaylistStore::default()
+            }
+        }
+    }
+
+    pub fn save(&self, app: &AppHandle) -> Result<(), String> {
+        let path = app_data_file(app, SMART_PLAYLISTS_FILE)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create smart playlists directory: {}", e))?;
+        }
+
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize smart playlists: {}", e))?;
+        fs::write(&path, contents).map_err(|e| format!("Failed to save smart playlists: {}", e))?;
+        println!("Smart playlists saved to {}", path.display());
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<&SmartPlaylist, String> {
+        self.playlists
+            .iter()
+            .find(|playlist| playlist.name == name)
+            .ok_or(format!("Smart playlist '{}' not found.", name))
+    }
+
+    // Replaces the playlist with the same name, or adds it as a new one.
+    pub fn upsert(&mut self, mut playlist: SmartPlaylist) -> Result<(), String> {
+        playlist.name = playlist.name.trim().to_string();
+        if playlist.name.is_empty() {
+            return Err("Smart playlist name cannot be empty.".to_string());
+        }
+        if playlist.rules.is_empty() {
+            return Err("Smart playlist needs at least one rule.".to_string());
+        }
+
+        self.results.remove(&playlist.name);
+        match self.playlists.iter_mut().find(|existing| existing.name == playlist.name) {
+            Some(existing) => *existing = playlist,
+            None => self.playlists.push(playlist),
+        }
+        Ok(())
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<(), String> {
+        let count = self.playlists.len();
+        self.playlists.retain(|playlist| playlist.name != name);
+        if self.playlists.len() == count {
+            return Err(format!("Smart playlist '{}' not found.", name));
+        }
+        self.results.remove(name);
+        Ok(())
+    }
+
+    // Re-evaluates every playlist and returns the ones whose tracks changed.
+    pub fn refresh(&mut self, songs: &[Song]) -> Vec<SmartPlaylistChange> {
+        let mut changes = Vec::new();
+        for playlist in &self.playlists {
+            let tracks: Vec<SongRef> = playlist
+                .evaluate(songs)
+                .into_iter()
+                .map(|song| SongRef {
+                    root: song.root,
+                    path: song.path,
+                })
+                .collect();
+
+            let previous = self.results.insert(playlist.name.clone(), tracks.clone());
+            if previous.as_ref() != Some(&tracks) {
+                changes.push(SmartPlaylistChange {
+                    name: playlist.name.clone(),
+                    tracks,
+                });
+            }
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{song, songs, titles};
+
+    fn playlist(rules: Vec<Rule>) -> SmartPlaylist {
+        SmartPlaylist {
+            name: "Test".to_string(),
+            rules,
+            match_all: true,
+            sort: None,
+            descending: false,
+            limit: None,
+        }
+    }
+
+    #[test]
+    fn matches_rules_on_song_fields() {
+        let mut kick = Song { path: "drums/kick.WAV".to_string(), ..song("Kick") };
+        kick.bpm = Some(128.0);
+        kick.key = Some("Am".to_string());
+        kick.artist = Some("Some Producer".to_string());
+        let now = 1_700_000_000;
+        kick.date_added = now - 2 * SECONDS_PER_DAY as u64;
+
+        assert!(Rule::Extension { extensions: vec![".wav".to_string()] }.matches(&kick, now));
+        assert!(!Rule::Extension { extensions: vec!["flac".to_string()] }.matches(&kick, now));
+        assert!(Rule::Bpm { min: Some(120.0), max: Some(130.0) }.matches(&kick, now));
+        assert!(!Rule::Duration { min: Some(1.0), max: None }.matches(&kick, now));
+        assert!(Rule::Key { key: " am ".to_string() }.matches(&kick, now));
+        assert!(Rule::AddedWithin { days: 3.0 }.matches(&kick, now));
+        assert!(!Rule::AddedWithin { days: 1.0 }.matches(&kick, now));
+        assert!(Rule::Root { roots: vec!["main".to_string()] }.matches(&kick, now));
+        assert!(Rule::Contains { field: TagField::Artist, value: "producer".to_string() }.matches(&kick, now));
+        assert!(!Rule::Contains { field: TagField::Album, value: "producer".to_string() }.matches(&kick, now));
+    }
+
+    #[test]
+    fn combines_rules_with_all_or_any() {
+        let mut songs = songs(&["Kick", "Snare", "Hat"]);
+        songs[1].path = "Snare.flac".to_string();
+        songs[2].path = "Hat.mp3".to_string();
+        let rules = vec![
+            Rule::Extension { extensions: vec!["wav".to_string(), "flac".to_string()] },
+            Rule::Contains { field: TagField::Title, value: "a".to_string() },
+        ];
+
+        let mut list = playlist(rules);
+        assert_eq!(titles(&list.evaluate(&songs)), ["Snare"]);
+
+        list.match_all = false;
+        assert_eq!(titles(&list.evaluate(&songs)), ["Hat", "Kick", "Snare"]);
+    }
+
+    #[test]
+    fn sorts_and_limits_results() {
+        let mut songs = songs(&["A", "B", "C"]);
+        songs[0].bpm = Some(90.0);
+        songs[2].bpm = Some(140.0);
+
+        let mut list = playlist(vec![Rule::Extension { extensions: vec!["wav".to_string()] }]);
+        list.sort = Some(SortField::Bpm);
+        list.descending = true;
+        assert_eq!(titles(&list.evaluate(&songs)), ["C", "A", "B"]);
+
+        list.limit = Some(2);
+        assert_eq!(titles(&list.evaluate(&songs)), ["C", "A"]);
+    }
+
+    #[test]
+    fn reads_rules_from_json() {
+        let json = r#"{"name": "Slow", "rules": [{"type": "bpm", "max": 100.0}, {"type": "contains", "field": "genre", "value": "dub"}]}"#;
+        let list: SmartPlaylist = serde_json::from_str(json).unwrap();
+        assert!(list.match_all);
+        assert_eq!(list.rules[0], Rule::Bpm { min: None, max: Some(100.0) });
+        assert_eq!(list.rules[1], Rule::Contains { field: TagField::Genre, value: "dub".to_string() });
+    }
+
+    #[test]
+    fn store_validates_and_reports_changed_playlists() {
+        let mut store = SmartPlaylistStore::default();
+        assert!(store.upsert(playlist(Vec::new())).is_err());
+
+        let mut list = playlist(vec![Rule::Extension { extensions: vec!["wav".to_string()] }]);
+        list.name = "  Drums ".to_string();
+        store.upsert(list).unwrap();
+        assert!(store.get("Drums").is_ok());
+
+        let mut songs = songs(&["Kick"]);
+        assert_eq!(store.refresh(&songs).len(), 1);
+        assert!(store.refresh(&songs).is_empty());
+
+        songs.push(song("Snare"));
+        let changes = store.refresh(&songs);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].tracks.len(), 2);
+
+        store.delete("Drums").unwrap();
+        assert!(store.delete("Drums").is_err());
+    }
+}
@@ -0,0 +1,499 @@
+use rodio::{source::SeekError, Source};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    f32::consts::PI,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+pub const MIN_SPEED: f32 = 0.25;
+pub const MAX_SPEED: f32 = 4.0;
+pub const MAX_PITCH_SEMITONES: f32 = 12.0;
+const MIN_SYNC_BPM: f32 = 20.0;
+const MAX_SYNC_BPM: f32 = 300.0;
+// Segment length for WSOLA; long enough for bass, short enough to keep transients tight.
+const SEGMENT_SECONDS: f32 = 0.04;
+// How far either side of the nominal position to search for the best overlap.
+const TOLERANCE_FRACTION: usize = 4;
+// Correlation is computed on every Nth frame of the overlap to keep the search cheap.
+const CORRELATION_STEP: usize = 4;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct TempoSettings {
+    pub speed: f32,
+    pub pitch_semitones: f32,
+    // When set, tracks with a known tempo are stretched to this BPM instead of using `speed`.
+    pub sync_bpm: Option<f32>,
+}
+
+impl Default for TempoSettings {
+    fn default() -> Self {
+        TempoSettings {
+            speed: 1.0,
+            pitch_semitones: 0.0,
+            sync_bpm: None,
+        }
+    }
+}
+
+impl TempoSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_SPEED..=MAX_SPEED).contains(&self.speed) {
+            return Err(format!("Speed must be between {} and {}.", MIN_SPEED, MAX_SPEED));
+        }
+        if !(-MAX_PITCH_SEMITONES..=MAX_PITCH_SEMITONES).contains(&self.pitch_semitones) {
+            return Err(format!("Pitch must be between -{0} and {0} semitones.", MAX_PITCH_SEMITONES));
+        }
+        if let Some(bpm) = self.sync_bpm {
+            if !(MIN_SYNC_BPM..=MAX_SYNC_BPM).contains(&bpm) {
+                return Err(format!("Sync BPM must be between {} and {}.", MIN_SYNC_BPM, MAX_SYNC_BPM));
+            }
+        }
+        Ok(())
+    }
+
+    // Half- and double-time are musically in sync, so the ratio is folded to
+    // whichever octave stretches the track the least.
+    pub fn speed_for(&self, bpm: Option<f32>) -> f32 {
+        let (Some(target), Some(bpm)) = (self.sync_bpm, bpm.filter(|bpm| *bpm > 0.0)) else {
+            return self.speed;
+        };
+
+        let mut ratio = target / bpm;
+        while ratio > std::f32::consts::SQRT_2 {
+            ratio /= 2.0;
+        }
+        while ratio < std::f32::consts::FRAC_1_SQRT_2 {
+            ratio *= 2.0;
+        }
+        ratio.clamp(MIN_SPEED, MAX_SPEED)
+    }
+}
+
+// Live speed and pitch for one deck.
+#[derive(Clone)]
+pub struct StretchControl {
+    speed: Arc<AtomicU32>,
+    pitch: Arc<AtomicU32>,
+}
+
+impl StretchControl {
+    pub fn new(speed: f32, pitch_semitones: f32) -> StretchControl {
+        StretchControl {
+            speed: Arc::new(AtomicU32::new(speed.to_bits())),
+            pitch: Arc::new(AtomicU32::new(pitch_semitones.to_bits())),
+        }
+    }
+
+    pub fn set(&self, speed: f32, pitch_semitones: f32) {
+        self.speed.store(speed.to_bits(), Ordering::Relaxed);
+        self.pitch.store(pitch_semitones.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn speed(&self) -> f32 {
+        f32::from_bits(self.speed.load(Ordering::Relaxed))
+    }
+
+    fn pitch_ratio(&self) -> f32 {
+        let semitones = f32::from_bits(self.pitch.load(Ordering::Relaxed));
+        2f32.powf(semitones / 12.0)
+    }
+
+    pub fn wrap<S>(&self, source: S) -> Stretched<S>
+    where
+        S: Source<Item = i16>,
+    {
+        let channels = source.channels().max(1) as usize;
+        let sample_rate = source.sample_rate().max(1);
+        Stretched {
+            inner: source,
+            control: self.clone(),
+            channels,
+            sample_rate,
+            wsola: None,
+            pending: VecDeque::new(),
+            output: VecDeque::new(),
+            frames_read: 0,
+        }
+    }
+}
+
+// Waveform-similarity overlap-add: Hann-windowed segments are taken from the
+// input at the stretched rate, each one shifted within a small tolerance to
+// best line up with where the previous segment would have continued.
+struct Wsola {
+    channels: usize,
+    length: usize,
+    hop: usize,
+    tolerance: usize,
+    window: Vec<f32>,
+    // Interleaved input frames; `input_start` is the stream frame of the first one.
+    input: Vec<f32>,
+    input_start: u64,
+    input_done: bool,
+    analysis: f64,
+    // Natural continuation of the last segment, used as the template for the next search.
+    template: Option<Vec<f32>>,
+    overlap: Vec<f32>,
+    // Pitch resampler over the stretched output.
+    resample_input: Vec<f32>,
+    resample_position: f64,
+}
+
+impl Wsola {
+    fn new(channels: usize, sample_rate: u32, start_frame: u64) -> Wsola {
+        let length = ((sample_rate as f32 * SEGMENT_SECONDS) as usize / 2 * 2).max(64);
+        let hop = length / 2;
+        let window = (0..length)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / length as f32).cos())
+            .collect();
+        Wsola {
+            channels,
+            length,
+            hop,
+            tolerance: length / TOLERANCE_FRACTION,
+            window,
+            input: Vec::new(),
+            input_start: start_frame,
+            input_done: false,
+            analysis: start_frame as f64,
+            template: None,
+            overlap: vec![0.0; length * channels],
+            resample_input: Vec::new(),
+            resample_position: 0.0,
+        }
+    }
+
+    fn input_frames(&self) -> usize {
+        self.input.len() / self.channels
+    }
+
+    // Frames of input needed past the current analysis point before a segment can be produced.
+    fn lookahead(&self) -> usize {
+        self.tolerance * 2 + self.length + self.hop + 1
+    }
+
+    fn needs_input(&self) -> bool {
+        let offset = (self.analysis as i64 - self.input_start as i64 - self.tolerance as i64).max(0) as usize;
+        !self.input_done && self.input_frames() < offset + self.lookahead()
+    }
+
+    fn frame(&self, frame: i64, channel: usize) -> f32 {
+        let index = frame - self.input_start as i64;
+        if index < 0 || index as usize >= self.input_frames() {
+            return 0.0;
+        }
+        self.input[index as usize * self.channels + channel]
+    }
+
+    fn mono(&self, frame: i64) -> f32 {
+        (0..self.channels).map(|channel| self.frame(frame, channel)).sum::<f32>()
+    }
+
+    fn best_offset(&self, nominal: i64) -> i64 {
+        let Some(template) = &self.template else {
+            return 0;
+        };
+
+        let overlap = self.length - self.hop;
+        let tolerance = self.tolerance as i64;
+        let mut best = (0, f32::MIN);
+        for offset in -tolerance..=tolerance {
+            let start = nominal + offset;
+            let mut score = 0.0;
+            for i in (0..overlap).step_by(CORRELATION_STEP) {
+                score += template[i] * self.mono(start + i as i64);
+            }
+            if score > best.1 {
+                best = (offset, score);
+            }
+        }
+        best.0
+    }
+
+    // Produces one hop of stretched audio, or None once the input is exhausted.
+    fn step(&mut self, speed: f32, pitch_ratio: f32) -> Option<Vec<f32>> {
+        let end = self.input_start + self.input_frames() as u64;
+        if self.input_done && self.analysis as u64 >= end {
+            return None;
+        }
+
+        let nominal = self.analysis.round() as i64;
+        let start = nominal + self.best_offset(nominal);
+        for i in 0..self.length {
+            for channel in 0..self.channels {
+                self.overlap[i * self.channels + channel] += self.window[i] * self.frame(start + i as i64, channel);
+            }
+        }
+        let overlap = self.length - self.hop;
+        let continuation = start + self.hop as i64;
+        self.template = Some((0..overlap).map(|i| self.mono(continuation + i as i64)).collect());
+
+        let hop_samples = self.hop * self.channels;
+        let output: Vec<f32> = self.overlap.drain(..hop_samples).collect();
+        self.overlap.extend(std::iter::repeat(0.0).take(hop_samples));
+
+        // The resampler raises the pitch by `pitch_ratio` and speeds it up by the
+        // same amount, so the stretch makes up the rest of the requested speed.
+        self.analysis += self.hop as f64 * (speed / pitch_ratio) as f64;
+        self.discard_consumed();
+        Some(output)
+    }
+
+    fn discard_consumed(&mut self) {
+        let keep_from = (self.analysis as i64 - self.tolerance as i64 - 1).max(self.input_start as i64) as u64;
+        let drop = ((keep_from - self.input_start) as usize).min(self.input_frames());
+        if drop > 0 {
+            self.input.drain(..drop * self.channels);
+            self.input_start += drop as u64;
+        }
+    }
+
+    // Four-point cubic interpolation through the stretched output at `pitch_ratio` frames per frame.
+    fn resample(&mut self, stretched: Vec<f32>, pitch_ratio: f32, output: &mut VecDeque<f32>) {
+        self.resample_input.extend(stretched);
+        let channels = self.channels;
+        let frames = self.resample_input.len() / channels;
+
+        while self.resample_position + 2.0 < frames as f64 {
+            let index = self.resample_position.floor() as usize;
+            let t = (self.resample_position - index as f64) as f32;
+            for channel in 0..channels {
+                let sample = |i: isize| {
+                    let frame = (index as isize + i).clamp(0, frames as isize - 1) as usize;
+                    self.resample_input[frame * channels + channel]
+                };
+                let (y0, y1, y2, y3) = (sample(-1), sample(0), sample(1), sample(2));
+                let a = -0.5 * y0 + 1.5 * y1 - 1.5 * y2 + 0.5 * y3;
+                let b = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+                let c = -0.5 * y0 + 0.5 * y2;
+                output.push_back(((a * t + b) * t + c) * t + y1);
+            }
+            self.resample_position += pitch_ratio as f64;
+        }
+
+        let consumed = (self.resample_position.floor() as usize).saturating_sub(1);
+        if consumed > 0 {
+            self.resample_input.drain(..consumed * channels);
+            self.resample_position -= consumed as f64;
+        }
+    }
+}
+
+// Time-stretch and pitch-shift adapter. At normal speed and pitch samples
+// pass straight through, so the default path adds no latency.
+pub struct Stretched<S> {
+    inner: S,
+    control: StretchControl,
+    channels: usize,
+    sample_rate: u32,
+    wsola: Option<Wsola>,
+    // Input the stretcher had read ahead when it was bypassed, played before reading on.
+    pending: VecDeque<f32>,
+    output: VecDeque<f32>,
+    // Stream frame of the next frame taken from the input.
+    frames_read: u64,
+}
+
+impl<S> Stretched<S>
+where
+    S: Source<Item = i16>,
+{
+    fn read_frame(&mut self) -> Option<Vec<f32>> {
+        let mut frame = Vec::with_capacity(self.channels);
+        if self.pending.len() >= self.channels {
+            frame.extend(self.pending.drain(..self.channels));
+        } else {
+            for _ in 0..self.channels {
+                frame.push(self.inner.next()? as f32 / 32768.0);
+            }
+        }
+        self.frames_read += 1;
+        Some(frame)
+    }
+
+    fn fill(&mut self) -> bool {
+        let speed = self.control.speed();
+        let pitch_ratio = self.control.pitch_ratio();
+        let bypass = (speed - 1.0).abs() < 1e-3 && (pitch_ratio - 1.0).abs() < 1e-3;
+
+        if bypass {
+            // Picks up exactly where the stretched output reached.
+            if let Some(wsola) = self.wsola.take() {
+                let resume = wsola.analysis.max(0.0) as u64;
+                let consumed = resume.saturating_sub(wsola.input_start) as usize;
+                let skip = (consumed * self.channels).min(wsola.input.len());
+                let mut unread: VecDeque<f32> = wsola.input[skip..].iter().copied().collect();
+                unread.extend(self.pending.drain(..));
+                self.pending = unread;
+                self.frames_read = resume;
+            }
+            let Some(frame) = self.read_frame() else {
+                return false;
+            };
+            self.output.extend(frame);
+            return true;
+        }
+
+        let mut wsola = self
+            .wsola
+            .take()
+            .unwrap_or_else(|| Wsola::new(self.channels, self.sample_rate, self.frames_read));
+        while wsola.needs_input() {
+            match self.read_frame() {
+                Some(frame) => wsola.input.extend(frame),
+                None => {
+                    wsola.input_done = true;
+                    break;
+                }
+            }
+        }
+
+        let stretched = wsola.step(speed, pitch_ratio);
+        if let Some(stretched) = stretched {
+            wsola.resample(stretched, pitch_ratio, &mut self.output);
+            self.wsola = Some(wsola);
+            return true;
+        }
+        false
+    }
+}
+
+impl<S> Iterator for Stretched<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        while self.output.is_empty() {
+            if !self.fill() {
+                return None;
+            }
+        }
+        let sample = self.output.pop_front()?;
+        Some((sample * 32768.0).clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+    }
+}
+
+impl<S> Source for Stretched<S>
+where
+    S: Source<Item = i16>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Depends on the speed, which can change while playing.
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+        self.output.clear();
+        self.pending.clear();
+        self.wsola = None;
+        self.frames_read = (pos.as_secs_f64() * self.sample_rate as f64) as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    const SAMPLE_RATE: u32 = 44_100;
+
+    fn sine(frequency: f32, seconds: f32) -> Vec<i16> {
+        let frames = (SAMPLE_RATE as f32 * seconds) as usize;
+        (0..frames)
+            .map(|i| ((2.0 * PI * frequency * i as f32 / SAMPLE_RATE as f32).sin() * 16384.0) as i16)
+            .collect()
+    }
+
+    fn stretch(input: &[i16], speed: f32, pitch_semitones: f32) -> Vec<i16> {
+        StretchControl::new(speed, pitch_semitones)
+            .wrap(SamplesBuffer::new(1, SAMPLE_RATE, input.to_vec()))
+            .collect()
+    }
+
+    // Estimates the dominant frequency from upward zero crossings.
+    fn frequency(samples: &[i16]) -> f32 {
+        let crossings = samples.windows(2).filter(|pair| pair[0] < 0 && pair[1] >= 0).count();
+        crossings as f32 * SAMPLE_RATE as f32 / samples.len() as f32
+    }
+
+    #[test]
+    fn bypass_is_bit_exact() {
+        let input = sine(440.0, 0.5);
+        assert_eq!(stretch(&input, 1.0, 0.0), input);
+    }
+
+    #[test]
+    fn speed_changes_length_but_not_pitch() {
+        let input = sine(441.0, 2.0);
+        for speed in [0.5, 1.5, 2.0] {
+            let output = stretch(&input, speed, 0.0);
+            let expected = input.len() as f32 / speed;
+            assert!((output.len() as f32 - expected).abs() < expected * 0.03, "speed {} gave {} samples", speed, output.len());
+
+            let middle = &output[output.len() / 4..output.len() * 3 / 4];
+            assert!((frequency(middle) - 441.0).abs() < 10.0, "speed {} gave {} Hz", speed, frequency(middle));
+        }
+    }
+
+    #[test]
+    fn pitch_changes_frequency_but_not_length() {
+        let input = sine(441.0, 2.0);
+        let output = stretch(&input, 1.0, 12.0);
+        assert!((output.len() as f32 - input.len() as f32).abs() < input.len() as f32 * 0.03);
+
+        let middle = &output[output.len() / 4..output.len() * 3 / 4];
+        assert!((frequency(middle) - 882.0).abs() < 20.0, "got {} Hz", frequency(middle));
+    }
+
+    #[test]
+    fn resumes_bypass_where_the_stretch_left_off() {
+        let input: Vec<i16> = (0..20_000).map(|i| i as i16).collect();
+        let control = StretchControl::new(1.5, 0.0);
+        let mut stretched = control.wrap(SamplesBuffer::new(1, SAMPLE_RATE, input.clone()));
+        let _: Vec<i16> = stretched.by_ref().take(5_000).collect();
+
+        control.set(1.0, 0.0);
+        let rest: Vec<i16> = stretched.collect();
+        // After the stretched samples already buffered, input continues without gaps or repeats.
+        let run = rest.windows(2).rev().take_while(|pair| pair[1] == pair[0] + 1).count() + 1;
+        let resumed = 20_000 - run;
+        assert!(run > 10_000, "only {} contiguous samples", run);
+        assert!((resumed as i64 - 7_500).abs() < 2_000, "resumed at {}", resumed);
+        assert_eq!(rest.last(), Some(&19_999));
+    }
+
+    #[test]
+    fn sync_folds_to_the_nearest_octave() {
+        let settings = TempoSettings {
+            sync_bpm: Some(128.0),
+            ..TempoSettings::default()
+        };
+        assert_eq!(settings.speed_for(Some(128.0)), 1.0);
+        assert!((settings.speed_for(Some(64.0)) - 1.0).abs() < 1e-6);
+        assert!((settings.speed_for(Some(120.0)) - 128.0 / 120.0).abs() < 1e-6);
+        assert_eq!(settings.speed_for(None), 1.0);
+        assert!(TempoSettings { speed: 5.0, ..TempoSettings::default() }.validate().is_err());
+    }
+}
@@ -0,0 +1,19 @@
+use crate::library::Song;
+
+// Fixtures shared by the unit tests of modules that work on songs.
+pub fn song(title: &str) -> Song {
+    Song {
+        title: title.to_string(),
+        path: format!("{}.wav", title),
+        root: "main".to_string(),
+        ..Song::default()
+    }
+}
+
+pub fn songs(titles: &[&str]) -> Vec<Song> {
+    titles.iter().map(|title| song(title)).collect()
+}
+
+pub fn titles<'a>(songs: impl IntoIterator<Item = &'a Song>) -> Vec<&'a str> {
+    songs.into_iter().map(|song| song.title.as_str()).collect()
+}
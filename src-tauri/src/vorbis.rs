@@ -0,0 +1,827 @@
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    f32::consts::PI,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
+
+// Every packet is a long block; the short size is only declared because the
+// identification header needs one.
+const SHORT_BLOCK_EXPONENT: u32 = 8;
+const LONG_BLOCK_EXPONENT: u32 = 11;
+const BLOCK_SIZE: usize = 1 << LONG_BLOCK_EXPONENT;
+const HOP: usize = BLOCK_SIZE / 2;
+
+// Floor heights are coded in steps of two table entries, about 1.1 dB.
+const FLOOR_MULTIPLIER: i32 = 2;
+const FLOOR_RANGE: i32 = 128;
+const FLOOR_RANGE_BITS: u32 = 10;
+// Posts between the two implicit ones at 0 and HOP, spaced roughly evenly on
+// a log scale so the curve can follow the low end closely.
+const FLOOR_POSTS: [u32; 30] = [
+    2, 4, 6, 8, 11, 14, 18, 22, 27, 33, 40, 48, 57, 68, 80, 94, 110, 128, 150, 175, 204, 238, 277, 322, 375, 436, 507,
+    590, 686, 798,
+];
+// The floor sits this far below the loudest coefficient around each post, so
+// the residue there is quantized to about 1/RESOLUTION of its size.
+const RESOLUTION: f32 = 16.0;
+// Quietest floor, about -100 dB below a full-scale sine. Coefficients under
+// half of it round to zero, which drops dither and noise below 16 bits.
+const MIN_FLOOR: f32 = 1e-5;
+// The floor also stays within this ratio of the block's loudest coefficient,
+// a crude stand-in for masking that leaves near-silent bands uncoded.
+const MASKING: f32 = 1e-3;
+
+const PARTITION_SIZE: usize = 16;
+// Largest residue the three cascaded books can add up to: 4 + 4 * 9 + 4 * 81.
+const MAX_RESIDUE: i32 = 364;
+
+const FLOOR_BOOK: usize = 0;
+const CLASS_BOOK: usize = 1;
+const ONES_BOOK: usize = 2;
+const TWOS_BOOK: usize = 3;
+const FOURS_BOOK: usize = 4;
+const NINES_BOOK: usize = 5;
+const EIGHTY_ONES_BOOK: usize = 6;
+// A partition takes the first class whose limit covers its largest residue,
+// and is coded with that class's books, one per pass, summed by the decoder.
+const RESIDUE_CLASSES: [(i32, &[usize]); 6] = [
+    (0, &[]),
+    (1, &[ONES_BOOK]),
+    (2, &[TWOS_BOOK]),
+    (4, &[FOURS_BOOK]),
+    (40, &[FOURS_BOOK, NINES_BOOK]),
+    (MAX_RESIDUE, &[FOURS_BOOK, NINES_BOOK, EIGHTY_ONES_BOOK]),
+];
+// Rough class frequencies for the classification book's code lengths.
+const CLASS_WEIGHTS: [f64; 6] = [0.3, 0.2, 0.15, 0.15, 0.15, 0.05];
+
+// Source channel for each Vorbis channel, from the channel order the Vorbis
+// specification fixes for up to eight channels.
+const CHANNEL_ORDER: [&[usize]; 8] = [
+    &[0],
+    &[0, 1],
+    &[0, 2, 1],
+    &[0, 1, 2, 3],
+    &[0, 2, 1, 3, 4],
+    &[0, 2, 1, 4, 5, 3],
+    &[0, 2, 1, 5, 6, 4, 3],
+    &[0, 2, 1, 6, 7, 4, 5, 3],
+];
+
+// Minimal Ogg Vorbis writer: long blocks only, one floor 1 and one residue 1
+// setup with fixed codebooks, and no channel coupling. The floor follows the
+// spectral envelope at a fixed distance instead of a psychoacoustic model,
+// which keeps quality high at the cost of size: around 250 kbps for stereo
+// at 44.1 kHz.
+pub struct VorbisWriter {
+    packets: PacketWriter<BufWriter<File>>,
+    serial: u32,
+    channels: usize,
+    books: Vec<Codebook>,
+    posts: Vec<u32>,
+    mdct: Mdct,
+    window: Vec<f32>,
+    // Per Vorbis channel, samples from the start of the next block.
+    buffer: Vec<Vec<f32>>,
+    blocks: u64,
+    total_frames: u64,
+    // Held back until the next one arrives, so the last can end the stream.
+    pending: Option<Vec<u8>>,
+}
+
+impl VorbisWriter {
+    pub fn create(path: &Path, channels: usize, sample_rate: u32) -> Result<VorbisWriter, String> {
+        if !(1..=CHANNEL_ORDER.len()).contains(&channels) {
+            return Err(format!("Ogg Vorbis supports 1 to 8 channels, the source has {}.", channels));
+        }
+        let file = File::create(path).map_err(|e| format!("Error creating file: {}: {}", path.display(), e))?;
+        let mut writer = VorbisWriter {
+            packets: PacketWriter::new(BufWriter::new(file)),
+            serial: rand::random(),
+            channels,
+            books: codebooks(),
+            posts: floor_posts(),
+            mdct: Mdct::new(BLOCK_SIZE),
+            window: (0..BLOCK_SIZE)
+                .map(|i| {
+                    let x = (PI * (i as f32 + 0.5) / BLOCK_SIZE as f32).sin();
+                    (PI / 2.0 * x * x).sin()
+                })
+                .collect(),
+            // The first block starts half a block before the audio.
+            buffer: vec![vec![0.0; HOP]; channels],
+            blocks: 0,
+            total_frames: 0,
+            pending: None,
+        };
+
+        // The identification header gets a page of its own; the other two
+        // finish on the page before the audio starts.
+        let identification = writer.identification_header(sample_rate);
+        writer.write_packet(identification, PacketWriteEndInfo::EndPage, 0)?;
+        writer.write_packet(comment_header(), PacketWriteEndInfo::NormalPacket, 0)?;
+        let setup = writer.setup_header();
+        writer.write_packet(setup, PacketWriteEndInfo::EndPage, 0)?;
+        Ok(writer)
+    }
+
+    // Interleaved samples in the source's channel order.
+    pub fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let order = CHANNEL_ORDER[self.channels - 1];
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, &source) in self.buffer.iter_mut().zip(order) {
+                channel.push(frame[source]);
+            }
+            if self.buffer[0].len() == BLOCK_SIZE {
+                self.write_block()?;
+            }
+        }
+        self.total_frames += (samples.len() / self.channels) as u64;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        // Packet k ends at sample k * HOP, so the audio is covered once a
+        // packet ends at or past the last sample.
+        while self.blocks <= self.total_frames.div_ceil(HOP as u64) {
+            for channel in &mut self.buffer {
+                channel.resize(BLOCK_SIZE, 0.0);
+            }
+            self.write_block()?;
+        }
+
+        // The last granule position trims the padding off the end.
+        let last = self.pending.take().unwrap_or_default();
+        self.write_packet(last, PacketWriteEndInfo::EndStream, self.total_frames)?;
+        self.packets
+            .into_inner()
+            .flush()
+            .map_err(|e| format!("Failed to write Ogg file: {}", e))
+    }
+
+    fn write_packet(&mut self, packet: Vec<u8>, end: PacketWriteEndInfo, granule: u64) -> Result<(), String> {
+        self.packets
+            .write_packet(packet.into_boxed_slice(), self.serial, end, granule)
+            .map_err(|e| format!("Failed to write Ogg file: {}", e))
+    }
+
+    fn write_block(&mut self) -> Result<(), String> {
+        let packet = self.audio_packet();
+        for channel in &mut self.buffer {
+            channel.drain(..HOP);
+        }
+
+        if let Some(previous) = self.pending.replace(packet) {
+            let granule = (self.blocks - 1) * HOP as u64;
+            self.write_packet(previous, PacketWriteEndInfo::NormalPacket, granule)?;
+        }
+        self.blocks += 1;
+        Ok(())
+    }
+
+    fn audio_packet(&mut self) -> Vec<u8> {
+        let mut w = BitPacker::default();
+        // Audio packet; with a single mode there are no mode bits. The
+        // neighbouring blocks are always long too.
+        w.write(0, 1);
+        w.write(1, 1);
+        w.write(1, 1);
+
+        let mut windowed = vec![0.0; BLOCK_SIZE];
+        let mut spectrum = vec![0.0; HOP];
+        let mut residues = Vec::with_capacity(self.channels);
+        for channel in 0..self.channels {
+            for ((out, sample), window) in windowed.iter_mut().zip(&self.buffer[channel]).zip(&self.window) {
+                *out = sample * window;
+            }
+            self.mdct.forward(&windowed, &mut spectrum);
+
+            let targets = floor_targets(&self.posts, &spectrum);
+            let (values, curve) = floor_curve(&self.posts, &targets);
+            let residue: Vec<i32> = spectrum
+                .iter()
+                .zip(&curve)
+                .map(|(coefficient, floor)| ((coefficient / floor).round() as i32).clamp(-MAX_RESIDUE, MAX_RESIDUE))
+                .collect();
+
+            // A channel with nothing to code is marked unused and decodes as silence.
+            if residue.iter().all(|&value| value == 0) {
+                w.write(0, 1);
+                continue;
+            }
+            w.write(1, 1);
+            let range_bits = ilog(FLOOR_RANGE as u32 - 1);
+            w.write(values[0], range_bits);
+            w.write(values[1], range_bits);
+            for &value in &values[2..] {
+                self.books[FLOOR_BOOK].write_entry(&mut w, value as usize);
+            }
+            residues.push(residue);
+        }
+
+        self.write_residue(&mut w, &residues);
+        w.finish()
+    }
+
+    // Residue type 1: each channel's vector is split into partitions, and the
+    // partition classes are sent ahead of the first pass, two per codeword.
+    fn write_residue(&self, w: &mut BitPacker, residues: &[Vec<i32>]) {
+        let classes: Vec<Vec<usize>> = residues
+            .iter()
+            .map(|residue| {
+                residue
+                    .chunks(PARTITION_SIZE)
+                    .map(|partition| {
+                        let peak = partition.iter().map(|value| value.abs()).max().unwrap_or(0);
+                        RESIDUE_CLASSES.iter().position(|(limit, _)| peak <= *limit).unwrap()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let class_book = &self.books[CLASS_BOOK];
+        let per_codeword = class_book.dimensions;
+        let passes = RESIDUE_CLASSES.iter().map(|(_, books)| books.len()).max().unwrap();
+        let partitions = HOP / PARTITION_SIZE;
+        for pass in 0..passes {
+            for first in (0..partitions).step_by(per_codeword) {
+                if pass == 0 {
+                    for channel in &classes {
+                        let entry = channel[first..first + per_codeword]
+                            .iter()
+                            .fold(0, |entry, class| entry * RESIDUE_CLASSES.len() + class);
+                        class_book.write_entry(w, entry);
+                    }
+                }
+
+                for partition in first..first + per_codeword {
+                    for (residue, channel) in residues.iter().zip(&classes) {
+                        let books = RESIDUE_CLASSES[channel[partition]].1;
+                        let Some(&book) = books.get(pass) else {
+                            continue;
+                        };
+                        let book = &self.books[book];
+                        let start = partition * PARTITION_SIZE;
+                        for vector in residue[start..start + PARTITION_SIZE].chunks(book.dimensions) {
+                            let digits = vector.iter().map(|&value| residue_digit(value, pass, books.len()));
+                            book.write_entry(w, book.vector_entry(digits));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn identification_header(&self, sample_rate: u32) -> Vec<u8> {
+        let mut header = vec![1];
+        header.extend_from_slice(b"vorbis");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.push(self.channels as u8);
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        // No bitrate hints.
+        header.extend_from_slice(&[0; 12]);
+        header.push((LONG_BLOCK_EXPONENT << 4 | SHORT_BLOCK_EXPONENT) as u8);
+        header.push(1);
+        header
+    }
+
+    fn setup_header(&self) -> Vec<u8> {
+        let mut w = BitPacker::default();
+        w.write(5, 8);
+        for byte in b"vorbis" {
+            w.write(*byte as u32, 8);
+        }
+
+        w.write(self.books.len() as u32 - 1, 8);
+        for book in &self.books {
+            book.write_header(&mut w);
+        }
+        // One time-domain transform, a placeholder the format requires.
+        w.write(0, 6);
+        w.write(0, 16);
+
+        // One floor of type 1: every post pair shares class 0, coded straight
+        // from the floor book.
+        w.write(0, 6);
+        w.write(1, 16);
+        w.write(self.posts.len() as u32 / 2 - 1, 5);
+        for _ in 0..self.posts.len() / 2 - 1 {
+            w.write(0, 4);
+        }
+        w.write(1, 3);
+        w.write(0, 2);
+        w.write(FLOOR_BOOK as u32 + 1, 8);
+        w.write(FLOOR_MULTIPLIER as u32 - 1, 2);
+        w.write(FLOOR_RANGE_BITS, 4);
+        for x in &self.posts[2..] {
+            w.write(*x, FLOOR_RANGE_BITS);
+        }
+
+        // One residue of type 1 over the whole spectrum.
+        w.write(0, 6);
+        w.write(1, 16);
+        w.write(0, 24);
+        w.write(HOP as u32, 24);
+        w.write(PARTITION_SIZE as u32 - 1, 24);
+        w.write(RESIDUE_CLASSES.len() as u32 - 1, 6);
+        w.write(CLASS_BOOK as u32, 8);
+        for (_, books) in RESIDUE_CLASSES {
+            let cascade = (1u32 << books.len()) - 1;
+            w.write(cascade & 7, 3);
+            w.write((cascade > 7) as u32, 1);
+            if cascade > 7 {
+                w.write(cascade >> 3, 5);
+            }
+        }
+        for (_, books) in RESIDUE_CLASSES {
+            for book in books {
+                w.write(*book as u32, 8);
+            }
+        }
+
+        // One mapping: a single submap for every channel, no coupling.
+        w.write(0, 6);
+        w.write(0, 16);
+        w.write(0, 1);
+        w.write(0, 1);
+        w.write(0, 2);
+        w.write(0, 8);
+        w.write(0, 8);
+        w.write(0, 8);
+
+        // One mode, long blocks with the standard window and transform.
+        w.write(0, 6);
+        w.write(1, 1);
+        w.write(0, 16);
+        w.write(0, 16);
+        w.write(0, 8);
+
+        w.write(1, 1);
+        w.finish()
+    }
+}
+
+fn comment_header() -> Vec<u8> {
+    let vendor = concat!("crate ", env!("CARGO_PKG_VERSION"));
+    let mut header = vec![3];
+    header.extend_from_slice(b"vorbis");
+    header.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    header.extend_from_slice(vendor.as_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.push(1);
+    header
+}
+
+// The implicit end posts, then the rest ordered by repeatedly splitting the
+// widest gap, so each post is predicted from the two that enclose it.
+fn floor_posts() -> Vec<u32> {
+    let mut posts = vec![0, HOP as u32];
+    let mut spans = vec![(0, FLOOR_POSTS.len())];
+    let mut start = 0;
+    while start < spans.len() {
+        let (low, high) = spans[start];
+        start += 1;
+        if low < high {
+            let middle = (low + high) / 2;
+            posts.push(FLOOR_POSTS[middle]);
+            spans.push((low, middle));
+            spans.push((middle + 1, high));
+        }
+    }
+    posts
+}
+
+// One step of the floor table; entry 255 is 0 dB and entry 0 about -140 dB.
+fn floor_level(y: i32) -> f32 {
+    const LOWEST: f64 = 1.0649863e-07;
+    (LOWEST * (1.0 / LOWEST).powf(y as f64 / 255.0)) as f32
+}
+
+// Desired floor height per post: RESOLUTION below the loudest coefficient
+// between the midpoints to the neighbouring posts.
+fn floor_targets(posts: &[u32], spectrum: &[f32]) -> Vec<i32> {
+    let mut sorted: Vec<u32> = posts.to_vec();
+    sorted.sort_unstable();
+    let step = (1.0 / floor_level(0) as f64).ln() / 255.0 * FLOOR_MULTIPLIER as f64;
+    let loudest = spectrum.iter().fold(0.0f32, |peak, coefficient| peak.max(coefficient.abs()));
+    let lowest = (loudest * MASKING).max(MIN_FLOOR);
+
+    posts
+        .iter()
+        .map(|post| {
+            let index = sorted.binary_search(post).unwrap();
+            let low = if index == 0 { 0 } else { (sorted[index - 1] + post) / 2 };
+            let high = sorted.get(index + 1).map_or(HOP as u32, |next| (post + next) / 2).max(low + 1);
+            let peak = spectrum[low as usize..(high as usize).min(HOP)]
+                .iter()
+                .fold(0.0f32, |peak, coefficient| peak.max(coefficient.abs()));
+            let level = (peak / RESOLUTION).max(lowest);
+            let y = ((level as f64 / floor_level(0) as f64).ln() / step).ceil() as i32;
+            y.clamp(0, FLOOR_RANGE - 1)
+        })
+        .collect()
+}
+
+// Codes the targets relative to the decoder's prediction and renders the
+// curve exactly as the decoder will, so the residue divides by the same floor.
+// Returns the coded values and the floor per coefficient.
+fn floor_curve(posts: &[u32], targets: &[i32]) -> (Vec<u32>, Vec<f32>) {
+    let mut values = vec![targets[0] as u32, targets[1] as u32];
+    let mut finals = vec![targets[0], targets[1]];
+    let mut used = vec![true; posts.len()];
+
+    for i in 2..posts.len() {
+        let (low, high) = neighbours(posts, i);
+        let predicted = render_point(posts[low], finals[low], posts[high], finals[high], posts[i]);
+        let (highroom, lowroom) = (FLOOR_RANGE - predicted, predicted);
+        let room = 2 * highroom.min(lowroom);
+        let target = targets[i];
+
+        let value = if target >= predicted {
+            let difference = target - predicted;
+            if 2 * difference < room {
+                2 * difference
+            } else {
+                difference + lowroom
+            }
+        } else {
+            let difference = predicted - target;
+            if 2 * difference - 1 < room {
+                2 * difference - 1
+            } else {
+                difference + highroom - 1
+            }
+        };
+
+        values.push(value as u32);
+        finals.push(target);
+        if value == 0 {
+            used[i] = false;
+        } else {
+            used[low] = true;
+            used[high] = true;
+        }
+    }
+
+    let mut order: Vec<usize> = (0..posts.len()).collect();
+    order.sort_unstable_by_key(|&i| posts[i]);
+    let mut curve = vec![0.0; HOP];
+    let (mut lx, mut ly) = (0, finals[order[0]] * FLOOR_MULTIPLIER);
+    for &i in &order[1..] {
+        if used[i] {
+            let (hx, hy) = (posts[i], finals[i] * FLOOR_MULTIPLIER);
+            render_line(lx, ly, hx, hy, &mut curve);
+            (lx, ly) = (hx, hy);
+        }
+    }
+    (values, curve)
+}
+
+// The closest earlier posts below and above post `i`.
+fn neighbours(posts: &[u32], i: usize) -> (usize, usize) {
+    let x = posts[i];
+    let low = (0..i).filter(|&j| posts[j] < x).max_by_key(|&j| posts[j]).unwrap();
+    let high = (0..i).filter(|&j| posts[j] > x).min_by_key(|&j| posts[j]).unwrap();
+    (low, high)
+}
+
+fn render_point(x0: u32, y0: i32, x1: u32, y1: i32, x: u32) -> i32 {
+    let offset = (y1 - y0).abs() * (x - x0) as i32 / (x1 - x0) as i32;
+    if y1 < y0 {
+        y0 - offset
+    } else {
+        y0 + offset
+    }
+}
+
+// The specification's integer line drawing, in floor table steps.
+fn render_line(x0: u32, y0: i32, x1: u32, y1: i32, curve: &mut [f32]) {
+    let dy = y1 - y0;
+    let adx = (x1 - x0) as i32;
+    let base = dy / adx;
+    let sy = if dy < 0 { base - 1 } else { base + 1 };
+    let ady = dy.abs() - base.abs() * adx;
+
+    let mut y = y0;
+    let mut error = 0;
+    curve[x0 as usize] = floor_level(y);
+    for value in &mut curve[x0 as usize + 1..(x1 as usize).min(HOP)] {
+        error += ady;
+        if error >= adx {
+            error -= adx;
+            y += sy;
+        } else {
+            y += base;
+        }
+        *value = floor_level(y);
+    }
+}
+
+// A residue split across cascaded books in balanced base 9: each pass but the
+// last takes the digit in -4..=4 and leaves the rest for the next pass, which
+// the decoder weighs nine times as much.
+fn residue_digit(value: i32, pass: usize, passes: usize) -> i32 {
+    let mut value = value;
+    for _ in 0..pass {
+        value = (value + 4).div_euclid(9);
+    }
+    if pass + 1 == passes {
+        value
+    } else {
+        value - 9 * (value + 4).div_euclid(9)
+    }
+}
+
+fn codebooks() -> Vec<Codebook> {
+    // Floor values are mostly small corrections to the prediction.
+    let floor_weights = (0..FLOOR_RANGE).map(|value| 0.8f64.powi(value) + 1e-4).collect();
+    let class_weights = (0..CLASS_WEIGHTS.len().pow(2))
+        .map(|entry| CLASS_WEIGHTS[entry / CLASS_WEIGHTS.len()] * CLASS_WEIGHTS[entry % CLASS_WEIGHTS.len()])
+        .collect();
+    vec![
+        Codebook::scalar(1, floor_weights),
+        Codebook::scalar(2, class_weights),
+        Codebook::vector(3, 1, 0.5),
+        Codebook::vector(5, 1, 0.6),
+        Codebook::vector(9, 1, 0.6),
+        Codebook::vector(9, 9, 0.35),
+        Codebook::vector(9, 81, 0.35),
+    ]
+}
+
+struct Codebook {
+    dimensions: usize,
+    lengths: Vec<u8>,
+    codewords: Vec<u32>,
+    // Values per dimension and the step between them, for vector books.
+    lookup: Option<(u32, i32)>,
+}
+
+impl Codebook {
+    fn scalar(dimensions: usize, weights: Vec<f64>) -> Codebook {
+        let lengths = huffman_lengths(&weights);
+        Codebook {
+            dimensions,
+            codewords: codewords(&lengths),
+            lengths,
+            lookup: None,
+        }
+    }
+
+    // Two-dimensional book over `values` evenly spaced values centred on
+    // zero, each weighted by `decay` to the power of its distance from zero.
+    fn vector(values: u32, step: i32, decay: f64) -> Codebook {
+        let half = (values / 2) as i32;
+        let weight = |digit: u32| decay.powi((digit as i32 - half).abs()) + 1e-4;
+        let weights: Vec<f64> = (0..values * values)
+            .map(|entry| weight(entry % values) * weight(entry / values))
+            .collect();
+        Codebook {
+            lookup: Some((values, step)),
+            ..Codebook::scalar(2, weights)
+        }
+    }
+
+    // Entry for a vector of value indices, first dimension least significant.
+    fn vector_entry(&self, digits: impl DoubleEndedIterator<Item = i32>) -> usize {
+        let (values, _) = self.lookup.unwrap();
+        let half = (values / 2) as i32;
+        digits
+            .rev()
+            .fold(0, |entry, digit| entry * values as usize + (digit + half) as usize)
+    }
+
+    fn write_entry(&self, w: &mut BitPacker, entry: usize) {
+        // Codewords are read from their most significant bit.
+        let length = self.lengths[entry] as u32;
+        w.write(self.codewords[entry].reverse_bits() >> (32 - length), length);
+    }
+
+    fn write_header(&self, w: &mut BitPacker) {
+        w.write(0x564342, 24);
+        w.write(self.dimensions as u32, 16);
+        w.write(self.lengths.len() as u32, 24);
+        // Not ordered, not sparse.
+        w.write(0, 1);
+        w.write(0, 1);
+        for length in &self.lengths {
+            w.write(*length as u32 - 1, 5);
+        }
+
+        let Some((values, step)) = self.lookup else {
+            w.write(0, 4);
+            return;
+        };
+        let bits = ilog(values - 1);
+        w.write(1, 4);
+        w.write(float32_pack(-(values as i32 / 2) * step), 32);
+        w.write(float32_pack(step), 32);
+        w.write(bits - 1, 4);
+        w.write(0, 1);
+        for multiplicand in 0..values {
+            w.write(multiplicand, bits);
+        }
+    }
+}
+
+// Code lengths from the classic Huffman construction.
+fn huffman_lengths(weights: &[f64]) -> Vec<u8> {
+    let total: f64 = weights.iter().sum();
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = weights
+        .iter()
+        .enumerate()
+        .map(|(i, weight)| Reverse(((weight / total * 1e12) as u64 + 1, i)))
+        .collect();
+    let mut parents = vec![usize::MAX; weights.len()];
+    while heap.len() > 1 {
+        let Reverse((first, i)) = heap.pop().unwrap();
+        let Reverse((second, j)) = heap.pop().unwrap();
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[i] = node;
+        parents[j] = node;
+        heap.push(Reverse((first + second, node)));
+    }
+
+    (0..weights.len())
+        .map(|mut node| {
+            let mut length = 0;
+            while parents[node] != usize::MAX {
+                node = parents[node];
+                length += 1;
+            }
+            length
+        })
+        .collect()
+}
+
+// Codewords the decoder derives from the lengths: each entry, in order, takes
+// the leftmost free node at its depth.
+fn codewords(lengths: &[u8]) -> Vec<u32> {
+    let mut next = [0u32; 33];
+    lengths
+        .iter()
+        .map(|&length| {
+            let length = length as usize;
+            let codeword = next[length];
+            for i in (0..=length).rev() {
+                if next[i] & 1 == 1 {
+                    next[i] = next[i - 1] << 1;
+                    break;
+                }
+                next[i] += 1;
+            }
+            let branch = next[length];
+            for (shift, next) in next[length..].iter_mut().enumerate().skip(1) {
+                if *next != codeword << shift {
+                    break;
+                }
+                *next = branch << shift;
+            }
+            codeword
+        })
+        .collect()
+}
+
+// Vorbis floats: a 21-bit mantissa scaled by a power of two biased by 788.
+fn float32_pack(value: i32) -> u32 {
+    let sign = if value < 0 { 0x8000_0000 } else { 0 };
+    sign | 788 << 21 | value.unsigned_abs()
+}
+
+fn ilog(value: u32) -> u32 {
+    32 - value.leading_zeros()
+}
+
+// Forward MDCT through a quarter-length complex FFT. Scaled so the decoder's
+// unscaled inverse, windowed and overlapped, gives back the input.
+struct Mdct {
+    fft: Arc<dyn Fft<f32>>,
+    twiddles: Vec<Complex<f32>>,
+    buffer: Vec<Complex<f32>>,
+}
+
+impl Mdct {
+    fn new(size: usize) -> Mdct {
+        let n = size / 2;
+        Mdct {
+            fft: FftPlanner::new().plan_fft_forward(n / 2),
+            twiddles: (0..n / 2)
+                .map(|k| Complex::from_polar(1.0, -PI * (k as f32 + 0.25) / n as f32))
+                .collect(),
+            buffer: vec![Complex::default(); n / 2],
+        }
+    }
+
+    fn forward(&mut self, input: &[f32], output: &mut [f32]) {
+        let n = output.len();
+        let quarter = n / 2;
+        let scale = 2.0 / n as f32;
+        // Fold the block [a b c d] to (-c' - d, a - b'), primes reversed, which
+        // turns the MDCT into a DCT-IV.
+        let folded = |i: usize| {
+            if i < quarter {
+                -input[3 * quarter - 1 - i] - input[3 * quarter + i]
+            } else {
+                input[i - quarter] - input[3 * quarter - 1 - i]
+            }
+        };
+        for (k, value) in self.buffer.iter_mut().enumerate() {
+            *value = Complex::new(folded(2 * k), folded(n - 1 - 2 * k)) * self.twiddles[k];
+        }
+        self.fft.process(&mut self.buffer);
+        for (k, value) in self.buffer.iter().enumerate() {
+            let value = value * Complex::from_polar(scale, -PI * k as f32 / n as f32);
+            output[2 * k] = value.re;
+            output[n - 1 - 2 * k] = -value.im;
+        }
+    }
+}
+
+#[derive(Default)]
+struct BitPacker {
+    bytes: Vec<u8>,
+    pending: u64,
+    pending_bits: u32,
+}
+
+impl BitPacker {
+    // Writes the low `count` bits of `value`, least significant first.
+    fn write(&mut self, value: u32, count: u32) {
+        self.pending |= (value as u64 & ((1 << count) - 1)) << self.pending_bits;
+        self.pending_bits += count;
+        while self.pending_bits >= 8 {
+            self.bytes.push(self.pending as u8);
+            self.pending >>= 8;
+            self.pending_bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.pending_bits > 0 {
+            self.bytes.push(self.pending as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mdct_matches_the_definition() {
+        let size = 64;
+        let n = size / 2;
+        let input: Vec<f32> = (0..size).map(|i| ((i * 7919) % 61) as f32 / 30.0 - 1.0).collect();
+        let mut output = vec![0.0; n];
+        Mdct::new(size).forward(&input, &mut output);
+
+        for (k, value) in output.iter().enumerate() {
+            let expected: f64 = input
+                .iter()
+                .enumerate()
+                .map(|(i, x)| {
+                    let phase = std::f64::consts::PI / n as f64 * (i as f64 + 0.5 + n as f64 / 2.0) * (k as f64 + 0.5);
+                    *x as f64 * phase.cos()
+                })
+                .sum::<f64>()
+                * 2.0
+                / n as f64;
+            assert!((*value as f64 - expected).abs() < 1e-4, "bin {}: {} vs {}", k, value, expected);
+        }
+    }
+
+    #[test]
+    fn codebooks_are_complete_and_prefix_free() {
+        for book in codebooks() {
+            assert!(book.lengths.iter().all(|&length| (1..=32).contains(&length)));
+            let kraft: f64 = book.lengths.iter().map(|&length| 0.5f64.powi(length as i32)).sum();
+            assert!((kraft - 1.0).abs() < 1e-9, "Kraft sum {}", kraft);
+            for (i, (&a, &la)) in book.codewords.iter().zip(&book.lengths).enumerate() {
+                for (&b, &lb) in book.codewords.iter().zip(&book.lengths).skip(i + 1) {
+                    let shorter = la.min(lb) as u32;
+                    assert_ne!(a >> (la as u32 - shorter), b >> (lb as u32 - shorter));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn residues_split_across_passes() {
+        for (limit, books) in RESIDUE_CLASSES.iter().skip(1) {
+            for value in -limit..=*limit {
+                let digits: Vec<i32> = (0..books.len()).map(|pass| residue_digit(value, pass, books.len())).collect();
+                assert!(digits.iter().all(|digit| digit.abs() <= 4), "{} -> {:?}", value, digits);
+                let sum: i32 = digits.iter().rev().fold(0, |sum, digit| sum * 9 + digit);
+                assert_eq!(sum, value);
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use notify_debouncer_mini::{
+    new_debouncer,
+    notify::{RecommendedWatcher, RecursiveMode},
+    DebounceEventResult, Debouncer,
+};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    library::{index_file, relative_path, LibraryFilter, LibraryRoot},
+    library_index::LibraryChange,
+    library_service::LibraryService,
+};
+
+// Long enough to coalesce the burst of events from copying a whole folder.
+const DEBOUNCE: Duration = Duration::from_millis(750);
+
+pub type LibraryWatcher = Debouncer<RecommendedWatcher>;
+
+pub fn watch_library(library: impl LibraryService, root: LibraryRoot) -> Result<LibraryWatcher, String> {
+    let directory = root.path.clone();
+    let mut debouncer = new_debouncer(DEBOUNCE, move |result: DebounceEventResult| match result {
+        Ok(events) => {
+            let mut paths: Vec<PathBuf> = events.into_iter().map(|event| event.path).collect();
+            paths.sort();
+            paths.dedup();
+            handle_changes(&library, &root, &paths);
+        }
+        Err(e) => eprintln!("Library watcher error: {}", e),
+    })
+    .map_err(|e| format!("Failed to create library watcher: {}", e))?;
+
+    debouncer
+        .watcher()
+        .watch(Path::new(&directory), RecursiveMode::Recursive)
+        .map_err(|e| format!("Failed to watch {}: {}", directory, e))?;
+    println!("Watching library directory: {}", directory);
+    Ok(debouncer)
+}
+
+fn handle_changes(library: &impl LibraryService, root: &LibraryRoot, paths: &[PathBuf]) {
+    if library.library_index().lock().unwrap().root(&root.name) != Some(root) {
+        return;
+    }
+
+    // A new or moved folder can hold any number of files, so fall back to an
+    // incremental rescan rather than walking it here.
+    let result = if paths.iter().any(|path| path.is_dir()) {
+        library.rescan(root)
+    } else {
+        update_entries(library, root, paths)
+    };
+
+    match result {
+        Ok(change) if !change.is_empty() => {
+            println!(
+                "Library '{}' changed: {} added, {} updated, {} removed",
+                root.name,
+                change.added.len(),
+                change.updated.len(),
+                change.removed.len()
+            );
+            library.emit_event("library-changed", change);
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("Failed to update library: {}", e),
+    }
+}
+
+fn update_entries(library: &impl LibraryService, root: &LibraryRoot, paths: &[PathBuf]) -> Result<LibraryChange, String> {
+    let filter = LibraryFilter::new(&library.scan_options())?;
+
+    // Files are probed without the index lock; it is only taken again to merge.
+    let before = library.library_index().lock().unwrap().root_tracks(&root.name);
+    let mut tracks = before.clone();
+    for path in paths {
+        let Some(relative) = relative_path(Path::new(&root.path), path) else {
+            continue;
+        };
+
+        if path.is_file() && filter.accepts(&relative) {
+            let position = tracks.iter().position(|entry| entry.song.path == relative);
+            let cached = position.map(|i| &tracks[i]);
+            if let Some(entry) = index_file(path, &root.name, relative, cached) {
+                match position {
+                    Some(i) => tracks[i] = entry,
+                    None => tracks.push(entry),
+                }
+            }
+        } else {
+            // Removed, renamed away or no longer matching the scan options.
+            let prefix = format!("{}/", relative);
+            tracks.retain(|entry| entry.song.path != relative && !entry.song.path.starts_with(&prefix));
+        }
+    }
+
+    let change = LibraryChange::between(&before, &tracks);
+    if change.is_empty() {
+        return Ok(change);
+    }
+
+    let mut index = library.library_index().lock().unwrap();
+    if index.root(&root.name) != Some(root) {
+        // Removed or replaced while the files were being probed.
+        return Ok(LibraryChange::default());
+    }
+    index.replace_root_tracks(&root.name, tracks);
+    library.update_song_cache(index.songs());
+    drop(index);
+    library.save_index()?;
+    library.request_analysis();
+    Ok(change)
+}
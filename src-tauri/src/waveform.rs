@@ -0,0 +1,385 @@
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Read,
+    path::Path,
+    time::UNIX_EPOCH,
+};
+use tauri::AppHandle;
+
+use crate::{audio::open_source, emit_event, settings::app_data_file};
+
+const WAVEFORM_DIR: &str = "waveforms";
+const INDEX_FILE: &str = "index.json";
+// Frames per peak at the finest level; each further level halves the resolution.
+const BASE_FRAMES_PER_PEAK: usize = 256;
+const MIN_LEVEL_PEAKS: usize = 256;
+const PROGRESS_STEP: f32 = 0.05;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PeakLevel {
+    pub frames_per_peak: usize,
+    pub min: Vec<f32>,
+    pub max: Vec<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Waveform {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub frames: u64,
+    pub levels: Vec<PeakLevel>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WaveformData {
+    pub width: usize,
+    pub duration: f64,
+    pub min: Vec<f32>,
+    pub max: Vec<f32>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WaveformProgress {
+    pub path: String,
+    pub progress: f32,
+}
+
+impl Waveform {
+    // Picks the coarsest level that still has at least `width` peaks and
+    // folds it down to exactly `width` columns.
+    pub fn render(&self, width: usize) -> WaveformData {
+        let level = self
+            .levels
+            .iter()
+            .rev()
+            .find(|level| level.min.len() >= width)
+            .or(self.levels.first());
+        let duration = self.frames as f64 / self.sample_rate.max(1) as f64;
+
+        let Some(level) = level.filter(|level| !level.min.is_empty()) else {
+            return WaveformData {
+                width,
+                duration,
+                min: vec![0.0; width],
+                max: vec![0.0; width],
+            };
+        };
+
+        let peaks = level.min.len();
+        let mut min = Vec::with_capacity(width);
+        let mut max = Vec::with_capacity(width);
+        for column in 0..width {
+            let start = column * peaks / width;
+            let end = ((column + 1) * peaks / width).max(start + 1).min(peaks);
+            min.push(level.min[start..end].iter().copied().fold(f32::MAX, f32::min));
+            max.push(level.max[start..end].iter().copied().fold(f32::MIN, f32::max));
+        }
+
+        WaveformData { width, duration, min, max }
+    }
+}
+
+// Size and modification time of a file when its waveform was cached.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct IndexedFile {
+    stamp: FileStamp,
+    hash: String,
+}
+
+// Maps file paths to the content hash their waveform is stored under, so an unchanged
+// file is found without reading it again.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+struct WaveformIndex {
+    files: HashMap<String, IndexedFile>,
+}
+
+pub fn load_waveform(app: &AppHandle, path: &Path) -> Result<Waveform, String> {
+    let cache_dir = app_data_file(app, WAVEFORM_DIR)?;
+    cached_waveform(&cache_dir, path, || compute_waveform(app, path))
+}
+
+// Waveforms are stored by content hash, so a file that was moved or copied still
+// finds its cached peaks; the file is only hashed when its stamp has changed.
+fn cached_waveform(
+    cache_dir: &Path,
+    path: &Path,
+    compute: impl FnOnce() -> Result<Waveform, String>,
+) -> Result<Waveform, String> {
+    let stamp = file_stamp(path)?;
+    let key = path.to_string_lossy().into_owned();
+    let mut index = load_index(cache_dir);
+
+    if let Some(indexed) = index.files.get(&key).filter(|indexed| indexed.stamp == stamp) {
+        if let Some(waveform) = read_waveform(&cache_dir.join(format!("{}.json", indexed.hash))) {
+            return Ok(waveform);
+        }
+    }
+
+    let hash = hash_file(path)?;
+    let cache_path = cache_dir.join(format!("{}.json", hash));
+    let waveform = match read_waveform(&cache_path) {
+        Some(waveform) => waveform,
+        None => {
+            let waveform = compute()?;
+            if let Err(e) = save_waveform(&cache_path, &waveform) {
+                eprintln!("{}", e);
+            }
+            waveform
+        }
+    };
+
+    index.files.insert(key, IndexedFile { stamp, hash });
+    if let Err(e) = save_index(cache_dir, &index) {
+        eprintln!("{}", e);
+    }
+    Ok(waveform)
+}
+
+fn read_waveform(cache_path: &Path) -> Option<Waveform> {
+    let contents = fs::read_to_string(cache_path).ok()?;
+    match serde_json::from_str::<Waveform>(&contents) {
+        Ok(waveform) => Some(waveform),
+        Err(e) => {
+            eprintln!("Ignoring unreadable waveform cache {}: {}", cache_path.display(), e);
+            None
+        }
+    }
+}
+
+fn compute_waveform(app: &AppHandle, path: &Path) -> Result<Waveform, String> {
+    let (source, duration) = open_source(path)?;
+    let channels = source.channels().max(1);
+    let sample_rate = source.sample_rate();
+    let expected_frames = duration.map(|duration| (duration.as_secs_f64() * sample_rate as f64) as u64);
+    let path_label = path.to_string_lossy().into_owned();
+    println!("Generating waveform for {}", path_label);
+
+    let mut min = Vec::new();
+    let mut max = Vec::new();
+    let mut peak_min = f32::MAX;
+    let mut peak_max = f32::MIN;
+    let mut frames: u64 = 0;
+    let mut frame_in_peak = 0;
+    let mut channel = 0;
+    let mut last_progress = 0.0;
+
+    for sample in source {
+        let value = sample as f32 / i16::MAX as f32;
+        peak_min = peak_min.min(value);
+        peak_max = peak_max.max(value);
+
+        channel += 1;
+        if channel < channels {
+            continue;
+        }
+        channel = 0;
+        frames += 1;
+        frame_in_peak += 1;
+
+        if frame_in_peak == BASE_FRAMES_PER_PEAK {
+            min.push(peak_min);
+            max.push(peak_max);
+            peak_min = f32::MAX;
+            peak_max = f32::MIN;
+            frame_in_peak = 0;
+
+            if let Some(expected) = expected_frames.filter(|&expected| expected > 0) {
+                let progress = (frames as f32 / expected as f32).min(1.0);
+                if progress - last_progress >= PROGRESS_STEP {
+                    last_progress = progress;
+                    emit_event(app, "waveform-progress", WaveformProgress { path: path_label.clone(), progress });
+                }
+            }
+        }
+    }
+    if frame_in_peak > 0 {
+        min.push(peak_min);
+        max.push(peak_max);
+    }
+    emit_event(app, "waveform-progress", WaveformProgress { path: path_label, progress: 1.0 });
+
+    Ok(Waveform {
+        sample_rate,
+        channels,
+        frames,
+        levels: build_levels(min, max),
+    })
+}
+
+fn build_levels(min: Vec<f32>, max: Vec<f32>) -> Vec<PeakLevel> {
+    let mut levels = vec![PeakLevel {
+        frames_per_peak: BASE_FRAMES_PER_PEAK,
+        min,
+        max,
+    }];
+
+    while let Some(previous) = levels.last().filter(|level| level.min.len() > MIN_LEVEL_PEAKS) {
+        let level = PeakLevel {
+            frames_per_peak: previous.frames_per_peak * 2,
+            min: previous.min.chunks(2).map(|pair| pair.iter().copied().fold(f32::MAX, f32::min)).collect(),
+            max: previous.max.chunks(2).map(|pair| pair.iter().copied().fold(f32::MIN, f32::max)).collect(),
+        };
+        levels.push(level);
+    }
+    levels
+}
+
+fn save_waveform(cache_path: &Path, waveform: &Waveform) -> Result<(), String> {
+    if let Some(parent) = cache_path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create waveform cache directory: {}", e))?;
+    }
+
+    let contents = serde_json::to_string(waveform).map_err(|e| format!("Failed to serialize waveform: {}", e))?;
+    fs::write(cache_path, contents).map_err(|e| format!("Failed to save waveform cache: {}", e))
+}
+
+fn load_index(cache_dir: &Path) -> WaveformIndex {
+    let Ok(contents) = fs::read_to_string(cache_dir.join(INDEX_FILE)) else {
+        return WaveformIndex::default();
+    };
+    serde_json::from_str(&contents).unwrap_or_else(|e| {
+        eprintln!("Ignoring unreadable waveform index in {}: {}", cache_dir.display(), e);
+        WaveformIndex::default()
+    })
+}
+
+fn save_index(cache_dir: &Path, index: &WaveformIndex) -> Result<(), String> {
+    fs::create_dir_all(cache_dir).map_err(|e| format!("Failed to create waveform cache directory: {}", e))?;
+    let contents = serde_json::to_string(index).map_err(|e| format!("Failed to serialize waveform index: {}", e))?;
+    fs::write(cache_dir.join(INDEX_FILE), contents).map_err(|e| format!("Failed to save waveform index: {}", e))
+}
+
+fn file_stamp(path: &Path) -> Result<FileStamp, String> {
+    let metadata = fs::metadata(path).map_err(|e| format!("Error opening file: {}: {}", path.display(), e))?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    Ok(FileStamp {
+        size: metadata.len(),
+        modified_secs: modified.as_secs(),
+        modified_nanos: modified.subsec_nanos(),
+    })
+}
+
+fn hash_file(path: &Path) -> Result<String, String> {
+    let mut file = File::open(path).map_err(|e| format!("Error opening file: {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .map_err(|e| format!("Error reading file: {}: {}", path.display(), e))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{path::PathBuf, time::Duration};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("waveform-test-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn waveform(frames: u64) -> Waveform {
+        Waveform {
+            sample_rate: 44100,
+            channels: 2,
+            frames,
+            levels: build_levels(vec![-0.5; 4], vec![0.5; 4]),
+        }
+    }
+
+    fn not_computed() -> Result<Waveform, String> {
+        panic!("the cached waveform should have been used")
+    }
+
+    #[test]
+    fn unchanged_files_are_not_hashed_again() {
+        let dir = temp_dir("stamp");
+        let file = dir.join("song.wav");
+        fs::write(&file, "first").unwrap();
+        let modified = fs::metadata(&file).unwrap().modified().unwrap();
+
+        assert_eq!(cached_waveform(&dir, &file, || Ok(waveform(1))).unwrap().frames, 1);
+        assert_eq!(cached_waveform(&dir, &file, not_computed).unwrap().frames, 1);
+
+        // Same size and time: the stamp matches, so the new contents are never read.
+        fs::write(&file, "other").unwrap();
+        File::options().write(true).open(&file).unwrap().set_modified(modified).unwrap();
+        assert_eq!(cached_waveform(&dir, &file, not_computed).unwrap().frames, 1);
+
+        File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(modified + Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(cached_waveform(&dir, &file, || Ok(waveform(2))).unwrap().frames, 2);
+        assert_eq!(cached_waveform(&dir, &file, not_computed).unwrap().frames, 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn copies_share_the_cached_waveform() {
+        let dir = temp_dir("copy");
+        let file = dir.join("song.wav");
+        let copy = dir.join("copy.wav");
+        fs::write(&file, "contents").unwrap();
+        fs::copy(&file, &copy).unwrap();
+
+        assert_eq!(cached_waveform(&dir, &file, || Ok(waveform(3))).unwrap().frames, 3);
+        assert_eq!(cached_waveform(&dir, &copy, not_computed).unwrap().frames, 3);
+        assert_eq!(load_index(&dir).files.len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_files_are_an_error() {
+        let dir = temp_dir("missing");
+        assert!(cached_waveform(&dir, &dir.join("gone.wav"), not_computed).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn renders_from_the_coarsest_sufficient_level() {
+        let peaks = MIN_LEVEL_PEAKS * 4;
+        let min = (0..peaks).map(|i| -(i as f32) / peaks as f32).collect();
+        let max = (0..peaks).map(|i| i as f32 / peaks as f32).collect();
+        let waveform = Waveform {
+            sample_rate: 1000,
+            channels: 1,
+            frames: (peaks * BASE_FRAMES_PER_PEAK) as u64,
+            levels: build_levels(min, max),
+        };
+        assert_eq!(waveform.levels.len(), 3);
+
+        let data = waveform.render(4);
+        assert_eq!(data.width, 4);
+        assert!((data.duration - 262.144).abs() < 1e-9);
+        assert_eq!(data.max.last(), Some(&((peaks - 1) as f32 / peaks as f32)));
+        assert_eq!(data.min[0], -((peaks / 4 - 1) as f32) / peaks as f32);
+    }
+}
@@ -5,21 +5,28 @@ import styles from './Library.module.css';
 
 interface Song {
     title: string;
+    path: string;
+    root: string;
+}
+
+interface LibraryRoot {
+    name: string;
+    path: string;
 }
 
 const Library: React.FC = () => {
     const [songs, setSongs] = useState<Song[]>([]);
-    const [directory, setDirectory] = useState<string | null>(null);
+    const [roots, setRoots] = useState<LibraryRoot[]>([]);
 
     useEffect(() => {
         const fetchCachedData = async () => {
             try {
-                const cachedDir = await invoke<string | null>('get_directory_path');
+                const cachedRoots = await invoke<LibraryRoot[]>('list_library_roots');
                 const cachedSongs = await invoke<Song[]>('get_cached_songs');
 
-                if (cachedDir) {
-                    setDirectory(cachedDir);
-                    console.log(`Cached directory loaded: ${cachedDir}`);
+                if (cachedRoots.length > 0) {
+                    setRoots(cachedRoots);
+                    console.log(`Library folders loaded:`, cachedRoots);
                 }
 
                 if (cachedSongs.length > 0) {
@@ -43,11 +50,12 @@ const Library: React.FC = () => {
 
             if (!selectedDir) return;
 
-            setDirectory(selectedDir as string);
-
+            // Adds the folder as another library root; folders already in the library are rescanned.
             await invoke('set_directory', { path: selectedDir });
-            console.log('Directory set:', selectedDir);
+            console.log('Directory added:', selectedDir);
 
+            const fetchedRoots = await invoke<LibraryRoot[]>('list_library_roots');
+            setRoots(fetchedRoots);
             const fetchedSongs = await invoke<Song[]>('get_cached_songs');
             setSongs(fetchedSongs);
         } catch (error) {
@@ -57,7 +65,7 @@ const Library: React.FC = () => {
 
     const handlePlaySong = async (song: Song) => {
         try {
-            await invoke('play_song', { title: song.title });
+            await invoke('play_song', { root: song.root, path: song.path });
             console.log(`Now playing: ${song.title}`);
         } catch (error) {
             console.error('Error playing song:', error);
@@ -68,9 +76,9 @@ const Library: React.FC = () => {
         <div className={styles.container}>
             <h1>My Library</h1>
             <button onClick={selectDirectory} className={styles.selectButton}>
-                Select Directory
+                Add Directory
             </button>
-            {directory && <p>Selected Directory: {directory}</p>}
+            {roots.length > 0 && <p>Library Folders: {roots.map((root) => root.path).join(', ')}</p>}
             <div className={styles.listBg}>
                 <div className={styles.songList}>
                     <ul>